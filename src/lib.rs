@@ -0,0 +1,21 @@
+//! Raytracing: The Next Week implementation in Rust.
+//! Original book by Peter Shirley [here](https://raytracing.github.io/books/RayTracingTheNextWeek.html)
+//!
+//! ```no_run
+//! use rttnw::scene::SceneDescription;
+//! use rttnw::Renderer;
+//! use std::path::Path;
+//!
+//! let scene = SceneDescription::load("scenes/07_cornell_box.ron")
+//!     .and_then(|description| description.build(Path::new("scenes")))
+//!     .unwrap();
+//! let image = Renderer::new(300, 300, 10).render(&scene);
+//! image.save("cornell_box.png").unwrap();
+//! ```
+
+pub mod math;
+pub mod obj;
+pub mod renderer;
+pub mod scene;
+
+pub use renderer::{Framebuffer, Renderer};
@@ -1,270 +1,258 @@
-//! Raytracing: The Next Week implementation in Rust.
-//! Original book by Peter Shirley [here](https://raytracing.github.io/books/RayTracingTheNextWeek.html)
+use rttnw::scene::{SceneDescription, SceneError};
+use rttnw::Renderer;
 
-use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
-use rand::Rng;
-use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
 
-mod math;
-mod scenes;
+const USAGE: &str = "Usage: rttnw [options] <scene file>
 
-use crate::math::Position;
-#[allow(unused_imports)]
-use math::{BvhTree, Camera, CameraDescriptor, Color, Hittable, List, Ray, Vec3f};
-use std::error::Error;
+Options:
+    -w, --width <pixels>        Image width. Defaults to the scene's width
+        --height <pixels>       Image height. Defaults to the scene's aspect ratio
+    -s, --samples <count>       Samples per pixel. Defaults to the scene's samples
+    -d, --depth <bounces>       Maximum number of bounces of a ray [default: 50]
+        --roulette <bounces>    Bounces before Russian roulette can end a ray [default: 5]
+    -o, --output <path>         Output image [default: image.png]
+    -j, --threads <count>       Number of threads. Defaults to the number of CPUs
+        --shutter <open:close>  Time interval in which the shutter is open. Defaults to the scene's
+        --seed <number>         Seed of the random number generator [default: 0]
+        --spectral              Trace wavelengths instead of RGB, for dispersive glass
+        --help                  Print this message";
 
-#[repr(C)]
-#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
-struct Rgba {
-    r: u8,
-    g: u8,
-    b: u8,
-    a: u8,
+/// Command line options. `None` means the scene file decides.
+struct Options {
+    scene: PathBuf,
+    width: Option<u32>,
+    height: Option<u32>,
+    samples: Option<usize>,
+    depth: i32,
+    roulette: i32,
+    output: PathBuf,
+    threads: Option<usize>,
+    shutter: Option<(f64, f64)>,
+    seed: u64,
+    spectral: bool,
 }
 
-/// The resulting color of a ray pointing to a direction
-fn color<T: Hittable>(ray: Ray, background: Vec3f<Color>, world: &T, depth: i32) -> Vec3f<Color> {
-    // If the ray bounce limit is reached, no more light is gathered.
-    if depth <= 0 {
-        return Vec3f::repeat(0.);
-    }
-    // If the ray hits something
-    // `t_min` is not 0.0 to avoid the shadow acne problem
-    if let Some(record) = world.hit(ray, 0.001, f64::MAX) {
-        let emitted = record.material.emitted(record.u, record.v, record.p);
+enum Error {
+    /// Wrong command line arguments.
+    Usage(String),
+    Help,
+    Scene(SceneError),
+    ThreadPool(rayon::ThreadPoolBuildError),
+    Image(PathBuf, image::ImageError),
+}
 
-        // New random point at a random direction. Where the ray is reflected.
-        if let Some((attenuation, scattered)) = record.material.scatter(ray, record) {
-            emitted + attenuation * color(scattered, background, world, depth - 1)
-        } else {
-            emitted
+impl Display for Error {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            Error::Usage(message) => write!(f, "{}\n\n{}", message, USAGE),
+            Error::Help => f.write_str(USAGE),
+            Error::Scene(error) => write!(f, "Could not load the scene: {}", error),
+            Error::ThreadPool(error) => write!(f, "Could not create the thread pool: {}", error),
+            Error::Image(path, error) => {
+                write!(f, "Could not save the image {}: {}", path.display(), error)
+            }
         }
-    } else {
-        background
     }
 }
 
-#[derive(Default)]
-struct Scene {
-    background: Vec3f<Color>,
-    world: List,
-    lookfrom: Vec3f<Position>,
-    lookat: Vec3f<Position>,
-    vertical_fov: f64,
-    aperture: f64,
+fn value<T: FromStr>(flag: &str, value: Option<String>) -> Result<T, Error> {
+    let value = value.ok_or_else(|| Error::Usage(format!("Missing value for {}", flag)))?;
+    value
+        .parse()
+        .map_err(|_| Error::Usage(format!("Invalid value for {}: {:?}", flag, value)))
 }
 
-/// Saves the scene to a .png image of size `nx*ny`
-fn render(mut width: u32, mut aspect_ratio: f64, mut samples: usize, scene: usize) -> Option<()> {
-    let Scene {
-        background,
-        world,
-        lookfrom,
-        lookat,
-        vertical_fov,
-        aperture,
-    } = match scene {
-        1 => {
-            println!("Running scene random_scene");
-            Scene {
-                background: Vec3f::new(0.7, 0.8, 1.),
-                world: scenes::random_scene(),
-                lookfrom: Vec3f::new(13.0, 2.0, 3.0),
-                lookat: Vec3f::repeat(0.0),
-                vertical_fov: 20.0,
-                aperture: 0.1,
-            }
-        }
-        2 => {
-            println!("Running scene two_spheres");
-            Scene {
-                background: Vec3f::new(0.7, 0.8, 1.),
-                world: scenes::two_spheres(),
-                lookfrom: Vec3f::new(13.0, 2.0, 3.0),
-                lookat: Vec3f::repeat(0.0),
-                vertical_fov: 20.0,
-                ..Default::default()
+impl Options {
+    fn parse<I: Iterator<Item = String>>(mut args: I) -> Result<Self, Error> {
+        let mut scene = None;
+        let mut options = Options {
+            scene: PathBuf::new(),
+            width: None,
+            height: None,
+            samples: None,
+            depth: 50,
+            roulette: 5,
+            output: PathBuf::from("image.png"),
+            threads: None,
+            shutter: None,
+            seed: 0,
+            spectral: false,
+        };
+        while let Some(arg) = args.next() {
+            match arg.as_str() {
+                "-w" | "--width" => options.width = Some(value(&arg, args.next())?),
+                "--height" => options.height = Some(value(&arg, args.next())?),
+                "-s" | "--samples" => options.samples = Some(value(&arg, args.next())?),
+                "-d" | "--depth" => options.depth = value(&arg, args.next())?,
+                "--roulette" => options.roulette = value(&arg, args.next())?,
+                "-o" | "--output" => options.output = value(&arg, args.next())?,
+                "-j" | "--threads" => options.threads = Some(value(&arg, args.next())?),
+                "--shutter" => {
+                    let shutter: String = value(&arg, args.next())?;
+                    let invalid =
+                        || Error::Usage(format!("Invalid shutter interval {:?}", shutter));
+                    let (open, close) = shutter.split_once(':').ok_or_else(invalid)?;
+                    let open = open.parse().map_err(|_| invalid())?;
+                    let close = close.parse().map_err(|_| invalid())?;
+                    options.shutter = Some((open, close));
+                }
+                "--seed" => options.seed = value(&arg, args.next())?,
+                "--spectral" => options.spectral = true,
+                "--help" => return Err(Error::Help),
+                _ if arg.starts_with('-') => {
+                    return Err(Error::Usage(format!("Unknown option {}", arg)))
+                }
+                _ if scene.is_none() => scene = Some(PathBuf::from(arg)),
+                _ => return Err(Error::Usage(format!("Unexpected argument {}", arg))),
             }
         }
-        3 => {
-            println!("Running scene two_perlin_spheres");
-            Scene {
-                background: Vec3f::new(0.7, 0.8, 1.),
-                world: scenes::two_perlin_spheres(),
-                lookfrom: Vec3f::new(13.0, 2.0, 3.0),
-                lookat: Vec3f::repeat(0.0),
-                vertical_fov: 20.0,
-                ..Default::default()
+        options.scene = scene.ok_or_else(|| {
+            let mut message = String::from("Missing scene file. Example scenes:");
+            let mut examples: Vec<_> = std::fs::read_dir("scenes")
+                .into_iter()
+                .flatten()
+                .flatten()
+                .map(|entry| entry.path())
+                .filter(|path| path.extension().is_some_and(|x| x == "ron"))
+                .collect();
+            examples.sort();
+            for example in examples {
+                message += &format!("\n\t- {}", example.display());
             }
+            Error::Usage(message)
+        })?;
+        if options.width == Some(0) || options.height == Some(0) || options.samples == Some(0) {
+            return Err(Error::Usage(String::from(
+                "The width, height and samples must be greater than 0",
+            )));
         }
-        4 => {
-            println!("Running scene earth");
-            Scene {
-                background: Vec3f::new(0.7, 0.8, 1.),
-                world: scenes::earth(),
-                lookfrom: Vec3f::new(13.0, 2.0, 3.0),
-                lookat: Vec3f::repeat(0.0),
-                vertical_fov: 20.0,
-                ..Default::default()
-            }
-        }
-        5 => {
-            println!("Running scene simple_light");
-            samples = 400;
-            Scene {
-                background: Vec3f::new(0.0, 0.0, 0.0),
-                world: scenes::simple_light(),
-                lookfrom: Vec3f::new(26.0, 3.0, 6.0),
-                lookat: Vec3f::new(0., 2., 0.),
-                vertical_fov: 20.,
-                ..Default::default()
-            }
-        }
-        6 => {
-            println!("Running scene empty_cornell_box");
-            samples = 200;
-            aspect_ratio = 1.0;
-            width = 600;
-            Scene {
-                background: Vec3f::new(0.0, 0.0, 0.0),
-                world: scenes::empty_cornell_box(),
-                lookfrom: Vec3f::new(278.0, 278.0, -800.0),
-                lookat: Vec3f::new(278., 278., 0.),
-                vertical_fov: 40.,
-                ..Default::default()
-            }
-        }
-        7 => {
-            println!("Running scene cornell_box");
-            samples = 200;
-            aspect_ratio = 1.0;
-            width = 600;
-            Scene {
-                background: Vec3f::new(0.0, 0.0, 0.0),
-                world: scenes::cornell_box(),
-                lookfrom: Vec3f::new(278.0, 278.0, -800.0),
-                lookat: Vec3f::new(278., 278., 0.),
-                vertical_fov: 40.,
-                ..Default::default()
-            }
-        }
-        8 => {
-            println!("Running scene smoke_cornell_box");
-            samples = 200;
-            aspect_ratio = 1.0;
-            width = 600;
-            Scene {
-                background: Vec3f::new(0.0, 0.0, 0.0),
-                world: scenes::smoke_cornell_box(),
-                lookfrom: Vec3f::new(278.0, 278.0, -800.0),
-                lookat: Vec3f::new(278., 278., 0.),
-                vertical_fov: 40.,
-                ..Default::default()
-            }
-        }
-        9 => {
-            println!("Running scene final_scene");
-            samples = 10000;
-            aspect_ratio = 1.0;
-            width = 800;
-            Scene {
-                background: Vec3f::new(0.0, 0.0, 0.0),
-                world: scenes::final_scene(),
-                lookfrom: Vec3f::new(478.0, 278.0, -600.0),
-                lookat: Vec3f::new(278., 278., 0.),
-                vertical_fov: 40.,
-                ..Default::default()
-            }
-        }
-        _ => {
-            eprintln!("There is no scene {}", scene);
-            return None;
-        },
-    };
-    let height = (width as f64 / aspect_ratio) as u32;
-    let view_up = Vec3f::new(0.0, 1.0, 0.0);
-    let focus_distance = 10.0;
-    let camera = Camera::new(&CameraDescriptor {
-        lookfrom,
-        lookat,
-        view_up,
-        vertical_fov,
-        aspect_ratio,
-        aperture,
-        focus_distance,
-        open_time: 0.0,
-        close_time: 1.0,
-    });
-
-    let progress = ProgressBar::new(height as u64)
-        .with_style(ProgressStyle::default_spinner().template("{pos}/{len} {spinner:.dim.bold}"));
-    // For each pixel
-    let image: Vec<Rgba> = (0..height)
-        .into_par_iter()
-        .rev()
-        .progress_with(progress)
-        .flat_map(|j| {
-            (0..width)
-                .into_par_iter()
-                .map(|i| {
-                    // Calculate the color `ns` times and average the result
-                    let col = (0..samples).fold(Vec3f::<Color>::repeat(0.0), |acc, _| {
-                        let mut rng = rand::thread_rng();
-                        let u = (i as f64 + rng.gen::<f64>()) / width as f64;
-                        let v = (j as f64 + rng.gen::<f64>()) / height as f64;
-                        let ray = camera.ray(u, v);
-                        acc + color(ray, background, &world, 50)
-                    }) / samples as f64;
-                    // Gamma correction
-                    let col = col.map(|x| x.sqrt().clamp(0.0, 0.999) * 256.);
-                    Rgba {
-                        r: col.x() as u8,
-                        g: col.y() as u8,
-                        b: col.z() as u8,
-                        a: 255,
-                    }
-                })
-                .collect::<Vec<_>>()
-        })
-        .collect();
-    let buffer: &[u8] = bytemuck::cast_slice(&image);
-    image::save_buffer("image.png", buffer, width, height, image::ColorType::Rgba8).unwrap();
-    Some(())
+        Ok(options)
+    }
 }
 
+fn run(options: Options) -> Result<(), Error> {
+    if let Some(threads) = options.threads {
+        rayon::ThreadPoolBuilder::new()
+            .num_threads(threads)
+            .build_global()
+            .map_err(Error::ThreadPool)?;
+    }
 
-fn main() -> Result<(), DummyError> {
-    let args: Vec<String> = std::env::args().collect();
-    if args.len() != 2 {
-        eprintln!("Usage: {} <scene>", args[0]);
-        eprintln!("Possible scenes:");
-        eprintln!("\t- 1: random_scene");
-        eprintln!("\t- 2: two_spheres");
-        eprintln!("\t- 3: two_perlin_spheres");
-        eprintln!("\t- 4: earth");
-        eprintln!("\t- 5: simple_light");
-        eprintln!("\t- 6: empty_cornell_box");
-        eprintln!("\t- 7: cornell_box");
-        eprintln!("\t- 8: smoke_cornell_box");
-        eprintln!("\t- 9: final_scene");
-        return Err(ERROR);
+    let path = options.scene.as_path();
+    println!("Running scene {}", path.display());
+    let description = SceneDescription::load(path).map_err(Error::Scene)?;
+    let mut scene = description
+        .build(path.parent().unwrap_or_else(|| Path::new("")))
+        .map_err(Error::Scene)?;
+    if let Some((open, close)) = options.shutter {
+        scene.camera.open_time = open;
+        scene.camera.close_time = close;
     }
-    let scene = args.get(1).unwrap_or(&String::from("1")).parse().map_err(|_| ERROR)?;
-    println!("Scene number: {}", scene);
+
+    let image = description.image;
+    let (width, height) = match (options.width, options.height) {
+        (Some(width), Some(height)) => (width, height),
+        (Some(width), None) => (width, (width as f64 / image.aspect_ratio) as u32),
+        (None, Some(height)) => ((height as f64 * image.aspect_ratio) as u32, height),
+        (None, None) => (
+            image.width,
+            (image.width as f64 / image.aspect_ratio) as u32,
+        ),
+    };
+    let mut renderer = Renderer::new(
+        width.max(1),
+        height.max(1),
+        options.samples.unwrap_or(image.samples),
+    );
+    renderer.max_depth = options.depth;
+    renderer.roulette_depth = options.roulette;
+    renderer.seed = options.seed;
+    renderer.spectral = options.spectral;
+    renderer.progress = true;
+    println!(
+        "{}x{}, {} samples per pixel",
+        renderer.width, renderer.height, renderer.samples
+    );
+
     let instant = std::time::Instant::now();
-    render(400, 16.0 / 9.0, 100, scene).ok_or(ERROR)?;
+    let framebuffer = renderer.render(&scene);
     println!("{:?}", instant.elapsed());
-    Ok(())
+    framebuffer
+        .save(&options.output)
+        .map_err(|error| Error::Image(options.output.clone(), error))
 }
 
-use std::fmt::{Display, Formatter, self};
-#[derive(Debug)]
-struct DummyError;
-impl Display for DummyError {
-    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
-        f.write_str("There was an error")
+fn main() {
+    if let Err(error) = Options::parse(std::env::args().skip(1)).and_then(run) {
+        eprintln!("{}", error);
+        let code = if let Error::Help = error { 0 } else { 1 };
+        std::process::exit(code);
     }
 }
-impl Error for DummyError {}
 
-const ERROR: DummyError = DummyError{};
\ No newline at end of file
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse(args: &str) -> Result<Options, Error> {
+        Options::parse(args.split_whitespace().map(String::from))
+    }
+
+    /// The message of a usage error.
+    fn usage(args: &str) -> String {
+        match parse(args) {
+            Err(Error::Usage(message)) => message,
+            Err(error) => panic!("{}", error),
+            Ok(_) => panic!("{:?} parsed", args),
+        }
+    }
+
+    #[test]
+    fn flags_set_their_options() {
+        let args = "-w 320 --height 240 -s 16 -d 8 --roulette 3 -o out.png -j 2 \
+                    --shutter 0.25:0.5 --seed 7 --spectral scene.ron";
+        let options = parse(args).ok().unwrap();
+        assert_eq!(options.scene, PathBuf::from("scene.ron"));
+        assert_eq!((options.width, options.height), (Some(320), Some(240)));
+        assert_eq!(options.samples, Some(16));
+        assert_eq!((options.depth, options.roulette), (8, 3));
+        assert_eq!(options.output, PathBuf::from("out.png"));
+        assert_eq!(options.threads, Some(2));
+        assert_eq!(options.shutter, Some((0.25, 0.5)));
+        assert_eq!(options.seed, 7);
+        assert!(options.spectral);
+
+        let defaults = parse("scene.ron").ok().unwrap();
+        assert_eq!((defaults.width, defaults.height), (None, None));
+        assert_eq!((defaults.samples, defaults.shutter), (None, None));
+        assert_eq!((defaults.depth, defaults.roulette), (50, 5));
+        assert_eq!(defaults.output, PathBuf::from("image.png"));
+        assert!(matches!(parse("--help"), Err(Error::Help)));
+    }
+
+    #[test]
+    fn flags_need_a_value() {
+        assert_eq!(usage("scene.ron --width"), "Missing value for --width");
+        assert_eq!(usage("scene.ron -s many"), "Invalid value for -s: \"many\"");
+        let zero = "The width, height and samples must be greater than 0";
+        assert_eq!(usage("scene.ron -w 0"), zero);
+    }
+
+    #[test]
+    fn shutter_intervals_are_two_times() {
+        for shutter in ["0.5", "0.5:", ":1", "a:1", "0:1:2"] {
+            let message = usage(&format!("scene.ron --shutter {}", shutter));
+            assert_eq!(message, format!("Invalid shutter interval {:?}", shutter));
+        }
+    }
+
+    #[test]
+    fn unknown_flags_are_rejected() {
+        assert_eq!(usage("scene.ron --fast"), "Unknown option --fast");
+        let extra = "Unexpected argument other.ron";
+        assert_eq!(usage("scene.ron other.ron"), extra);
+    }
+}
@@ -1,13 +1,26 @@
 use super::{Position, Ray, Vec3f};
 
 /// Axis-aligned bounding box
-#[derive(Copy, Clone, Default)]
+#[derive(Copy, Clone, Default, PartialEq)]
 pub struct Bound {
     pub min: Vec3f<Position>,
     pub max: Vec3f<Position>,
 }
 
 impl Bound {
+    /// How much flat shapes are padded on each side, so their boxes have a volume even
+    /// when they are axis-aligned.
+    pub const FLAT_PADDING: f64 = 0.0001;
+
+    /// The box grown by `padding` on every side.
+    pub fn padded(&self, padding: f64) -> Self {
+        let padding = Vec3f::repeat(padding);
+        Self {
+            min: self.min - padding,
+            max: self.max + padding,
+        }
+    }
+
     /// Andrew Kensler's version of the hit detection.
     /// http://psgraphics.blogspot.com/2016/02/new-simple-ray-box-test-from-andrew.html
     pub fn hit(&self, ray: Ray, mut min: f64, mut max: f64) -> bool {
@@ -31,6 +44,22 @@ impl Bound {
         true
     }
 
+    /// The part of `min..max` in which the ray is inside the box.
+    pub fn clip(&self, ray: Ray, mut min: f64, mut max: f64) -> Option<(f64, f64)> {
+        for dimension in 0..3 {
+            let inverse_direction = 1.0 / ray.direction().at(dimension);
+            let t0 = (self.min.at(dimension) - ray.origin().at(dimension)) * inverse_direction;
+            let t1 = (self.max.at(dimension) - ray.origin().at(dimension)) * inverse_direction;
+            // NaN when the ray lies in a side of the box, which doesn't clip it
+            min = min.max(t0.min(t1));
+            max = max.min(t0.max(t1));
+            if max < min {
+                return None;
+            }
+        }
+        Some((min, max))
+    }
+
     pub fn surrounding(&self, other: Self) -> Self {
         let min = Vec3f::new(
             self.min.x().min(other.min.x()),
@@ -44,4 +73,13 @@ impl Bound {
         );
         Self { min, max }
     }
+
+    pub fn surface_area(&self) -> f64 {
+        let extent = self.max - self.min;
+        2.0 * (extent.x() * extent.y() + extent.y() * extent.z() + extent.z() * extent.x())
+    }
+
+    pub fn centroid(&self) -> Vec3f<Position> {
+        (self.min + self.max) * 0.5
+    }
 }
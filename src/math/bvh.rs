@@ -0,0 +1,560 @@
+use rand::Rng;
+use rayon::prelude::*;
+
+use std::cmp::Ordering;
+use std::fmt::{self, Display, Formatter};
+
+use super::{Bound, HitRecord, Hittable, Light, List, Position, Ray, Sampler, Vec3f};
+
+/// Number of buckets in which the centroids are binned to evaluate the SAH.
+const BINS: usize = 12;
+/// Cost of visiting a node, relative to intersecting an object.
+const TRAVERSAL_COST: f64 = 0.125;
+/// Leaves with more objects are always split.
+const MAX_LEAF_SIZE: usize = 4;
+
+/// How the objects of a node are divided between its children.
+#[derive(Clone, Copy, Debug, Default, Eq, PartialEq)]
+pub enum SplitMethod {
+    /// Binned Surface Area Heuristic. Picks the split with the lowest expected
+    /// cost and keeps small groups of objects together in a leaf when it's cheaper.
+    #[default]
+    Sah,
+    /// Splits the objects in halves along a random axis, down to one object per leaf.
+    /// The axes are drawn from `seed` and the node, so the tree is the same however
+    /// many threads build it.
+    Median { seed: u64 },
+}
+
+/// Shape of a [`BvhTree`], to compare construction methods.
+#[derive(Clone, Debug, Default)]
+pub struct BvhStats {
+    /// Number of nodes, leaves included.
+    pub nodes: usize,
+    pub leaves: usize,
+    /// Length of the longest path from the root to a leaf.
+    pub depth: usize,
+    /// `leaf_sizes[n]` is the number of leaves with `n` objects.
+    pub leaf_sizes: Vec<usize>,
+    /// Expected cost of a ray that hits the root, in units of object intersections,
+    /// according to the Surface Area Heuristic.
+    pub cost: f64,
+}
+
+impl BvhStats {
+    pub fn objects(&self) -> usize {
+        self.leaf_sizes
+            .iter()
+            .enumerate()
+            .map(|(size, count)| size * count)
+            .sum()
+    }
+
+    fn leaf(&mut self, size: usize, depth: usize, relative_area: f64) {
+        self.nodes += 1;
+        self.leaves += 1;
+        self.depth = self.depth.max(depth);
+        if self.leaf_sizes.len() <= size {
+            self.leaf_sizes.resize(size + 1, 0);
+        }
+        self.leaf_sizes[size] += 1;
+        self.cost += relative_area * size as f64;
+    }
+
+    fn branch(&mut self, relative_area: f64) {
+        self.nodes += 1;
+        self.cost += relative_area * TRAVERSAL_COST;
+    }
+
+    fn merge(&mut self, other: BvhStats) {
+        self.nodes += other.nodes;
+        self.leaves += other.leaves;
+        self.depth = self.depth.max(other.depth);
+        if self.leaf_sizes.len() < other.leaf_sizes.len() {
+            self.leaf_sizes.resize(other.leaf_sizes.len(), 0);
+        }
+        for (count, other) in self.leaf_sizes.iter_mut().zip(other.leaf_sizes) {
+            *count += other;
+        }
+        self.cost += other.cost;
+    }
+}
+
+impl Display for BvhStats {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{} objects, {} nodes, {} leaves ({:.2} objects per leaf), depth {}, cost {:.2}",
+            self.objects(),
+            self.nodes,
+            self.leaves,
+            self.objects() as f64 / self.leaves as f64,
+            self.depth,
+            self.cost
+        )
+    }
+}
+
+/// An object being placed in the tree.
+#[derive(Clone, Copy)]
+struct Primitive {
+    /// Position in the original list.
+    index: usize,
+    bound: Bound,
+    centroid: Vec3f<Position>,
+}
+
+fn surrounding(primitives: &[Primitive], bound: impl Fn(&Primitive) -> Bound) -> Bound {
+    primitives
+        .iter()
+        .map(bound)
+        .reduce(|a, b| a.surrounding(b))
+        .unwrap_or_default()
+}
+
+/// Moves the elements that satisfy `predicate` to the front.
+/// Returns the number of such elements.
+fn partition<T>(slice: &mut [T], predicate: impl Fn(&T) -> bool) -> usize {
+    let mut mid = 0;
+    for i in 0..slice.len() {
+        if predicate(&slice[i]) {
+            slice.swap(i, mid);
+            mid += 1;
+        }
+    }
+    mid
+}
+
+/// Finds the cheapest binned SAH split of `primitives`, which are contained by `bound`,
+/// and partitions them accordingly. Returns the size of the first half and the split axis,
+/// or `None` if a leaf is cheaper.
+fn sah_split(primitives: &mut [Primitive], bound: Bound) -> Option<(usize, usize)> {
+    let length = primitives.len();
+    if length == 1 {
+        return None;
+    }
+    let centroids = surrounding(primitives, |p| Bound {
+        min: p.centroid,
+        max: p.centroid,
+    });
+    let extent = centroids.max - centroids.min;
+    let axis = if extent.x() > extent.y() && extent.x() > extent.z() {
+        0
+    } else if extent.y() > extent.z() {
+        1
+    } else {
+        2
+    };
+    let (low, high) = (centroids.min.at(axis), centroids.max.at(axis));
+    // Every centroid is at the same place, no split can separate them
+    if high <= low {
+        return if length <= MAX_LEAF_SIZE {
+            None
+        } else {
+            Some((length / 2, axis))
+        };
+    }
+
+    let bin = |p: &Primitive| {
+        let bin = ((p.centroid.at(axis) - low) / (high - low) * BINS as f64) as usize;
+        bin.min(BINS - 1)
+    };
+    let mut counts = [0; BINS];
+    let mut bounds: [Option<Bound>; BINS] = [None; BINS];
+    for primitive in primitives.iter() {
+        let i = bin(primitive);
+        counts[i] += 1;
+        bounds[i] = Some(bounds[i].map_or(primitive.bound, |b| b.surrounding(primitive.bound)));
+    }
+
+    // `below[i]` and `above[i]` hold the count and area at each side of the split after bin `i`
+    let sweep = |bins: &mut dyn Iterator<Item = usize>| {
+        let mut count = 0;
+        let mut bound: Option<Bound> = None;
+        bins.map(|i| {
+            count += counts[i];
+            if let Some(b) = bounds[i] {
+                bound = Some(bound.map_or(b, |bound| bound.surrounding(b)));
+            }
+            (count, bound.map_or(0.0, |b| b.surface_area()))
+        })
+        .collect::<Vec<_>>()
+    };
+    let below = sweep(&mut (0..BINS - 1));
+    let mut above = sweep(&mut (1..BINS).rev());
+    above.reverse();
+
+    // Costs are scaled by the area of the node, which doesn't change the minimum
+    let (split, cost) = below
+        .iter()
+        .zip(&above)
+        .map(|(&(count_below, area_below), &(count_above, area_above))| {
+            TRAVERSAL_COST * bound.surface_area()
+                + count_below as f64 * area_below
+                + count_above as f64 * area_above
+        })
+        .enumerate()
+        .min_by(|(_, a), (_, b)| a.partial_cmp(b).unwrap_or(Ordering::Equal))?;
+    let leaf_cost = length as f64 * bound.surface_area();
+    if length <= MAX_LEAF_SIZE && leaf_cost <= cost {
+        return None;
+    }
+    // Both sides hold at least the primitives with the extreme centroids
+    Some((partition(primitives, |p| bin(p) <= split), axis))
+}
+
+/// Node of a [`Bvh`].
+#[derive(Clone, Copy, PartialEq)]
+enum Node {
+    /// Holds the primitives `start..start + count` of the order returned by [`Bvh::build`].
+    Leaf {
+        bound: Bound,
+        start: usize,
+        count: usize,
+    },
+    /// The first child is the next node, the second one is at `second`.
+    Branch {
+        bound: Bound,
+        second: usize,
+        axis: usize,
+    },
+}
+
+impl Node {
+    fn bound(&self) -> Bound {
+        match self {
+            Node::Leaf { bound, .. } | Node::Branch { bound, .. } => *bound,
+        }
+    }
+}
+
+/// Nodes deeper than this are split in halves, which bounds the depth of the tree
+/// to twice this value, the size of the traversal stack.
+const MAX_DEPTH: usize = 64;
+
+/// Subtrees with fewer primitives are built by a single thread.
+const PARALLEL_THRESHOLD: usize = 4096;
+
+struct Builder {
+    method: SplitMethod,
+    root_area: f64,
+    /// Subtrees with fewer primitives are built by a single thread.
+    parallel_threshold: usize,
+}
+
+impl Builder {
+    /// Appends the subtree of `primitives`, which start at `offset` in the final order,
+    /// to `nodes` in depth-first order. `primitives` is partitioned in place.
+    ///
+    /// Large subtrees are built in parallel into their own vectors and then appended,
+    /// which gives the same nodes as appending them one after the other.
+    fn build(
+        &self,
+        primitives: &mut [Primitive],
+        offset: usize,
+        depth: usize,
+        nodes: &mut Vec<Node>,
+        stats: &mut BvhStats,
+    ) {
+        let bound = surrounding(primitives, |p| p.bound);
+        let relative_area = if self.root_area > 0.0 {
+            bound.surface_area() / self.root_area
+        } else {
+            1.0
+        };
+        let split = match self.method {
+            _ if primitives.len() == 1 => None,
+            _ if depth >= MAX_DEPTH => Some((primitives.len() / 2, 0)),
+            SplitMethod::Sah => sah_split(primitives, bound),
+            SplitMethod::Median { seed } => {
+                // Seeded from the node, so the axes don't depend on the order of construction
+                let mut rng = Sampler::for_sample(seed, offset as u64, primitives.len() as u64);
+                let axis = rng.gen_range(0..3);
+                let mid = primitives.len() / 2;
+                primitives.select_nth_unstable_by(mid, |a, b| {
+                    a.bound
+                        .min
+                        .at(axis)
+                        .partial_cmp(&b.bound.min.at(axis))
+                        .unwrap_or(Ordering::Equal)
+                });
+                Some((mid, axis))
+            }
+        };
+        let (mid, axis) = match split {
+            Some(split) => split,
+            None => {
+                stats.leaf(primitives.len(), depth, relative_area);
+                nodes.push(Node::Leaf {
+                    bound,
+                    start: offset,
+                    count: primitives.len(),
+                });
+                return;
+            }
+        };
+        stats.branch(relative_area);
+        let index = nodes.len();
+        nodes.push(Node::Branch {
+            bound,
+            second: 0,
+            axis,
+        });
+        let parallel = primitives.len() >= self.parallel_threshold;
+        let (left, right) = primitives.split_at_mut(mid);
+        let second = if !parallel {
+            self.build(left, offset, depth + 1, nodes, stats);
+            let second = nodes.len();
+            self.build(right, offset + mid, depth + 1, nodes, stats);
+            second
+        } else {
+            let subtree = |primitives: &mut [Primitive], offset: usize| {
+                let mut nodes = Vec::new();
+                let mut stats = BvhStats::default();
+                self.build(primitives, offset, depth + 1, &mut nodes, &mut stats);
+                (nodes, stats)
+            };
+            let ((left_nodes, left_stats), (right_nodes, right_stats)) = rayon::join(
+                || subtree(left, offset),
+                || subtree(right, offset + mid),
+            );
+            append(nodes, left_nodes);
+            let second = nodes.len();
+            append(nodes, right_nodes);
+            stats.merge(left_stats);
+            stats.merge(right_stats);
+            second
+        };
+        if let Node::Branch { second: s, .. } = &mut nodes[index] {
+            *s = second;
+        }
+    }
+}
+
+/// Appends a subtree built on its own, moving the indices of its nodes.
+fn append(nodes: &mut Vec<Node>, subtree: Vec<Node>) {
+    let base = nodes.len();
+    nodes.extend(subtree.into_iter().map(|mut node| {
+        if let Node::Branch { second, .. } = &mut node {
+            *second += base;
+        }
+        node
+    }));
+}
+
+/// A bounding volume hierarchy over primitives stored elsewhere,
+/// flattened into a vector in depth-first order.
+pub(crate) struct Bvh {
+    nodes: Vec<Node>,
+    stats: BvhStats,
+}
+
+impl Bvh {
+    /// Builds the hierarchy of the primitives with the given bounds. Also returns the order
+    /// in which the primitives must be stored, so every leaf holds a contiguous range.
+    /// The subtrees are built in parallel, the result is the same for any number of threads.
+    ///
+    /// # Panics
+    /// If `bounds` is empty.
+    pub(crate) fn build(bounds: &[Bound], method: SplitMethod) -> (Self, Vec<usize>) {
+        Self::build_with_threshold(bounds, method, PARALLEL_THRESHOLD)
+    }
+
+    fn build_with_threshold(
+        bounds: &[Bound],
+        method: SplitMethod,
+        parallel_threshold: usize,
+    ) -> (Self, Vec<usize>) {
+        assert!(!bounds.is_empty(), "A BVH needs at least one primitive");
+        let mut primitives = bounds
+            .par_iter()
+            .enumerate()
+            .map(|(index, &bound)| Primitive {
+                index,
+                bound,
+                centroid: bound.centroid(),
+            })
+            .collect::<Vec<_>>();
+        let root_area = surrounding(&primitives, |p| p.bound).surface_area();
+        let builder = Builder {
+            method,
+            root_area,
+            parallel_threshold,
+        };
+        let mut nodes = Vec::with_capacity(2 * bounds.len());
+        let mut stats = BvhStats::default();
+        builder.build(&mut primitives, 0, 0, &mut nodes, &mut stats);
+        let order = primitives.iter().map(|p| p.index).collect();
+        (Self { nodes, stats }, order)
+    }
+
+    pub(crate) fn bound(&self) -> Bound {
+        self.nodes[0].bound()
+    }
+
+    pub(crate) fn stats(&self) -> &BvhStats {
+        &self.stats
+    }
+
+    /// Finds the closest primitive hit by the ray. `intersect` is called with the position
+    /// of a primitive in the build order and the current `t_max`, and returns the `t` of
+    /// the hit, if any. The children of every node are visited front to back.
+    pub(crate) fn closest<T>(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        mut t_max: f64,
+        mut intersect: impl FnMut(usize, f64) -> Option<(f64, T)>,
+    ) -> Option<T> {
+        let negative = [
+            ray.direction().x() < 0.0,
+            ray.direction().y() < 0.0,
+            ray.direction().z() < 0.0,
+        ];
+        let mut closest = None;
+        let mut stack = [0; 2 * MAX_DEPTH];
+        let mut length = 0;
+        let mut index = 0;
+        loop {
+            let node = &self.nodes[index];
+            if node.bound().hit(ray, t_min, t_max) {
+                match *node {
+                    Node::Leaf { start, count, .. } => {
+                        for primitive in start..start + count {
+                            if let Some((t, hit)) = intersect(primitive, t_max) {
+                                t_max = t;
+                                closest = Some(hit);
+                            }
+                        }
+                    }
+                    Node::Branch { second, axis, .. } => {
+                        // Visit first the child closer to the origin of the ray
+                        let (near, far) = if negative[axis] {
+                            (second, index + 1)
+                        } else {
+                            (index + 1, second)
+                        };
+                        stack[length] = far;
+                        length += 1;
+                        index = near;
+                        continue;
+                    }
+                }
+            }
+            if length == 0 {
+                return closest;
+            }
+            length -= 1;
+            index = stack[length];
+        }
+    }
+}
+
+/// Bounding Volume Hierarchy of objects, which are kept in a contiguous vector.
+pub struct BvhTree {
+    objects: Vec<Box<dyn Hittable>>,
+    bvh: Bvh,
+}
+
+impl From<List> for BvhTree {
+    fn from(list: List) -> Self {
+        Self::from_time(list, 0., 1.)
+    }
+}
+
+impl BvhTree {
+    /// Builds the tree with the default [`SplitMethod`].
+    ///
+    /// # Panics
+    /// If the list is empty or an object has no bounding box.
+    pub fn from_time(list: List, initial_time: f64, final_time: f64) -> Self {
+        Self::with_split_method(list, initial_time, final_time, SplitMethod::default())
+    }
+
+    /// The objects must be bounded between `initial_time` and `final_time`.
+    ///
+    /// # Panics
+    /// If the list is empty or an object has no bounding box.
+    pub fn with_split_method(
+        list: List,
+        initial_time: f64,
+        final_time: f64,
+        method: SplitMethod,
+    ) -> Self {
+        assert!(!list.list.is_empty(), "A BvhTree needs at least one object");
+        let bounds = list
+            .list
+            .par_iter()
+            .map(|object| {
+                object
+                    .bounding_box(initial_time, final_time)
+                    .expect("The objects of a BvhTree must be bounded")
+            })
+            .collect::<Vec<_>>();
+        let (bvh, order) = Bvh::build(&bounds, method);
+        let mut objects = list.list.into_iter().map(Some).collect::<Vec<_>>();
+        let objects = order
+            .into_iter()
+            .filter_map(|index| objects[index].take())
+            .collect();
+        Self { objects, bvh }
+    }
+
+    pub fn stats(&self) -> &BvhStats {
+        self.bvh.stats()
+    }
+}
+
+impl Hittable for BvhTree {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        self.bvh.closest(ray, t_min, t_max, |index, t_max| {
+            let record = self.objects[index].hit(ray, t_min, t_max, sampler)?;
+            Some((record.t, record))
+        })
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        Some(self.bvh.bound())
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        for object in &self.objects {
+            object.lights(lights);
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn bounds(count: usize) -> Vec<Bound> {
+        let mut sampler = Sampler::new(1);
+        (0..count)
+            .map(|_| {
+                let min = Vec3f::random(-100.0..100.0, &mut sampler);
+                Bound {
+                    min,
+                    max: min + Vec3f::random(0.0..2.0, &mut sampler),
+                }
+            })
+            .collect()
+    }
+
+    #[test]
+    fn parallel_build_is_the_same_as_serial() {
+        let bounds = bounds(3 * PARALLEL_THRESHOLD);
+        for method in [SplitMethod::Sah, SplitMethod::Median { seed: 3 }] {
+            let (serial, serial_order) = Bvh::build_with_threshold(&bounds, method, usize::MAX);
+            let (parallel, parallel_order) = Bvh::build(&bounds, method);
+            assert!(serial.nodes == parallel.nodes, "{:?}", method);
+            assert_eq!(serial_order, parallel_order);
+        }
+    }
+}
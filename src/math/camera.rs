@@ -1,4 +1,4 @@
-use super::{Position, Ray, Vec3f};
+use super::{Position, Ray, Sampler, Vec3f};
 use rand::Rng;
 
 #[derive(Default)]
@@ -60,8 +60,7 @@ impl Camera {
         }
     }
     /// Generate a point around
-    fn random_in_unit_disk() -> Vec3f<Position> {
-        let mut rng = rand::thread_rng();
+    fn random_in_unit_disk(rng: &mut Sampler) -> Vec3f<Position> {
         loop {
             let p = 2.0 * Vec3f::new(rng.gen(), rng.gen(), 0.0) - Vec3f::new(1.0, 1.0, 0.0);
             if p.dot(p) < 1.0 {
@@ -70,16 +69,20 @@ impl Camera {
         }
     }
     /// The resulting ray pointing from the camera to the (u, v) coordinates.
-    pub fn ray(&self, s: f64, t: f64) -> Ray {
-        let mut rng = rand::thread_rng();
-        let rd = self.lens_radius * Self::random_in_unit_disk();
+    pub fn ray(&self, s: f64, t: f64, sampler: &mut Sampler) -> Ray {
+        let rd = self.lens_radius * Self::random_in_unit_disk(sampler);
         let offset = self.u * rd.x() + self.v * rd.y();
         Ray {
             a: self.origin + offset,
             b: self.lower_left_corner + s * self.horizontal + t * self.vertical
                 - self.origin
                 - offset,
-            time: rng.gen_range(self.open_time..self.close_time),
+            time: if self.open_time < self.close_time {
+                sampler.gen_range(self.open_time..self.close_time)
+            } else {
+                self.open_time
+            },
+            wavelength: None,
         }
     }
 }
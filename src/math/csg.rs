@@ -0,0 +1,248 @@
+use super::{Bound, HitRecord, Hittable, Position, Ray, Sampler, Vec3f};
+
+/// How a [`Csg`] combines the space inside its operands.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum CsgOperation {
+    /// Inside either operand
+    Union,
+    /// Inside both operands
+    Intersection,
+    /// Inside the left operand but not the right one
+    Difference,
+}
+
+impl CsgOperation {
+    fn contains(self, left: bool, right: bool) -> bool {
+        match self {
+            CsgOperation::Union => left || right,
+            CsgOperation::Intersection => left && right,
+            CsgOperation::Difference => left && !right,
+        }
+    }
+}
+
+/// Constructive solid geometry: a solid made of two closed objects.
+///
+/// The ray is followed through every crossing of both operands, and the
+/// crossings where it enters or leaves the combined solid are its surface.
+pub struct Csg {
+    pub operation: CsgOperation,
+    pub left: Box<dyn Hittable>,
+    pub right: Box<dyn Hittable>,
+}
+
+impl Csg {
+    pub fn new(operation: CsgOperation, left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Self {
+        Self {
+            operation,
+            left,
+            right,
+        }
+    }
+
+    pub fn union(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Self {
+        Self::new(CsgOperation::Union, left, right)
+    }
+
+    pub fn intersection(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Self {
+        Self::new(CsgOperation::Intersection, left, right)
+    }
+
+    pub fn difference(left: Box<dyn Hittable>, right: Box<dyn Hittable>) -> Self {
+        Self::new(CsgOperation::Difference, left, right)
+    }
+}
+
+/// The part of the ray up to `t_max` in which it can cross `operand`: before it, the ray
+/// is outside of the operand. The box is padded so no crossing on its sides is lost.
+fn walk(operand: &dyn Hittable, ray: Ray, t_max: f64) -> Option<(f64, f64)> {
+    match operand.bounding_box(ray.time, ray.time) {
+        Some(bound) => {
+            let padded = bound.padded(1e-6 * (bound.max - bound.min).magnitude());
+            padded.clip(ray, f64::NEG_INFINITY, t_max)
+        }
+        None => Some((f64::NEG_INFINITY, t_max)),
+    }
+}
+
+impl Csg {
+    /// Calls `surface` with every crossing in `t_min..t_max` where the ray enters or leaves
+    /// the solid, in order, until it returns `false`. The crossings of the operands are
+    /// collected into `operands`.
+    fn walk_surface<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+        operands: &mut Vec<HitRecord<'a>>,
+        mut surface: impl FnMut(HitRecord<'a>) -> bool,
+    ) {
+        if let Some(bound) = self.bounding_box(ray.time, ray.time) {
+            if !bound.hit(ray, t_min, t_max) {
+                return;
+            }
+        }
+        // Whether the ray is inside an operand is only known from where it
+        // starts outside of it, so the line is followed from the box of the operand.
+        if let Some((start, end)) = walk(self.left.as_ref(), ray, t_max) {
+            self.left.crossings(ray, start, end, sampler, operands);
+        }
+        let split = operands.len();
+        if let Some((start, end)) = walk(self.right.as_ref(), ray, t_max) {
+            self.right.crossings(ray, start, end, sampler, operands);
+        }
+        let (left, right) = operands.split_at(split);
+
+        let (mut inside_left, mut inside_right) = (false, false);
+        let (mut left, mut right) = (left.iter().peekable(), right.iter().peekable());
+        loop {
+            let from_left = match (left.peek(), right.peek()) {
+                (Some(a), Some(b)) => a.t <= b.t,
+                (Some(_), None) => true,
+                (None, Some(_)) => false,
+                (None, None) => break,
+            };
+            let inside = self.operation.contains(inside_left, inside_right);
+            let record = if from_left {
+                let record = left.next().unwrap();
+                inside_left = record.front_face;
+                record
+            } else {
+                let record = right.next().unwrap();
+                inside_right = record.front_face;
+                record
+            };
+            let now_inside = self.operation.contains(inside_left, inside_right);
+            if now_inside != inside && t_min <= record.t && record.t <= t_max {
+                // The normal already faces the ray. Where the ray leaves the right
+                // operand of a difference, it enters the solid through its surface.
+                let crossing = HitRecord {
+                    front_face: now_inside,
+                    ..*record
+                };
+                if !surface(crossing) {
+                    return;
+                }
+            }
+        }
+    }
+}
+
+impl Hittable for Csg {
+    /// Stops at the first crossing of the surface of the solid.
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let mut first = None;
+        self.walk_surface(ray, t_min, t_max, sampler, &mut Vec::new(), |record| {
+            first = Some(record);
+            false
+        });
+        first
+    }
+
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        self.walk_surface(ray, t_min, t_max, sampler, &mut Vec::new(), |record| {
+            crossings.push(record);
+            true
+        });
+    }
+
+    fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
+        let left = self.left.bounding_box(initial_time, final_time);
+        let right = self.right.bounding_box(initial_time, final_time);
+        match self.operation {
+            CsgOperation::Union => Some(left?.surrounding(right?)),
+            CsgOperation::Intersection => match (left, right) {
+                (Some(left), Some(right)) => {
+                    let min: Vec3f<Position> = Vec3f::new(
+                        left.min.x().max(right.min.x()),
+                        left.min.y().max(right.min.y()),
+                        left.min.z().max(right.min.z()),
+                    );
+                    // Operands that don't overlap leave an empty box at a corner
+                    let max = Vec3f::new(
+                        left.max.x().min(right.max.x()).max(min.x()),
+                        left.max.y().min(right.max.y()).max(min.y()),
+                        left.max.z().min(right.max.z()).max(min.z()),
+                    );
+                    Some(Bound { min, max })
+                }
+                (bound, None) | (None, bound) => bound,
+            },
+            CsgOperation::Difference => left,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{self, gray};
+    use crate::math::Cube;
+
+    fn cube(half_size: Vec3f<Position>) -> Box<dyn Hittable> {
+        Box::new(Cube::new(-half_size, half_size, gray()))
+    }
+
+    fn sphere(x: f64, radius: f64) -> Box<dyn Hittable> {
+        Box::new(test_util::sphere(Vec3f::new(x, 0.0, 0.0), radius))
+    }
+
+    /// The crossings of a ray along X from `x`, the first of which is its hit.
+    fn crossings(csg: &Csg, x: f64) -> Vec<(f64, bool)> {
+        let origin = Vec3f::new(x, 0.0, 0.0);
+        let crossings = test_util::crossings(csg, origin);
+        let ray = Ray {
+            a: origin,
+            b: Vec3f::new(1.0, 0.0, 0.0),
+            ..Ray::default()
+        };
+        let hit = csg.hit(ray, 0.0, f64::INFINITY, &mut Sampler::new(0));
+        let face = |record: HitRecord| (record.t, record.front_face);
+        assert_eq!(hit.map(face), crossings.first().copied());
+        crossings
+    }
+
+    fn assert_crossings(actual: &[(f64, bool)], expected: &[(f64, bool)]) {
+        test_util::assert_crossings(actual, expected, 1e-9);
+    }
+
+    #[test]
+    fn difference_crossings() {
+        // A bite out of the side of a cube
+        let bitten = Csg::difference(cube(Vec3f::repeat(1.0)), sphere(1.0, 0.5));
+        assert_crossings(&crossings(&bitten, -5.0), &[(4.0, true), (5.5, false)]);
+
+        // A hollow cube, entered again where the ray leaves the hole
+        let hollow = Csg::difference(cube(Vec3f::repeat(1.0)), sphere(0.0, 0.5));
+        let expected = [(4.0, true), (4.5, false), (5.5, true), (6.0, false)];
+        assert_crossings(&crossings(&hollow, -5.0), &expected);
+        assert_crossings(&crossings(&hollow, 0.0), &[(0.5, true), (1.0, false)]);
+        assert_crossings(&crossings(&hollow, 0.75), &[(0.25, false)]);
+    }
+
+    #[test]
+    fn intersection_crossings() {
+        let slab = cube(Vec3f::new(0.5, 2.0, 2.0));
+        let slice = Csg::intersection(slab, sphere(0.0, 1.0));
+        assert_crossings(&crossings(&slice, -5.0), &[(4.5, true), (5.5, false)]);
+
+        let lens = Csg::intersection(cube(Vec3f::repeat(1.0)), sphere(1.0, 1.0));
+        assert_crossings(&crossings(&lens, -5.0), &[(5.0, true), (6.0, false)]);
+        assert_crossings(&crossings(&lens, 0.5), &[(0.5, false)]);
+        assert_crossings(&crossings(&lens, 1.5), &[]);
+    }
+}
@@ -3,12 +3,12 @@
 
 use rand::Rng;
 
-use std::cmp::Ordering;
 use std::marker::PhantomData;
 use std::ops::Range;
 use std::sync::Arc;
 
-use super::{Bound, Coordinate, Material, Position, Ray, Vec3f, Isotropic};
+use super::medium::inside_intervals;
+use super::{AnimatedTransform, Bound, Coordinate, Keyframe, Material, Matrix4, Onb, Position, Ray, Sampler, Transform, Vec3f, Isotropic};
 use crate::math::Texture;
 
 /// The result after a ray hits an object.
@@ -44,10 +44,53 @@ impl HitRecord<'_> {
     }
 }
 
+/// The smallest `t` past a hit at `t` that doesn't hit the same point again.
+pub(super) fn after(t: f64) -> f64 {
+    t + 1e-9 * t.abs().max(1.0)
+}
+
 /// Trait for objects that a ray can hit.
 pub trait Hittable: Send + Sync {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord>;
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>>;
     fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound>;
+
+    /// Adds the emissive shapes to `lights`, so the renderer can sample them directly.
+    /// Objects that move the objects inside them add themselves to the transforms of
+    /// the lights inside.
+    fn lights<'a>(&'a self, _: &mut Vec<Light<'a>>) {}
+
+    /// Adds to `crossings` every hit in `t_min..t_max` along the ray, sorted by `t`.
+    /// Closed objects alternate between the ray entering them (`front_face`) and leaving
+    /// them, which is how [`Csg`](super::Csg) combines them.
+    ///
+    /// By default, the object is hit again just after every hit.
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        let mut t = t_min;
+        while let Some(record) = self.hit(ray, t, t_max, sampler) {
+            t = after(record.t);
+            crossings.push(record);
+        }
+    }
+
+    /// Whether the object is closed and convex, so a ray crosses it at most twice:
+    /// in at the first hit, and out at the next one.
+    fn is_convex(&self) -> bool {
+        false
+    }
+
     fn translate(self, offset: Vec3f<Position>) -> Translate
     where
         Self: 'static + Sized,
@@ -63,6 +106,53 @@ pub trait Hittable: Send + Sync {
     {
         YRotate::new(Box::new(self), angle)
     }
+
+    /// Moves the object by an affine transform.
+    fn transform(self, matrix: Matrix4) -> Transform
+    where
+        Self: 'static + Sized,
+    {
+        Transform::new(Box::new(self), matrix)
+    }
+    fn rotate_x(self, angle: f64) -> Transform
+    where
+        Self: 'static + Sized,
+    {
+        self.transform(Matrix4::rotation_x(angle))
+    }
+    fn rotate_z(self, angle: f64) -> Transform
+    where
+        Self: 'static + Sized,
+    {
+        self.transform(Matrix4::rotation_z(angle))
+    }
+    /// Counterclockwise rotation around `axis`, in degrees.
+    fn rotate(self, axis: Vec3f<Position>, angle: f64) -> Transform
+    where
+        Self: 'static + Sized,
+    {
+        self.transform(Matrix4::rotation(axis, angle))
+    }
+    fn scale(self, factor: Vec3f<Position>) -> Transform
+    where
+        Self: 'static + Sized,
+    {
+        self.transform(Matrix4::scaling(factor))
+    }
+    /// Moves the object along keyframes in time, for motion blur.
+    fn animate(self, keyframes: Vec<Keyframe>) -> AnimatedTransform
+    where
+        Self: 'static + Sized,
+    {
+        AnimatedTransform::new(Box::new(self), keyframes)
+    }
+    /// Places the object at `from`, with its Z axis pointing to `to`.
+    fn look_at(self, from: Vec3f<Position>, to: Vec3f<Position>, view_up: Vec3f<Position>) -> Transform
+    where
+        Self: 'static + Sized,
+    {
+        self.transform(Matrix4::look_at(from, to, view_up))
+    }
 }
 
 /// A sphere that can be hit by a ray.
@@ -84,7 +174,13 @@ impl Sphere {
 }
 
 impl Hittable for Sphere {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
         let oc = ray.origin() - self.center;
         // Solve quadratic equation
         let a = ray.direction().dot(ray.direction());
@@ -128,6 +224,49 @@ impl Hittable for Sphere {
             max: self.center + Vec3f::repeat(self.radius),
         })
     }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+impl LightShape for Sphere {
+    /// Uniform over the cone of directions that see the sphere,
+    /// or over every direction from inside the sphere.
+    fn pdf_value(&self, ray: Ray, sampler: &mut Sampler) -> f64 {
+        if self.hit(ray, 0.001, f64::INFINITY, sampler).is_none() {
+            return 0.0;
+        }
+        let distance_squared = (self.center - ray.origin()).squared_length();
+        let radius_squared = self.radius.powi(2);
+        if distance_squared <= radius_squared {
+            return 1.0 / (4.0 * std::f64::consts::PI);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        // 1 - cos_theta_max, without the cancellation of small cones
+        let one_minus_cos = radius_squared / distance_squared / (1.0 + cos_theta_max);
+        1.0 / (2.0 * std::f64::consts::PI * one_minus_cos)
+    }
+
+    fn random(&self, origin: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position> {
+        let direction = self.center - origin;
+        let distance_squared = direction.squared_length();
+        let radius_squared = self.radius.powi(2);
+        if distance_squared <= radius_squared {
+            return Vec3f::random_unit_vector(sampler);
+        }
+        let cos_theta_max = (1.0 - radius_squared / distance_squared).sqrt();
+        let phi = 2.0 * std::f64::consts::PI * sampler.gen::<f64>();
+        let z = 1.0 + sampler.gen::<f64>() * (cos_theta_max - 1.0);
+        let sin_theta = (1.0 - z * z).max(0.0).sqrt();
+        Onb::from_w(direction).local(phi.cos() * sin_theta, phi.sin() * sin_theta, z)
+    }
 }
 
 /// List of items that can be hit by a ray
@@ -150,11 +289,17 @@ impl List {
     }
 }
 impl Hittable for List {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
         let mut record = None;
         let mut closest = t_max;
         for i in &self.list {
-            if let Some(new_record) = i.hit(ray, t_min, closest) {
+            if let Some(new_record) = i.hit(ray, t_min, closest, sampler) {
                 closest = new_record.t;
                 record = Some(new_record);
             }
@@ -174,13 +319,129 @@ impl Hittable for List {
         }
         None
     }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        for item in &self.list {
+            item.lights(lights);
+        }
+    }
+}
+
+/// A shape that can be sampled by the directions that see it from a point, as a light.
+pub trait LightShape: Send + Sync {
+    /// Density, with respect to solid angle, with which [`LightShape::random`]
+    /// picks the direction of `ray` from its origin.
+    fn pdf_value(&self, ray: Ray, sampler: &mut Sampler) -> f64;
+
+    /// Random direction from `origin` to the shape.
+    fn random(&self, origin: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position>;
+}
+
+/// An object that moves the objects inside it, and so the samples of their lights.
+pub trait LightTransform: Send + Sync {
+    /// `ray` in the space of the object inside.
+    fn ray_to_object(&self, ray: Ray) -> Ray;
+
+    fn point_to_object(&self, point: Vec3f<Position>) -> Vec3f<Position>;
+
+    /// A direction in the space of the object inside, in the space outside.
+    fn direction_to_world(&self, direction: Vec3f<Position>) -> Vec3f<Position>;
+
+    /// Density of the direction of `ray` per density of the same direction inside,
+    /// for transforms that don't keep solid angles.
+    fn pdf_scale(&self, _: Ray) -> f64 {
+        1.0
+    }
+}
+
+/// An emissive shape, and the objects that move it into the world, innermost first.
+#[derive(Clone)]
+pub struct Light<'a> {
+    shape: &'a dyn LightShape,
+    transforms: Vec<&'a dyn LightTransform>,
+}
+
+impl<'a> Light<'a> {
+    pub fn new(shape: &'a dyn LightShape) -> Self {
+        Self {
+            shape,
+            transforms: Vec::new(),
+        }
+    }
+
+    /// Moves the light by the `transform` around it.
+    pub fn push_transform(&mut self, transform: &'a dyn LightTransform) {
+        self.transforms.push(transform);
+    }
+
+    /// Density, with respect to solid angle, with which [`Light::random`]
+    /// picks the direction of `ray` from its origin.
+    pub fn pdf_value(&self, mut ray: Ray, sampler: &mut Sampler) -> f64 {
+        let mut scale = 1.0;
+        for transform in self.transforms.iter().rev() {
+            scale *= transform.pdf_scale(ray);
+            ray = transform.ray_to_object(ray);
+        }
+        scale * self.shape.pdf_value(ray, sampler)
+    }
+
+    /// Random direction from `origin` to the light.
+    pub fn random(&self, mut origin: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position> {
+        for transform in self.transforms.iter().rev() {
+            origin = transform.point_to_object(origin);
+        }
+        let direction = self.shape.random(origin, sampler);
+        self.transforms
+            .iter()
+            .fold(direction, |direction, transform| transform.direction_to_world(direction))
+    }
+}
+
+/// The lights of a world, collected once and each sampled with the same probability.
+pub struct Lights<'a> {
+    lights: Vec<Light<'a>>,
+}
+
+impl<'a> Lights<'a> {
+    pub fn new(world: &'a dyn Hittable) -> Self {
+        let mut lights = Vec::new();
+        world.lights(&mut lights);
+        Self { lights }
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.lights.is_empty()
+    }
+
+    /// Density, with respect to solid angle, with which [`Lights::random`]
+    /// picks the direction of `ray` from its origin.
+    pub fn pdf_value(&self, ray: Ray, sampler: &mut Sampler) -> f64 {
+        if self.lights.is_empty() {
+            return 0.0;
+        }
+        let sum: f64 = self
+            .lights
+            .iter()
+            .map(|light| light.pdf_value(ray, sampler))
+            .sum();
+        sum / self.lights.len() as f64
+    }
+
+    /// Random direction from `origin` to one of the lights.
+    ///
+    /// # Panics
+    /// If there are no lights.
+    pub fn random(&self, origin: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position> {
+        let light = &self.lights[sampler.gen_range(0..self.lights.len())];
+        light.random(origin, sampler)
+    }
 }
 
 pub struct MovingSphere {
     pub center: Range<Vec3f<Position>>,
     pub time: Range<f64>,
     pub radius: f64,
-    pub material: Box<dyn Material>,
+    pub material: Arc<dyn Material>,
 }
 
 impl MovingSphere {
@@ -192,7 +453,13 @@ impl MovingSphere {
 }
 
 impl Hittable for MovingSphere {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
         let oc = ray.origin() - self.center(ray.time);
         // Solve quadratic equation
         let a = ray.direction().dot(ray.direction());
@@ -242,133 +509,9 @@ impl Hittable for MovingSphere {
 
         Some(initial_box.surrounding(final_box))
     }
-}
-
-/// Bounding Volume Hierarchy
-pub struct BvhTree {
-    left: Arc<dyn Hittable>,
-    right: Arc<dyn Hittable>,
-    bound: Bound,
-}
 
-impl From<List> for BvhTree {
-    fn from(list: List) -> Self {
-        Self::from_time(list, 0., 1.)
-    }
-}
-
-impl BvhTree {
-    pub fn from_time(mut list: List, initial_time: f64, final_time: f64) -> Self {
-        let length = list.list.len(); // Must due to borrow checker
-        Self::new(&mut list.list, 0, length, initial_time, final_time)
-    }
-    pub fn new(
-        objects: &mut Vec<Box<dyn Hittable>>,
-        start: usize,
-        end: usize,
-        initial_time: f64,
-        final_time: f64,
-    ) -> Self {
-        let mut rng = rand::thread_rng();
-        let axis = rng.gen_range(0..3);
-        let comparator = match axis {
-            0 => Self::x_comparator,
-            1 => Self::y_comparator,
-            2 => Self::z_comparator,
-            _ => unreachable!("Random int in range [0, 2] must be in range"),
-        };
-        let object_span = end - start;
-        let (left, right) = match object_span {
-            1 => {
-                let first: Arc<dyn Hittable> = objects.remove(0).into();
-                (first.clone(), first)
-            }
-            2 => {
-                let first: Arc<dyn Hittable> = objects.remove(0).into();
-                let second: Arc<dyn Hittable> = objects.remove(0).into();
-                match comparator(&*first, &*second) {
-                    Ordering::Less => (first, second),
-                    _ => (second, first),
-                }
-            }
-            _ => {
-                objects.sort_by(|x, y| comparator(&**x, &**y));
-                let mid = start + object_span / 2;
-                let left: Arc<dyn Hittable> =
-                    Arc::new(Self::new(objects, start, mid, initial_time, final_time));
-                let right: Arc<dyn Hittable> =
-                    Arc::new(Self::new(objects, mid, end, initial_time, final_time));
-
-                (left, right)
-            }
-        };
-
-        let box_left = left
-            .bounding_box(initial_time, final_time)
-            .unwrap_or_else(|| {
-                eprintln!("No bounding box in BvhTree constructor");
-                Default::default()
-            });
-        let box_right = right
-            .bounding_box(initial_time, final_time)
-            .unwrap_or_else(|| {
-                eprintln!("No bounding box in BvhTree constructor");
-                Default::default()
-            });
-
-        let bound = box_left.surrounding(box_right);
-        Self { bound, left, right }
-    }
-
-    fn comparator(x: &dyn Hittable, y: &dyn Hittable, axis: usize) -> Ordering {
-        let box_x = x.bounding_box(0.0, 0.0).unwrap_or_else(|| {
-            eprintln!("No bounding box in BvhTree constructor");
-            Default::default()
-        });
-        let box_y = y.bounding_box(0.0, 0.0).unwrap_or_else(|| {
-            eprintln!("No bounding box in BvhTree constructor");
-            Default::default()
-        });
-        let x = box_x.min.at(axis);
-        let y = box_y.min.at(axis);
-        // Poor man https://doc.rust-lang.org/std/primitive.f64.html#method.total_cmp
-        // Nightly only so far
-        if x < y {
-            Ordering::Less
-        } else if x > y {
-            Ordering::Greater
-        } else {
-            Ordering::Equal
-        }
-    }
-    pub fn x_comparator(x: &dyn Hittable, y: &dyn Hittable) -> Ordering {
-        Self::comparator(x, y, 0)
-    }
-    pub fn y_comparator(x: &dyn Hittable, y: &dyn Hittable) -> Ordering {
-        Self::comparator(x, y, 1)
-    }
-    pub fn z_comparator(x: &dyn Hittable, y: &dyn Hittable) -> Ordering {
-        Self::comparator(x, y, 2)
-    }
-}
-
-impl Hittable for BvhTree {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        if !self.bound.hit(ray, t_min, t_max) {
-            return None;
-        }
-        let left_record = self.left.hit(ray, t_min, t_max);
-        let t = if let Some(record) = left_record {
-            record.t
-        } else {
-            t_max
-        };
-        let right_record = self.right.hit(ray, t_min, t);
-        right_record.or(left_record)
-    }
-
-    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
-        Some(self.bound)
+    fn is_convex(&self) -> bool {
+        true
     }
 }
 
@@ -387,23 +530,27 @@ pub trait Plane: Send + Sync {
 
     /// The axes of the plane.
     /// ```
-    /// let a = XY::axis();
+    /// use rttnw::math::hittable::PlaneCoordinates;
+    /// use rttnw::math::{Coordinate, Plane, Xy};
+    ///
+    /// let a = Xy::axes();
     /// let b = PlaneCoordinates {
     ///     axis0: Coordinate::X,
     ///     axis1: Coordinate::Y,
     ///     k:  Coordinate::Z,
     /// };
-    /// assert_eq!(a, b);
+    /// assert!(a == b);
+    /// ```
     fn axes() -> PlaneCoordinates;
 
     /// Create a rectangle in the plane. It's supposed to be used
     /// as a aid type inference when creating a new rectangle.
-    fn rectangle<M: Material>(
-        material: Arc<M>,
+    fn rectangle(
+        material: Arc<dyn Material>,
         p0: Range<f64>,
         p1: Range<f64>,
         k: f64,
-    ) -> Rectangle<M, Self>
+    ) -> Rectangle<Self>
     where
         Self: std::marker::Sized,
     {
@@ -411,11 +558,11 @@ pub trait Plane: Send + Sync {
     }
 
     /// Create 2 rectangles in the plane, with different `k`.
-    fn rectangles<M: Material>(
+    fn rectangles(
         p0: Vec3f<Position>,
         p1: Vec3f<Position>,
-        material: &Arc<M>,
-    ) -> (Rectangle<M, Self>, Rectangle<M, Self>)
+        material: &Arc<dyn Material>,
+    ) -> (Rectangle<Self>, Rectangle<Self>)
     where
         Self: std::marker::Sized,
     {
@@ -431,8 +578,8 @@ pub trait Plane: Send + Sync {
 // Also, Peter Shirley creates a different rectangle class for each plane,
 // making this solution quite similar.
 /// A rectangle in a plane P
-pub struct Rectangle<M: Material, P: Plane> {
-    pub material: Arc<M>,
+pub struct Rectangle<P: Plane> {
+    pub material: Arc<dyn Material>,
     pub p0: Range<f64>,
     pub p1: Range<f64>,
     // Must be non-zero, or else problems appear when creating the bounding box in the BVH.
@@ -487,8 +634,8 @@ impl Plane for Yz {
     }
 }
 
-impl<M: Material, P: Plane> Rectangle<M, P> {
-    pub fn new(material: Arc<M>, p0: Range<f64>, p1: Range<f64>, k: f64) -> Self {
+impl<P: Plane> Rectangle<P> {
+    pub fn new(material: Arc<dyn Material>, p0: Range<f64>, p1: Range<f64>, k: f64) -> Self {
         Self {
             material,
             p0,
@@ -499,8 +646,14 @@ impl<M: Material, P: Plane> Rectangle<M, P> {
     }
 }
 
-impl<M: Material, P: Plane> Hittable for Rectangle<M, P> {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+impl<P: Plane> Hittable for Rectangle<P> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
         let PlaneCoordinates { axis0, axis1, k } = P::axes();
         let t = (self.k - ray.origin().at(k)) / ray.direction().at(k);
         if t < t_min || t > t_max {
@@ -521,7 +674,7 @@ impl<M: Material, P: Plane> Hittable for Rectangle<M, P> {
             t,
             p,
             normal,
-            material: &*self.material,
+            material: self.material.as_ref(),
             u,
             v,
             front_face,
@@ -544,6 +697,42 @@ impl<M: Material, P: Plane> Hittable for Rectangle<M, P> {
         };
         Some(bound)
     }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+impl<P: Plane> LightShape for Rectangle<P> {
+    /// Uniform over the area of the rectangle.
+    fn pdf_value(&self, ray: Ray, sampler: &mut Sampler) -> f64 {
+        let record = match self.hit(ray, 0.001, f64::INFINITY, sampler) {
+            Some(record) => record,
+            None => return 0.0,
+        };
+        let PlaneCoordinates { k, .. } = P::axes();
+        let area = ((self.p0.end - self.p0.start) * (self.p1.end - self.p1.start)).abs();
+        let distance_squared = record.t.powi(2) * ray.direction().squared_length();
+        let cosine = (ray.direction().at(k) / ray.direction().magnitude()).abs();
+        distance_squared / (cosine * area)
+    }
+
+    fn random(&self, origin: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position> {
+        let PlaneCoordinates { axis0, axis1, k } = P::axes();
+        let point = Vec3f::default()
+            .with_dimension(
+                axis0,
+                self.p0.start + sampler.gen::<f64>() * (self.p0.end - self.p0.start),
+            )
+            .with_dimension(
+                axis1,
+                self.p1.start + sampler.gen::<f64>() * (self.p1.end - self.p1.start),
+            )
+            .with_dimension(k, self.k);
+        point - origin
+    }
 }
 
 pub struct Cube {
@@ -553,10 +742,7 @@ pub struct Cube {
 }
 
 impl Cube {
-    pub fn new<T>(box_min: Vec3f<Position>, box_max: Vec3f<Position>, material: Arc<T>) -> Self
-    where
-        T: 'static + Material,
-    {
+    pub fn new(box_min: Vec3f<Position>, box_max: Vec3f<Position>, material: Arc<dyn Material>) -> Self {
         let mut sides = List::with_capacity(6);
         let xy = Xy::rectangles(box_min, box_max, &material);
         sides.push(xy.0);
@@ -577,8 +763,21 @@ impl Cube {
 }
 
 impl Hittable for Cube {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        self.sides.hit(ray, t_min, t_max)
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let mut record = self.sides.hit(ray, t_min, t_max, sampler)?;
+        // Every side faces the positive end of its axis, so the sides at `box_min` face in
+        let axis = (0..3).find(|&axis| record.normal[axis] != 0.0)?;
+        let to_min = (record.p[axis] - self.box_min[axis]).abs();
+        if to_min < (record.p[axis] - self.box_max[axis]).abs() {
+            record.front_face = !record.front_face;
+        }
+        Some(record)
     }
 
     #[allow(unused_variables)]
@@ -589,6 +788,14 @@ impl Hittable for Cube {
         };
         Some(bound)
     }
+
+    fn is_convex(&self) -> bool {
+        true
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        self.sides.lights(lights);
+    }
 }
 
 pub struct Translate {
@@ -596,14 +803,20 @@ pub struct Translate {
     pub offset: Vec3f<Position>,
 }
 impl Hittable for Translate {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
         let moved_ray = Ray {
             a: ray.origin() - self.offset,
             b: ray.direction(),
-            time: ray.time,
+            ..ray
         };
 
-        if let Some(record) = self.item.hit(moved_ray, t_min, t_max) {
+        if let Some(record) = self.item.hit(moved_ray, t_min, t_max, sampler) {
             let (normal, front_face) = HitRecord::face_normal(moved_ray, record.normal);
             Some(HitRecord {
                 normal,
@@ -617,15 +830,42 @@ impl Hittable for Translate {
     }
 
     fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
-        if let Some(bound) = self.item.bounding_box(initial_time, final_time) {
-            Some(Bound {
+        self.item
+            .bounding_box(initial_time, final_time)
+            .map(|bound| Bound {
                 min: bound.min + self.offset,
                 max: bound.max + self.offset,
             })
-        } else {
-            None
+    }
+
+    fn is_convex(&self) -> bool {
+        self.item.is_convex()
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        let start = lights.len();
+        self.item.lights(lights);
+        for light in &mut lights[start..] {
+            light.push_transform(self);
+        }
+    }
+}
+
+impl LightTransform for Translate {
+    fn ray_to_object(&self, ray: Ray) -> Ray {
+        Ray {
+            a: ray.origin() - self.offset,
+            ..ray
         }
     }
+
+    fn point_to_object(&self, point: Vec3f<Position>) -> Vec3f<Position> {
+        point - self.offset
+    }
+
+    fn direction_to_world(&self, direction: Vec3f<Position>) -> Vec3f<Position> {
+        direction
+    }
 }
 
 pub struct YRotate {
@@ -680,10 +920,32 @@ impl YRotate {
             cos_theta,
         }
     }
+
+    fn to_object(&self, v: Vec3f<Position>) -> Vec3f<Position> {
+        Vec3f::new(
+            self.cos_theta * v.x() - self.sin_theta * v.z(),
+            v.y(),
+            self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
+
+    fn to_world(&self, v: Vec3f<Position>) -> Vec3f<Position> {
+        Vec3f::new(
+            self.cos_theta * v.x() + self.sin_theta * v.z(),
+            v.y(),
+            -self.sin_theta * v.x() + self.cos_theta * v.z(),
+        )
+    }
 }
 
 impl Hittable for YRotate {
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
         let mut origin = ray.origin();
         let mut direction = ray.direction();
         origin[0] = self.cos_theta * ray.origin()[0] - self.sin_theta * ray.origin()[2];
@@ -693,10 +955,10 @@ impl Hittable for YRotate {
         let ray = Ray {
             a: origin,
             b: direction,
-            time: ray.time,
+            ..ray
         };
 
-        if let Some(mut record) = self.item.hit(ray, t_min, t_max) {
+        if let Some(mut record) = self.item.hit(ray, t_min, t_max, sampler) {
             record.p[0] = self.cos_theta * record.p[0] + self.sin_theta * record.p[2];
             record.p[2] = -self.sin_theta * record.p[0] + self.cos_theta * record.p[2];
             record.normal[0] =
@@ -719,83 +981,136 @@ impl Hittable for YRotate {
     fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
         Some(self.bound)
     }
+
+    fn is_convex(&self) -> bool {
+        self.item.is_convex()
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        let start = lights.len();
+        self.item.lights(lights);
+        for light in &mut lights[start..] {
+            light.push_transform(self);
+        }
+    }
 }
 
+impl LightTransform for YRotate {
+    fn ray_to_object(&self, ray: Ray) -> Ray {
+        Ray {
+            a: self.to_object(ray.origin()),
+            b: self.to_object(ray.direction()),
+            ..ray
+        }
+    }
+
+    fn point_to_object(&self, point: Vec3f<Position>) -> Vec3f<Position> {
+        self.to_object(point)
+    }
+
+    fn direction_to_world(&self, direction: Vec3f<Position>) -> Vec3f<Position> {
+        self.to_world(direction)
+    }
+}
+
+/// A volume of uniform density inside a closed `boundary`, like smoke or fog.
 pub struct ConstantMedium {
     boundary: Arc<dyn Hittable>,
-    phase_function: Isotropic,
+    phase_function: Arc<dyn Material>,
     neg_inv_density: f64,
 }
 
 impl ConstantMedium {
-    pub fn new(boundary: Arc<dyn Hittable>, density: f64, phase_function: Arc<dyn Texture>) -> Self {
+    /// A medium that scatters equally in every direction.
+    pub fn new(boundary: Arc<dyn Hittable>, density: f64, albedo: Arc<dyn Texture>) -> Self {
+        Self::with_phase_function(boundary, density, Arc::new(Isotropic { albedo }))
+    }
+
+    /// A medium that scatters with the directions of `phase_function`,
+    /// like a [`HenyeyGreenstein`](super::HenyeyGreenstein).
+    pub fn with_phase_function(
+        boundary: Arc<dyn Hittable>,
+        density: f64,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
         Self {
-            boundary, phase_function: Isotropic { albedo: phase_function }, neg_inv_density: -1./density
+            boundary,
+            phase_function,
+            neg_inv_density: -1. / density,
         }
     }
 }
 
 impl Hittable for ConstantMedium {
-    // Current implementation assumes the shape is convex
-    fn hit(&self, ray: Ray, t_min: f64, t_max: f64) -> Option<HitRecord> {
-        let mut rng = rand::thread_rng();
-        // Print occasional samples when debugging. To enable, set enableDebug true.
-        let _enable_debug = false;
-        let debugging = _enable_debug && rng.gen::<f64>() < 0.00001;
-        if let Some(mut record1) =
-            self.boundary
-                .hit(ray, f64::NEG_INFINITY, f64::INFINITY)
-        {
-            if let Some(mut record2) =
-                self.boundary
-                    .hit(ray, record1.t + 0.0001, f64::INFINITY)
-            {
-                if debugging {
-                    eprintln!("\nt_min = {}, t_max = {}", record1.t, record2.t);
-                }
-                record1.t = record1.t.max(t_min);
-                record2.t = record2.t.min(t_max);
-                if record1.t >= record2.t {
-                    return None;
-                }
-                record1.t = record1.t.max(0.);
-
-                let ray_length = ray.direction().magnitude();
-                let distance_inside_boundary = (record2.t - record1.t) * ray_length;
-                let hit_distance = self.neg_inv_density * rng.gen::<f64>().ln();
-
-                if hit_distance > distance_inside_boundary {
-                    return None;
-                }
-                let t = record1.t + hit_distance / ray_length;
-                let p = ray.point_at_parameter(t);
-                if debugging {
-                    eprintln!(
-                        "hit_distance = {}\nrec.t = {}\nrec.p {}",
-                        hit_distance, t, p
-                    );
-                }
-                let normal = Vec3f::new(1., 0., 0.); // Arbitrary
-                let front_face = true; // Arbitrary
-
-                Some(HitRecord {
-                    t,
-                    p,
-                    normal,
-                    front_face,
-                    material: &self.phase_function,
-                    u: 0.0,
-                    v: 0.0,
-                })
-            } else {
-                None
+    /// Works with any closed boundary, and with rays that start inside it.
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let ray_length = ray.direction().magnitude();
+        // Distance to the collision, inside the medium
+        let mut hit_distance = self.neg_inv_density * sampler.gen::<f64>().ln();
+        for (start, end) in inside_intervals(self.boundary.as_ref(), ray, t_min, t_max, sampler) {
+            let distance_inside_boundary = (end - start) * ray_length;
+            if hit_distance > distance_inside_boundary {
+                hit_distance -= distance_inside_boundary;
+                continue;
             }
-        } else {
-            None
+            let t = start + hit_distance / ray_length;
+            return Some(HitRecord {
+                t,
+                p: ray.point_at_parameter(t),
+                normal: Vec3f::new(1., 0., 0.), // Arbitrary
+                front_face: true,               // Arbitrary
+                material: self.phase_function.as_ref(),
+                u: 0.0,
+                v: 0.0,
+            });
         }
+        None
     }
 
     fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
         self.boundary.bounding_box(initial_time, final_time)
     }
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::sphere;
+    use crate::math::{Color, DiffuseLight};
+
+    fn light(center: Vec3f<Position>) -> Sphere {
+        Sphere {
+            material: DiffuseLight::arc(Vec3f::<Color>::repeat(1.0)),
+            ..sphere(center, 1.0)
+        }
+    }
+
+    #[test]
+    fn wrapped_lights_are_sampled_in_world_space() {
+        let wrapped = light(Vec3f::repeat(0.0))
+            .translate(Vec3f::new(0.0, 0.0, 5.0))
+            .rotate_y(90.0);
+        let world = light(Vec3f::new(5.0, 0.0, 0.0));
+        let (wrapped_lights, world_lights) = (Lights::new(&wrapped), Lights::new(&world));
+        let mut sampler = Sampler::new(0);
+        let origin = Vec3f::new(0.0, 0.5, 0.0);
+        for _ in 0..100 {
+            let direction = wrapped_lights.random(origin, &mut sampler);
+            let ray = Ray {
+                a: origin,
+                b: direction,
+                ..Ray::default()
+            };
+            assert!(world.hit(ray, 0.001, f64::INFINITY, &mut sampler).is_some());
+            let expected = world_lights.pdf_value(ray, &mut sampler);
+            let pdf = wrapped_lights.pdf_value(ray, &mut sampler);
+            assert!((pdf - expected).abs() < 1e-9 * expected);
+        }
+    }
+}
@@ -1,16 +1,85 @@
-use crate::math::{Color, HitRecord, Position, Ray, Texture, Vec3f};
+use crate::math::{Color, HitRecord, Onb, Position, Ray, Sampler, Texture, Vec3f};
 use rand::Rng;
 use std::sync::Arc;
 
+/// A direction in which a material scatters a ray.
+#[derive(Copy, Clone)]
+pub struct BsdfSample {
+    pub direction: Vec3f<Position>,
+    /// BSDF times the cosine between `direction` and the normal.
+    /// For specular samples, the attenuation along the single possible direction.
+    pub bsdf: Vec3f<Color>,
+    /// Density of `direction`, with respect to solid angle. 1 for specular samples.
+    pub pdf: f64,
+    /// The direction is the only one possible (or is not described by `eval` and
+    /// `scattering_pdf`), so it can't be combined with other sampling strategies.
+    pub specular: bool,
+}
+
+impl BsdfSample {
+    /// Contribution of the sample to the estimate of the scattered light.
+    pub fn weight(&self) -> Vec3f<Color> {
+        self.bsdf / self.pdf
+    }
+}
+
 /// Different materials scatter light differently
 pub trait Material: Send + Sync {
-    fn scatter(&self, ray: Ray, record: HitRecord) -> Option<(Vec3f<Color>, Ray)>;
+    /// Samples the direction in which `ray` is scattered. `None` if the ray is absorbed.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample>;
+
+    /// BSDF times the cosine between `direction` and the normal, for the light that
+    /// arrives from `direction` and leaves along `ray`. Black for specular materials.
+    fn eval(&self, _: Ray, _: HitRecord, _: Vec3f<Position>) -> Vec3f<Color> {
+        Vec3f::repeat(0.)
+    }
+
+    /// Density, with respect to solid angle, with which `sample` picks `direction`.
+    /// 0 for specular materials.
+    fn scattering_pdf(&self, _: Ray, _: HitRecord, _: Vec3f<Position>) -> f64 {
+        0.0
+    }
+
+    /// The attenuation and the scattered ray of a sample.
+    fn scatter(
+        &self,
+        ray: Ray,
+        record: HitRecord,
+        sampler: &mut Sampler,
+    ) -> Option<(Vec3f<Color>, Ray)> {
+        let sample = self.sample(ray, record, sampler)?;
+        let scattered = Ray {
+            a: record.p,
+            b: sample.direction,
+            ..ray
+        };
+        Some((sample.weight(), scattered))
+    }
 
     #[allow(unused_variables)]
     fn emitted(&self, u: f64, v: f64, point: Vec3f<Position>) -> Vec3f<Color> {
         Vec3f::repeat(0.)
     }
 
+    /// Whether `emitted` can be other than black.
+    fn is_emissive(&self) -> bool {
+        false
+    }
+
+    /// For the surfaces of closed objects that rays can go through, the fraction of each
+    /// color absorbed per unit of distance inside. The renderer keeps track of the objects
+    /// a path has entered, by their material, and attenuates it by the innermost. `None` for
+    /// other materials, and for clear ones that absorb nothing.
+    fn absorption(&self) -> Option<Vec3f<Color>> {
+        None
+    }
+
+    /// Whether the direction of the scattered ray depends on the wavelength of `ray`.
+    /// A spectral path can then only follow its hero wavelength.
+    fn is_dispersive(&self) -> bool {
+        false
+    }
+
     fn arc(self) -> Arc<Self> where Self: Sized {
         Arc::new(self)
     }
@@ -22,7 +91,7 @@ pub trait Material: Send + Sync {
 
 /// Solid material
 #[derive(Clone)]
-pub struct Lambertian<T: Texture> {
+pub struct Lambertian<T: Texture + ?Sized> {
     albedo: Arc<T>,
 }
 
@@ -38,7 +107,7 @@ where
 }
 
 #[allow(dead_code)]
-impl<T: Texture> Lambertian<T> {
+impl<T: Texture + ?Sized> Lambertian<T> {
     pub fn new<A>(albedo: A) -> Self
     where
         T: 'static,
@@ -49,7 +118,7 @@ impl<T: Texture> Lambertian<T> {
     }
     /// Create a Lambertian wrapped in a box
     /// TODO: Is there a way to help the compiler infer the type T? Example:
-    /// ```
+    /// ```ignore
     /// pub fn two_spheres() -> List {
     ///     let mut world = List::new();
     ///     let checker = Arc::new(CheckerTexture {
@@ -86,16 +155,33 @@ impl<T: Texture> Lambertian<T> {
     }
 }
 
-impl<T: Texture> Material for Lambertian<T> {
-    fn scatter(&self, ray: Ray, record: HitRecord) -> Option<(Vec3f<Color>, Ray)> {
-        let target = record.p + record.normal + Vec3f::random_in_unit_space();
-        let scattered = Ray {
-            a: record.p,
-            b: target - record.p,
-            time: ray.time,
-        };
-        let attenuation = self.albedo.value(record.u, record.v, record.p);
-        Some((attenuation, scattered))
+impl<T: Texture + ?Sized> Material for Lambertian<T> {
+    /// Cosine-weighted direction around the normal.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let mut direction = record.normal + Vec3f::random_unit_vector(sampler);
+        if direction.near_zero() {
+            direction = record.normal;
+        }
+        let pdf = self.scattering_pdf(ray, record, direction);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            bsdf: self.eval(ray, record, direction),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> Vec3f<Color> {
+        self.albedo.value(record.u, record.v, record.p)
+            * self.scattering_pdf(ray, record, direction)
+    }
+
+    fn scattering_pdf(&self, _: Ray, record: HitRecord, direction: Vec3f<Position>) -> f64 {
+        let cosine = record.normal.dot(direction.unit());
+        cosine.max(0.0) / std::f64::consts::PI
     }
 }
 
@@ -132,42 +218,126 @@ impl Metal {
 }
 
 impl Material for Metal {
-    fn scatter(&self, ray: Ray, record: HitRecord) -> Option<(Vec3f<Color>, Ray)> {
+    /// The fuzzy reflection has no closed-form density, so it's treated as specular.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
         let reflected = ray.direction().unit().reflect(record.normal);
-        let scattered = Ray {
-            a: record.p,
-            b: reflected + self.fuzz * Vec3f::random_in_unit_space(),
-            time: ray.time,
-        };
-        let attenuation = self.albedo;
-        if scattered.direction().dot(record.normal) > 0.0 {
-            Some((attenuation, scattered))
+        let direction = reflected + self.fuzz * Vec3f::random_in_unit_space(sampler);
+        if direction.dot(record.normal) > 0.0 {
+            Some(BsdfSample {
+                direction,
+                bsdf: self.albedo,
+                pdf: 1.0,
+                specular: true,
+            })
         } else {
             None
         }
     }
 }
 
+/// Refraction index of a material at every wavelength, in nanometers.
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub enum RefractionIndex {
+    Constant(f64),
+    /// `a + b / λ²`, with the wavelength in micrometers.
+    Cauchy {
+        a: f64,
+        b: f64,
+    },
+    /// `n² = 1 + Σ bᵢλ² / (λ² - cᵢ)`, with the wavelength in micrometers.
+    Sellmeier {
+        b: [f64; 3],
+        c: [f64; 3],
+    },
+}
+
+impl RefractionIndex {
+    /// Wavelength at which the index of RGB rendering is taken: the yellow helium line
+    /// that glass catalogs give indices at.
+    pub const D_LINE: f64 = 587.6;
+
+    /// Borosilicate crown glass, the most common optical glass.
+    pub const BK7: Self = Self::Sellmeier {
+        b: [1.039_612_12, 0.231_792_344, 1.010_469_45],
+        c: [0.006_000_698_67, 0.020_017_914_4, 103.560_653],
+    };
+    pub const FUSED_SILICA: Self = Self::Sellmeier {
+        b: [0.696_166_3, 0.407_942_6, 0.897_479_4],
+        c: [0.004_679_148_26, 0.013_512_063_1, 97.934_002_5],
+    };
+    /// Diamond, known for its fire.
+    pub const DIAMOND: Self = Self::Sellmeier {
+        b: [0.3306, 4.3356, 0.0],
+        c: [0.030_625, 0.011_236, 0.0],
+    };
+
+    /// The index at `wavelength`, or at [`Self::D_LINE`] if there is no wavelength.
+    pub fn at(&self, wavelength: Option<f64>) -> f64 {
+        let micrometers = wavelength.unwrap_or(Self::D_LINE) / 1000.0;
+        let squared = micrometers * micrometers;
+        match *self {
+            Self::Constant(index) => index,
+            Self::Cauchy { a, b } => a + b / squared,
+            Self::Sellmeier { b, c } => {
+                let terms = b.iter().zip(&c).map(|(b, c)| b * squared / (squared - c));
+                (1.0 + terms.sum::<f64>()).sqrt()
+            }
+        }
+    }
+
+    pub fn is_dispersive(&self) -> bool {
+        !matches!(self, Self::Constant(_))
+    }
+}
+
+impl From<f64> for RefractionIndex {
+    fn from(index: f64) -> Self {
+        Self::Constant(index)
+    }
+}
+
 /// Glass material
 #[derive(Copy, Clone)]
 pub struct Dielectric {
-    refraction_index: f64,
+    refraction_index: RefractionIndex,
+    absorption: Vec3f<Color>,
 }
 
 impl Dielectric {
     #[allow(dead_code)]
     pub fn new(refraction_index: f64) -> Self {
-        Self { refraction_index }
+        Self::dispersive(refraction_index.into())
+    }
+
+    /// Glass that bends every wavelength differently, splitting white light into colors
+    /// when rendering spectrally.
+    pub fn dispersive(refraction_index: RefractionIndex) -> Self {
+        Self {
+            refraction_index,
+            absorption: Vec3f::repeat(0.0),
+        }
     }
 
     #[allow(dead_code)]
     pub fn arc(refraction_index: f64) -> Arc<Self> {
-        Arc::new(Self { refraction_index })
+        Arc::new(Self::new(refraction_index))
     }
 
     #[allow(dead_code)]
     pub fn boxed(refraction_index: f64) -> Box<Self> {
-        Box::new(Self { refraction_index })
+        Box::new(Self::new(refraction_index))
+    }
+
+    /// Colored glass or liquid, that absorbs `absorption` of each color per unit of distance,
+    /// by the Beer-Lambert law.
+    pub fn with_absorption(self, absorption: Vec3f<Color>) -> Self {
+        Self { absorption, ..self }
+    }
+
+    /// Colored glass through which `distance` units of travel leave `color` of the light.
+    pub fn tinted(refraction_index: f64, color: Vec3f<Color>, distance: f64) -> Self {
+        let absorption = color.map(|x| -x.max(1e-6).ln() / distance);
+        Self::new(refraction_index).with_absorption(absorption)
     }
 
     fn schlick(cosine: f64, refraction_index: f64) -> f64 {
@@ -176,36 +346,55 @@ impl Dielectric {
     }
 }
 
+/// `absorption` if any color is absorbed, so clear glass doesn't have to be tracked.
+pub(super) fn absorbing(absorption: Vec3f<Color>) -> Option<Vec3f<Color>> {
+    if absorption == Vec3f::repeat(0.0) {
+        None
+    } else {
+        Some(absorption)
+    }
+}
+
 impl Material for Dielectric {
-    fn scatter(&self, ray: Ray, record: HitRecord) -> Option<(Vec3f<Color>, Ray)> {
-        let mut rng = rand::thread_rng();
-        // Attenuation is 1 because glass absorbs nothing
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        // Attenuation is 1 because the surface absorbs nothing, the inside absorbs along the path
         let attenuation = Vec3f::new(1.0, 1.0, 1.0);
-        let refraction_ratio = if record.front_face { 1.0 / self.refraction_index } else { self.refraction_index };
+        let refraction_index = self.refraction_index.at(ray.wavelength);
+        let refraction_ratio = if record.front_face {
+            1.0 / refraction_index
+        } else {
+            refraction_index
+        };
         let unit_direction = ray.direction().unit();
         let cos_theta = (-unit_direction).dot(record.normal).min(1.);
         let sin_theta = f64::sqrt(1.0 - cos_theta.powi(2));
         let cannot_refract = refraction_ratio * sin_theta > 1.0;
-        let direction = if cannot_refract || Self::schlick(cos_theta, refraction_ratio) > rng.gen() {
-            unit_direction.reflect(record.normal)
-        } else {
-            unit_direction.refract(record.normal, refraction_ratio)
-        };
-        let scattered = Ray {
-            a: record.p,
-            b: direction,
-            time: ray.time,
-        };
-        Some((
-            attenuation,
-            scattered
-        ))
+        let direction =
+            if cannot_refract || Self::schlick(cos_theta, refraction_ratio) > sampler.gen() {
+                unit_direction.reflect(record.normal)
+            } else {
+                unit_direction.refract(record.normal, refraction_ratio)
+            };
+        Some(BsdfSample {
+            direction,
+            bsdf: attenuation,
+            pdf: 1.0,
+            specular: true,
+        })
+    }
+
+    fn absorption(&self) -> Option<Vec3f<Color>> {
+        absorbing(self.absorption)
+    }
+
+    fn is_dispersive(&self) -> bool {
+        self.refraction_index.is_dispersive()
     }
 }
 
 #[derive(Clone)]
 pub struct DiffuseLight {
-    emit: Arc<dyn Texture>,
+    pub emit: Arc<dyn Texture>,
 }
 
 impl<T> From<T> for DiffuseLight
@@ -240,13 +429,17 @@ impl DiffuseLight {
 }
 
 impl Material for DiffuseLight {
-    fn scatter(&self, _: Ray, _: HitRecord) -> Option<(Vec3f<Color>, Ray)> {
+    fn sample(&self, _: Ray, _: HitRecord, _: &mut Sampler) -> Option<BsdfSample> {
         None
     }
 
     fn emitted(&self, u: f64, v: f64, point: Vec3f<Position>) -> Vec3f<Color> {
         self.emit.value(u, v, point)
     }
+
+    fn is_emissive(&self) -> bool {
+        true
+    }
 }
 
 pub struct Isotropic {
@@ -254,13 +447,217 @@ pub struct Isotropic {
 }
 
 impl Material for Isotropic {
-    fn scatter(&self, ray: Ray, record: HitRecord) -> Option<(Vec3f<Color>, Ray)> {
-        let scattered = Ray {
-            a: record.p,
-            b: Vec3f::random_in_unit_space(),
-            time: ray.time,
+    /// Uniform over the sphere of directions.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let direction = Vec3f::random_unit_vector(sampler);
+        Some(BsdfSample {
+            direction,
+            bsdf: self.eval(ray, record, direction),
+            pdf: self.scattering_pdf(ray, record, direction),
+            specular: false,
+        })
+    }
+
+    /// The phase function. There is no cosine term inside a medium.
+    fn eval(&self, _: Ray, record: HitRecord, _: Vec3f<Position>) -> Vec3f<Color> {
+        self.albedo.value(record.u, record.v, record.p) / (4.0 * std::f64::consts::PI)
+    }
+
+    fn scattering_pdf(&self, _: Ray, _: HitRecord, _: Vec3f<Position>) -> f64 {
+        1.0 / (4.0 * std::f64::consts::PI)
+    }
+}
+
+/// Henyey–Greenstein phase function: the density of scattering `cosine` away from
+/// the direction of the ray, with asymmetry `g`.
+fn henyey_greenstein(cosine: f64, g: f64) -> f64 {
+    let denominator = 1.0 + g * g - 2.0 * g * cosine;
+    (1.0 - g * g) / (4.0 * std::f64::consts::PI * denominator * denominator.sqrt())
+}
+
+/// A direction scattered from `direction` with the density of [`henyey_greenstein`].
+fn sample_henyey_greenstein(
+    direction: Vec3f<Position>,
+    g: f64,
+    sampler: &mut Sampler,
+) -> Vec3f<Position> {
+    let (r1, r2) = (sampler.gen::<f64>(), sampler.gen::<f64>());
+    let cosine = if g.abs() < 1e-3 {
+        1.0 - 2.0 * r1
+    } else {
+        let square = (1.0 - g * g) / (1.0 - g + 2.0 * g * r1);
+        ((1.0 + g * g - square * square) / (2.0 * g)).clamp(-1.0, 1.0)
+    };
+    let sine = (1.0 - cosine * cosine).max(0.0).sqrt();
+    let phi = 2.0 * std::f64::consts::PI * r2;
+    Onb::from_w(direction).local(phi.cos() * sine, phi.sin() * sine, cosine)
+}
+
+/// Anisotropic phase function. Positive `g` scatters forward, like fog and clouds,
+/// negative `g` scatters back and 0 is [`Isotropic`].
+pub struct HenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    g: f64,
+}
+
+impl HenyeyGreenstein {
+    /// `g` is clamped inside `-1..1`, where the phase function stops being a delta.
+    pub fn new(albedo: Arc<dyn Texture>, g: f64) -> Self {
+        Self {
+            albedo,
+            g: g.clamp(-0.999, 0.999),
+        }
+    }
+}
+
+impl Material for HenyeyGreenstein {
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let direction = sample_henyey_greenstein(ray.direction(), self.g, sampler);
+        Some(BsdfSample {
+            direction,
+            bsdf: self.eval(ray, record, direction),
+            pdf: self.scattering_pdf(ray, record, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> Vec3f<Color> {
+        self.albedo.value(record.u, record.v, record.p)
+            * self.scattering_pdf(ray, record, direction)
+    }
+
+    fn scattering_pdf(&self, ray: Ray, _: HitRecord, direction: Vec3f<Position>) -> f64 {
+        let cosine = ray.direction().unit().dot(direction.unit());
+        henyey_greenstein(cosine, self.g)
+    }
+}
+
+/// Mix of a forward and a backward [`HenyeyGreenstein`] lobe, like the strong forward
+/// peak and the faint back glow of clouds. `weight` is the share of the forward lobe.
+pub struct DoubleHenyeyGreenstein {
+    pub albedo: Arc<dyn Texture>,
+    forward: f64,
+    backward: f64,
+    weight: f64,
+}
+
+impl DoubleHenyeyGreenstein {
+    pub fn new(albedo: Arc<dyn Texture>, forward: f64, backward: f64, weight: f64) -> Self {
+        Self {
+            albedo,
+            forward: forward.clamp(-0.999, 0.999),
+            backward: backward.clamp(-0.999, 0.999),
+            weight: weight.clamp(0.0, 1.0),
+        }
+    }
+}
+
+impl Material for DoubleHenyeyGreenstein {
+    /// Picks a lobe by its weight, so the density is the mix of both.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let g = if sampler.gen::<f64>() < self.weight {
+            self.forward
+        } else {
+            self.backward
         };
-        let attenuation = self.albedo.value(record.u, record.v, record.p);
-        Some((attenuation, scattered))
+        let direction = sample_henyey_greenstein(ray.direction(), g, sampler);
+        Some(BsdfSample {
+            direction,
+            bsdf: self.eval(ray, record, direction),
+            pdf: self.scattering_pdf(ray, record, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> Vec3f<Color> {
+        self.albedo.value(record.u, record.v, record.p)
+            * self.scattering_pdf(ray, record, direction)
+    }
+
+    fn scattering_pdf(&self, ray: Ray, _: HitRecord, direction: Vec3f<Position>) -> f64 {
+        let cosine = ray.direction().unit().dot(direction.unit());
+        self.weight * henyey_greenstein(cosine, self.forward)
+            + (1.0 - self.weight) * henyey_greenstein(cosine, self.backward)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{above, assert_samples_match_eval, surface_hit};
+
+    #[test]
+    fn samples_match_eval() {
+        let albedo = Vec3f::<Color>::new(0.2, 0.5, 0.8);
+        let texture = Arc::new(albedo);
+        assert_samples_match_eval(&Lambertian::new(albedo));
+        assert_samples_match_eval(&Isotropic {
+            albedo: texture.clone(),
+        });
+        assert_samples_match_eval(&HenyeyGreenstein::new(texture.clone(), 0.6));
+        assert_samples_match_eval(&DoubleHenyeyGreenstein::new(texture, 0.8, -0.3, 0.7));
     }
-}
\ No newline at end of file
+
+    #[test]
+    fn specular_samples_are_flagged() {
+        let albedo = Vec3f::<Color>::new(0.2, 0.5, 0.8);
+        let materials: [&dyn Material; 4] = [
+            &Metal::new(albedo, 0.0),
+            &Metal::new(albedo, 0.5),
+            &Dielectric::new(1.5),
+            &Dielectric::dispersive(RefractionIndex::BK7),
+        ];
+        let mut sampler = Sampler::new(0);
+        for material in materials {
+            for _ in 0..100 {
+                let (ray, record) = surface_hit(material, above(&mut sampler));
+                let sample = match material.sample(ray, record, &mut sampler) {
+                    Some(sample) => sample,
+                    None => continue,
+                };
+                assert!(sample.specular && sample.pdf == 1.0);
+                // Specular directions can't be evaluated or found by other sampling strategies
+                assert!(material.eval(ray, record, sample.direction) == Vec3f::repeat(0.0));
+                assert_eq!(material.scattering_pdf(ray, record, sample.direction), 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn glass_indices_match_the_catalogs() {
+        let bk7 = RefractionIndex::BK7;
+        assert!((bk7.at(Some(587.6)) - 1.5168).abs() < 1e-4);
+        assert_eq!(bk7.at(None), bk7.at(Some(RefractionIndex::D_LINE)));
+        // Blue light is bent more than red
+        assert!(bk7.at(Some(486.1)) > bk7.at(Some(656.3)));
+        assert!((RefractionIndex::FUSED_SILICA.at(None) - 1.4585).abs() < 1e-4);
+    }
+
+    #[test]
+    fn henyey_greenstein_is_a_density() {
+        let steps = 100_000;
+        // Over the sphere of directions, by rings of equal cosine
+        let width = 2.0 / steps as f64;
+        let ring = 2.0 * std::f64::consts::PI * width;
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let integral: f64 = (0..steps)
+                .map(|i| henyey_greenstein(-1.0 + (i as f64 + 0.5) * width, g) * ring)
+                .sum();
+            assert!((integral - 1.0).abs() < 1e-3, "g = {}: {}", g, integral);
+        }
+    }
+
+    #[test]
+    fn henyey_greenstein_samples_have_a_mean_cosine_of_g() {
+        let direction = Vec3f::new(0.0, 0.0, 2.0);
+        let mut sampler = Sampler::new(0);
+        let samples = 100_000;
+        for g in [-0.7, 0.0, 0.3, 0.9] {
+            let mean = (0..samples)
+                .map(|_| sample_henyey_greenstein(direction, g, &mut sampler).z())
+                .sum::<f64>()
+                / samples as f64;
+            assert!((mean - g).abs() < 1e-2, "g = {}: {}", g, mean);
+        }
+    }
+}
@@ -0,0 +1,327 @@
+use super::{Position, Vec3f};
+
+/// A 4x4 matrix of an affine transform, applied to column vectors.
+/// Angles are in degrees, like in [`YRotate`](super::YRotate).
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct Matrix4 {
+    /// Rows of the matrix.
+    pub m: [[f64; 4]; 4],
+}
+
+impl Default for Matrix4 {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Matrix4 {
+    pub const IDENTITY: Self = Self {
+        m: [
+            [1.0, 0.0, 0.0, 0.0],
+            [0.0, 1.0, 0.0, 0.0],
+            [0.0, 0.0, 1.0, 0.0],
+            [0.0, 0.0, 0.0, 1.0],
+        ],
+    };
+
+    /// The affine transform `p -> linear * p + offset`, `linear` given by rows.
+    pub fn affine(linear: [[f64; 3]; 3], offset: Vec3f<Position>) -> Self {
+        let mut m = Self::IDENTITY.m;
+        for (row, linear) in m.iter_mut().zip(&linear) {
+            row[..3].copy_from_slice(linear);
+        }
+        m[0][3] = offset.x();
+        m[1][3] = offset.y();
+        m[2][3] = offset.z();
+        Self { m }
+    }
+
+    pub fn translation(offset: Vec3f<Position>) -> Self {
+        Self::affine([[1.0, 0.0, 0.0], [0.0, 1.0, 0.0], [0.0, 0.0, 1.0]], offset)
+    }
+
+    pub fn scaling(factor: Vec3f<Position>) -> Self {
+        Self::affine(
+            [
+                [factor.x(), 0.0, 0.0],
+                [0.0, factor.y(), 0.0],
+                [0.0, 0.0, factor.z()],
+            ],
+            Vec3f::default(),
+        )
+    }
+
+    pub fn rotation_x(angle: f64) -> Self {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Self::affine(
+            [[1.0, 0.0, 0.0], [0.0, cos, -sin], [0.0, sin, cos]],
+            Vec3f::default(),
+        )
+    }
+
+    pub fn rotation_y(angle: f64) -> Self {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Self::affine(
+            [[cos, 0.0, sin], [0.0, 1.0, 0.0], [-sin, 0.0, cos]],
+            Vec3f::default(),
+        )
+    }
+
+    pub fn rotation_z(angle: f64) -> Self {
+        let (sin, cos) = angle.to_radians().sin_cos();
+        Self::affine(
+            [[cos, -sin, 0.0], [sin, cos, 0.0], [0.0, 0.0, 1.0]],
+            Vec3f::default(),
+        )
+    }
+
+    /// Counterclockwise rotation around `axis`, looking from its tip.
+    pub fn rotation(axis: Vec3f<Position>, angle: f64) -> Self {
+        let a = axis.unit();
+        let (sin, cos) = angle.to_radians().sin_cos();
+        let t = 1.0 - cos;
+        let (x, y, z) = (a.x(), a.y(), a.z());
+        Self::affine(
+            [
+                [t * x * x + cos, t * x * y - sin * z, t * x * z + sin * y],
+                [t * x * y + sin * z, t * y * y + cos, t * y * z - sin * x],
+                [t * x * z - sin * y, t * y * z + sin * x, t * z * z + cos],
+            ],
+            Vec3f::default(),
+        )
+    }
+
+    /// Places an object at `from` with its Z axis pointing to `to`,
+    /// and its Y axis as close to `view_up` as possible.
+    pub fn look_at(from: Vec3f<Position>, to: Vec3f<Position>, view_up: Vec3f<Position>) -> Self {
+        let z = (to - from).unit();
+        let x = view_up.cross(z).unit();
+        let y = z.cross(x);
+        Self::affine(
+            [
+                [x.x(), y.x(), z.x()],
+                [x.y(), y.y(), z.y()],
+                [x.z(), y.z(), z.z()],
+            ],
+            from,
+        )
+    }
+
+    pub fn transpose(&self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = self.m[j][i];
+            }
+        }
+        Self { m }
+    }
+
+    /// Gauss-Jordan elimination with partial pivoting. `None` if the matrix is singular.
+    pub fn inverse(&self) -> Option<Self> {
+        let mut a = self.m;
+        let mut inverse = Self::IDENTITY.m;
+        for column in 0..4 {
+            let pivot = (column..4)
+                .max_by(|&i, &j| a[i][column].abs().total_cmp(&a[j][column].abs()))
+                .unwrap_or(column);
+            if a[pivot][column].abs() < 1e-12 || a[pivot][column].is_nan() {
+                return None;
+            }
+            a.swap(column, pivot);
+            inverse.swap(column, pivot);
+            let scale = 1.0 / a[column][column];
+            for j in 0..4 {
+                a[column][j] *= scale;
+                inverse[column][j] *= scale;
+            }
+            for row in 0..4 {
+                if row == column {
+                    continue;
+                }
+                let factor = a[row][column];
+                for j in 0..4 {
+                    a[row][j] -= factor * a[column][j];
+                    inverse[row][j] -= factor * inverse[column][j];
+                }
+            }
+        }
+        Some(Self { m: inverse })
+    }
+
+    /// Determinant of the linear part, the change of volume of the transform.
+    pub fn determinant3(&self) -> f64 {
+        let m = &self.m;
+        m[0][0] * (m[1][1] * m[2][2] - m[1][2] * m[2][1])
+            - m[0][1] * (m[1][0] * m[2][2] - m[1][2] * m[2][0])
+            + m[0][2] * (m[1][0] * m[2][1] - m[1][1] * m[2][0])
+    }
+
+    pub fn transform_point(&self, p: Vec3f<Position>) -> Vec3f<Position> {
+        self.transform_vector(p) + Vec3f::new(self.m[0][3], self.m[1][3], self.m[2][3])
+    }
+
+    /// Transforms a direction, which is not affected by the translation.
+    pub fn transform_vector(&self, v: Vec3f<Position>) -> Vec3f<Position> {
+        let m = &self.m;
+        Vec3f::new(
+            m[0][0] * v.x() + m[0][1] * v.y() + m[0][2] * v.z(),
+            m[1][0] * v.x() + m[1][1] * v.y() + m[1][2] * v.z(),
+            m[2][0] * v.x() + m[2][1] * v.y() + m[2][2] * v.z(),
+        )
+    }
+
+    /// Transforms a normal with the transpose of the matrix.
+    /// Normals are transformed by the inverse transpose, so this is meant to be called on the inverse.
+    pub fn transform_normal(&self, n: Vec3f<Position>) -> Vec3f<Position> {
+        let m = &self.m;
+        Vec3f::new(
+            m[0][0] * n.x() + m[1][0] * n.y() + m[2][0] * n.z(),
+            m[0][1] * n.x() + m[1][1] * n.y() + m[2][1] * n.z(),
+            m[0][2] * n.x() + m[1][2] * n.y() + m[2][2] * n.z(),
+        )
+    }
+}
+
+impl std::ops::Mul for Matrix4 {
+    type Output = Self;
+
+    /// The transform that applies `rhs` first and then `self`.
+    fn mul(self, rhs: Self) -> Self {
+        let mut m = [[0.0; 4]; 4];
+        for (i, row) in m.iter_mut().enumerate() {
+            for (j, value) in row.iter_mut().enumerate() {
+                *value = (0..4).map(|k| self.m[i][k] * rhs.m[k][j]).sum();
+            }
+        }
+        Self { m }
+    }
+}
+
+/// A unit quaternion, a rotation that can be interpolated smoothly.
+#[derive(Copy, Clone)]
+pub struct Quaternion {
+    pub w: f64,
+    pub v: Vec3f<Position>,
+}
+
+impl Default for Quaternion {
+    fn default() -> Self {
+        Self::IDENTITY
+    }
+}
+
+impl Quaternion {
+    pub const IDENTITY: Self = Self {
+        w: 1.0,
+        v: Vec3f::<Position>::new(0.0, 0.0, 0.0),
+    };
+
+    /// Counterclockwise rotation around `axis`, in degrees.
+    pub fn from_axis_angle(axis: Vec3f<Position>, angle: f64) -> Self {
+        let (sin, cos) = (angle.to_radians() / 2.0).sin_cos();
+        Self {
+            w: cos,
+            v: axis.unit() * sin,
+        }
+    }
+
+    fn dot(&self, other: Self) -> f64 {
+        self.w * other.w + self.v.dot(other.v)
+    }
+
+    /// Angle in radians of the rotation that takes `self` to `other`.
+    pub fn angle_to(&self, other: Self) -> f64 {
+        2.0 * self.dot(other).abs().min(1.0).acos()
+    }
+
+    /// Spherical interpolation, at a constant angular speed along the shortest path.
+    pub fn slerp(self, other: Self, s: f64) -> Self {
+        let mut cos = self.dot(other);
+        let other = if cos < 0.0 {
+            cos = -cos;
+            Self {
+                w: -other.w,
+                v: -other.v,
+            }
+        } else {
+            other
+        };
+        let (a, b) = if cos > 0.9995 {
+            // Nearly the same rotation, where linear interpolation is precise enough
+            (1.0 - s, s)
+        } else {
+            let theta = cos.acos();
+            let sin = theta.sin();
+            (((1.0 - s) * theta).sin() / sin, (s * theta).sin() / sin)
+        };
+        let w = a * self.w + b * other.w;
+        let v = a * self.v + b * other.v;
+        let norm = (w * w + v.dot(v)).sqrt();
+        Self {
+            w: w / norm,
+            v: v / norm,
+        }
+    }
+
+    pub fn to_matrix(&self) -> Matrix4 {
+        let (w, x, y, z) = (self.w, self.v.x(), self.v.y(), self.v.z());
+        Matrix4::affine(
+            [
+                [
+                    1.0 - 2.0 * (y * y + z * z),
+                    2.0 * (x * y - w * z),
+                    2.0 * (x * z + w * y),
+                ],
+                [
+                    2.0 * (x * y + w * z),
+                    1.0 - 2.0 * (x * x + z * z),
+                    2.0 * (y * z - w * x),
+                ],
+                [
+                    2.0 * (x * z - w * y),
+                    2.0 * (y * z + w * x),
+                    1.0 - 2.0 * (x * x + y * y),
+                ],
+            ],
+            Vec3f::default(),
+        )
+    }
+
+    /// The opposite rotation.
+    pub fn conjugate(&self) -> Self {
+        Self {
+            w: self.w,
+            v: -self.v,
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(a: Matrix4, b: Matrix4) {
+        for (a, b) in a.m.iter().flatten().zip(b.m.iter().flatten()) {
+            assert!((a - b).abs() < 1e-12, "{:?} != {:?}", a, b);
+        }
+    }
+
+    #[test]
+    fn inverse_round_trip() {
+        let matrix = Matrix4::translation(Vec3f::new(1.0, -2.0, 3.0))
+            * Matrix4::rotation(Vec3f::new(1.0, 2.0, 3.0), 30.0)
+            * Matrix4::scaling(Vec3f::new(2.0, 0.5, 3.0));
+        let inverse = matrix.inverse().unwrap();
+        assert_close(matrix * inverse, Matrix4::IDENTITY);
+        assert_close(inverse * matrix, Matrix4::IDENTITY);
+    }
+
+    #[test]
+    fn singular_matrices_have_no_inverse() {
+        let flat = Matrix4::scaling(Vec3f::new(1.0, 0.0, 1.0));
+        assert!(flat.inverse().is_none());
+        let no_axis = Matrix4::rotation(Vec3f::repeat(0.0), 30.0);
+        assert!(no_axis.inverse().is_none());
+    }
+}
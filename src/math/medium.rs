@@ -0,0 +1,422 @@
+//! Participating media whose density changes from point to point, like clouds and smoke.
+
+use rand::Rng;
+
+use std::sync::Arc;
+
+use super::hittable::after;
+use super::{
+    Bound, HitRecord, Hittable, Isotropic, Material, Perlin, Position, Ray, Sampler, Texture, Vec3f,
+};
+
+/// Density of a medium at every point of space.
+pub trait Density: Send + Sync {
+    fn density(&self, p: Vec3f<Position>) -> f64;
+
+    /// The majorant: no point is denser than this.
+    /// A loose bound is correct, but makes the medium slower to trace.
+    fn majorant(&self) -> f64;
+}
+
+/// Wispy density from Perlin turbulence.
+pub struct NoiseDensity {
+    noise: Perlin,
+    /// Density where the turbulence is strongest
+    pub density: f64,
+    /// Frequency of the noise
+    pub scale: f64,
+    /// Octaves of the turbulence
+    pub depth: u32,
+}
+
+impl NoiseDensity {
+    pub fn new<R: Rng + ?Sized>(density: f64, scale: f64, rng: &mut R) -> Self {
+        Self {
+            noise: Perlin::new(rng),
+            density,
+            scale,
+            depth: 7,
+        }
+    }
+}
+
+impl Density for NoiseDensity {
+    fn density(&self, p: Vec3f<Position>) -> f64 {
+        let turbulence = self.noise.turbulence(p * self.scale, self.depth);
+        self.density * turbulence.abs().min(1.0)
+    }
+
+    fn majorant(&self) -> f64 {
+        self.density
+    }
+}
+
+/// Densities sampled at the corners of a regular grid spanning `bound`,
+/// interpolated trilinearly. There is nothing outside of the box.
+pub struct VoxelGrid {
+    bound: Bound,
+    resolution: [usize; 3],
+    /// X changes fastest, then Y and then Z.
+    values: Vec<f64>,
+    majorant: f64,
+}
+
+impl VoxelGrid {
+    /// `None` if the number of values is not the product of the resolution.
+    pub fn new(bound: Bound, resolution: [usize; 3], values: Vec<f64>) -> Option<Self> {
+        if resolution.contains(&0) || values.len() != resolution.iter().product() {
+            return None;
+        }
+        let majorant = values.iter().fold(0.0_f64, |max, &x| max.max(x));
+        Some(Self {
+            bound,
+            resolution,
+            values,
+            majorant,
+        })
+    }
+
+    fn value(&self, x: usize, y: usize, z: usize) -> f64 {
+        let [nx, ny, _] = self.resolution;
+        self.values[(z * ny + y) * nx + x].max(0.0)
+    }
+}
+
+impl Density for VoxelGrid {
+    fn density(&self, p: Vec3f<Position>) -> f64 {
+        let extent = self.bound.max - self.bound.min;
+        let mut cell = [0; 3];
+        let mut fraction = [0.0; 3];
+        for dimension in 0..3 {
+            let relative = (p.at(dimension) - self.bound.min.at(dimension)) / extent.at(dimension);
+            if !(0.0..=1.0).contains(&relative) {
+                return 0.0;
+            }
+            let last = self.resolution[dimension] - 1;
+            let position = relative * last as f64;
+            cell[dimension] = (position as usize).min(last.saturating_sub(1));
+            fraction[dimension] = if last == 0 {
+                0.0
+            } else {
+                position - cell[dimension] as f64
+            };
+        }
+        let mut accumulator = 0.0;
+        for corner in 0..8 {
+            let mut weight = 1.0;
+            let mut index = [0; 3];
+            for dimension in 0..3 {
+                let step = (corner >> dimension) & 1;
+                index[dimension] = (cell[dimension] + step).min(self.resolution[dimension] - 1);
+                weight *= if step == 1 {
+                    fraction[dimension]
+                } else {
+                    1.0 - fraction[dimension]
+                };
+            }
+            accumulator += weight * self.value(index[0], index[1], index[2]);
+        }
+        accumulator
+    }
+
+    fn majorant(&self) -> f64 {
+        self.majorant
+    }
+}
+
+/// Density given by a closure, clamped to `0..=majorant`. Nothing is denser than a
+/// negative majorant, so the medium is empty.
+pub struct DensityFunction<F> {
+    pub function: F,
+    pub majorant: f64,
+}
+
+impl<F> Density for DensityFunction<F>
+where
+    F: Fn(Vec3f<Position>) -> f64 + Send + Sync,
+{
+    fn density(&self, p: Vec3f<Position>) -> f64 {
+        (self.function)(p).min(self.majorant).max(0.0)
+    }
+
+    fn majorant(&self) -> f64 {
+        self.majorant
+    }
+}
+
+/// The parts of `t_min..t_max` in which a ray is inside a boundary, in order.
+pub enum Intervals {
+    /// A convex boundary is entered once at most.
+    Convex(Option<(f64, f64)>),
+    Walked(std::vec::IntoIter<(f64, f64)>),
+}
+
+impl Iterator for Intervals {
+    type Item = (f64, f64);
+
+    fn next(&mut self) -> Option<(f64, f64)> {
+        match self {
+            Intervals::Convex(interval) => interval.take(),
+            Intervals::Walked(intervals) => intervals.next(),
+        }
+    }
+}
+
+/// The parts of `t_min..t_max` in which the ray is inside the closed `boundary`, in order.
+///
+/// Every crossing of the boundary is walked, counting how many times the ray has entered
+/// it, so non-convex and overlapping boundaries work, and so do rays that start inside.
+/// Convex boundaries only need their first two hits, and nothing is allocated for them.
+pub fn inside_intervals(
+    boundary: &dyn Hittable,
+    ray: Ray,
+    t_min: f64,
+    t_max: f64,
+    sampler: &mut Sampler,
+) -> Intervals {
+    let t_min = t_min.max(0.0);
+    if boundary.is_convex() {
+        let entry = boundary.hit(ray, f64::NEG_INFINITY, f64::INFINITY, sampler);
+        let interval = entry.and_then(|entry| {
+            let exit = boundary.hit(ray, after(entry.t), f64::INFINITY, sampler)?;
+            Some((entry.t.max(t_min), exit.t.min(t_max)))
+        });
+        return Intervals::Convex(interval.filter(|(start, end)| start < end));
+    }
+
+    let mut crossings = Vec::new();
+    boundary.crossings(
+        ray,
+        f64::NEG_INFINITY,
+        f64::INFINITY,
+        sampler,
+        &mut crossings,
+    );
+    let mut intervals = Vec::new();
+    let mut depth = 0;
+    let mut start = f64::NEG_INFINITY;
+    let mut previous = f64::NEG_INFINITY;
+    for crossing in &crossings {
+        if crossing.front_face {
+            if depth == 0 {
+                start = crossing.t;
+            }
+            depth += 1;
+        } else {
+            // Leaving without having entered: the entry was missed, so the ray
+            // was inside since the previous crossing.
+            if depth == 0 {
+                start = previous;
+                depth = 1;
+            }
+            depth -= 1;
+            if depth == 0 {
+                let (start, end) = (start.max(t_min), crossing.t.min(t_max));
+                if start < end {
+                    intervals.push((start, end));
+                }
+            }
+        }
+        previous = crossing.t;
+        if previous >= t_max {
+            break;
+        }
+    }
+    Intervals::Walked(intervals.into_iter())
+}
+
+/// A volume inside `boundary` that scatters light where it's dense.
+///
+/// The distance to the next scattering is sampled by delta tracking: the medium is
+/// filled up to the majorant with fictitious particles, and a collision is real with
+/// probability `density / majorant`. Shadow rays are traced the same way, which makes
+/// their transmittance an unbiased estimate.
+pub struct HeterogeneousMedium {
+    boundary: Arc<dyn Hittable>,
+    density: Arc<dyn Density>,
+    phase_function: Arc<dyn Material>,
+}
+
+impl HeterogeneousMedium {
+    /// A medium that scatters equally in every direction.
+    pub fn new(
+        boundary: Arc<dyn Hittable>,
+        density: Arc<dyn Density>,
+        albedo: Arc<dyn Texture>,
+    ) -> Self {
+        Self::with_phase_function(boundary, density, Arc::new(Isotropic { albedo }))
+    }
+
+    /// A medium that scatters with the directions of `phase_function`.
+    pub fn with_phase_function(
+        boundary: Arc<dyn Hittable>,
+        density: Arc<dyn Density>,
+        phase_function: Arc<dyn Material>,
+    ) -> Self {
+        Self {
+            boundary,
+            density,
+            phase_function,
+        }
+    }
+}
+
+impl Hittable for HeterogeneousMedium {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let majorant = self.density.majorant();
+        if majorant <= 0.0 {
+            return None;
+        }
+        let ray_length = ray.direction().magnitude();
+        // The distance to a collision doesn't depend on the distance already travelled,
+        // so tracking can restart at every interval.
+        for (mut t, t_exit) in inside_intervals(self.boundary.as_ref(), ray, t_min, t_max, sampler)
+        {
+            loop {
+                // `1 - x` is never 0, so the logarithm is finite
+                t -= (1.0 - sampler.gen::<f64>()).ln() / (majorant * ray_length);
+                if t >= t_exit {
+                    break;
+                }
+                let p = ray.point_at_parameter(t);
+                if sampler.gen::<f64>() * majorant < self.density.density(p) {
+                    return Some(HitRecord {
+                        t,
+                        p,
+                        normal: Vec3f::new(1., 0., 0.), // Arbitrary
+                        front_face: true,               // Arbitrary
+                        material: self.phase_function.as_ref(),
+                        u: 0.0,
+                        v: 0.0,
+                    });
+                }
+            }
+        }
+        None
+    }
+
+    fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
+        self.boundary.bounding_box(initial_time, final_time)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{self, assert_close, gray};
+    use crate::math::{Color, Cube, List, Sphere, Torus};
+
+    /// The start and end of each interval inside `boundary` along X from `origin`.
+    fn intervals(boundary: &dyn Hittable, origin: Vec3f<Position>) -> Vec<f64> {
+        let ray = Ray {
+            a: origin,
+            b: Vec3f::new(1.0, 0.0, 0.0),
+            ..Ray::default()
+        };
+        let mut sampler = Sampler::new(0);
+        inside_intervals(boundary, ray, 0.001, f64::INFINITY, &mut sampler)
+            .flat_map(|(start, end)| [start, end])
+            .collect()
+    }
+
+    fn sphere() -> Sphere {
+        test_util::sphere(Vec3f::repeat(0.0), 1.0)
+    }
+
+    #[test]
+    fn constant_densities_transmit_exponentially() {
+        let (density, length) = (0.7, 2.0);
+        let boundary = Cube::new(
+            Vec3f::new(0.0, -1.0, -1.0),
+            Vec3f::new(length, 1.0, 1.0),
+            gray(),
+        );
+        // Twice as many fictitious collisions as real ones
+        let function = DensityFunction {
+            function: move |_| density,
+            majorant: 2.0 * density,
+        };
+        let albedo = Arc::new(Vec3f::<Color>::repeat(0.5));
+        let medium = HeterogeneousMedium::new(Arc::new(boundary), Arc::new(function), albedo);
+        let ray = Ray {
+            a: Vec3f::new(-1.0, 0.0, 0.0),
+            b: Vec3f::new(2.0, 0.0, 0.0),
+            ..Ray::default()
+        };
+        let mut sampler = Sampler::new(0);
+        let samples = 100_000;
+        let mut transmitted = 0;
+        for _ in 0..samples {
+            let hit = medium.hit(ray, 0.001, f64::INFINITY, &mut sampler);
+            if hit.is_none() {
+                transmitted += 1;
+            }
+        }
+        let transmittance = transmitted as f64 / samples as f64;
+        let expected = (-density * length).exp();
+        assert!((transmittance - expected).abs() < 5e-3, "{}", transmittance);
+    }
+
+    #[test]
+    fn voxel_grids_interpolate_between_corners() {
+        let bound = Bound {
+            min: Vec3f::new(-1.0, 0.0, 2.0),
+            max: Vec3f::new(3.0, 1.0, 2.5),
+        };
+        let values = (0..12).map(|i| (i * i) as f64 / 10.0).collect::<Vec<_>>();
+        let grid = VoxelGrid::new(bound, [3, 2, 2], values.clone()).unwrap();
+        let corner = |x: usize, y: usize, z: usize| {
+            Vec3f::new(-1.0 + 2.0 * x as f64, y as f64, 2.0 + 0.5 * z as f64)
+        };
+        for z in 0..2 {
+            for y in 0..2 {
+                for x in 0..3 {
+                    let density = grid.density(corner(x, y, z));
+                    let value = values[(z * 2 + y) * 3 + x];
+                    assert!((density - value).abs() < 1e-12, "{} != {}", density, value);
+                }
+            }
+        }
+        // Halfway along an edge
+        let middle = (corner(0, 1, 1) + corner(1, 1, 1)) / 2.0;
+        let expected = (values[9] + values[10]) / 2.0;
+        assert!((grid.density(middle) - expected).abs() < 1e-12);
+        assert_eq!(grid.majorant(), 12.1);
+    }
+
+    #[test]
+    fn torus_is_entered_twice() {
+        let torus = Torus {
+            center: Vec3f::repeat(0.0),
+            major_radius: 2.0,
+            minor_radius: 0.5,
+            material: gray(),
+        };
+        let outside = intervals(&torus, Vec3f::new(-5.0, 0.0, 0.0));
+        assert_close(&outside, &[2.5, 3.5, 6.5, 7.5]);
+        let inside = intervals(&torus, Vec3f::new(-2.0, 0.0, 0.0));
+        assert_close(&inside, &[0.001, 0.5, 3.5, 4.5]);
+    }
+
+    #[test]
+    fn rays_can_start_inside() {
+        let convex = sphere();
+        let mut walked = List::new();
+        walked.push(sphere());
+        assert!(convex.is_convex() && !walked.is_convex());
+        for boundary in [&convex as &dyn Hittable, &walked] {
+            let inside = intervals(boundary, Vec3f::new(-0.5, 0.0, 0.0));
+            assert_close(&inside, &[0.001, 1.5]);
+            let outside = intervals(boundary, Vec3f::new(-2.0, 0.0, 0.0));
+            assert_close(&outside, &[1.0, 3.0]);
+            let behind = intervals(boundary, Vec3f::new(2.0, 0.0, 0.0));
+            assert_close(&behind, &[]);
+        }
+    }
+}
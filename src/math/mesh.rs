@@ -0,0 +1,372 @@
+use rayon::prelude::*;
+
+use std::sync::Arc;
+
+use super::bvh::Bvh;
+use super::{Bound, BvhStats, HitRecord, Hittable, Material, Position, Ray, Sampler, Vec3f};
+
+/// Möller–Trumbore ray-triangle intersection.
+/// Returns the ray's `t` and the barycentric coordinates of the 2nd and 3rd vertices.
+fn intersect(
+    ray: Ray,
+    [v0, v1, v2]: [Vec3f<Position>; 3],
+    t_min: f64,
+    t_max: f64,
+) -> Option<(f64, f64, f64)> {
+    let edge1 = v1 - v0;
+    let edge2 = v2 - v0;
+    let p = ray.direction().cross(edge2);
+    let determinant = edge1.dot(p);
+    // The ray is parallel to the triangle
+    if determinant.abs() < 1e-12 {
+        return None;
+    }
+    let inverse_determinant = 1.0 / determinant;
+    let s = ray.origin() - v0;
+    let b1 = s.dot(p) * inverse_determinant;
+    if !(0.0..=1.0).contains(&b1) {
+        return None;
+    }
+    let q = s.cross(edge1);
+    let b2 = ray.direction().dot(q) * inverse_determinant;
+    if b2 < 0.0 || b1 + b2 > 1.0 {
+        return None;
+    }
+    let t = edge2.dot(q) * inverse_determinant;
+    if t < t_min || t > t_max {
+        return None;
+    }
+    Some((t, b1, b2))
+}
+
+/// Builds the record of a hit on a triangle.
+/// The shading normal, if any, is flipped to the side of the geometric normal.
+fn record<'a>(
+    ray: Ray,
+    t: f64,
+    (b1, b2): (f64, f64),
+    vertices: [Vec3f<Position>; 3],
+    normals: Option<[Vec3f<Position>; 3]>,
+    uvs: Option<[(f64, f64); 3]>,
+    material: &'a dyn Material,
+) -> HitRecord<'a> {
+    let [v0, v1, v2] = vertices;
+    let b0 = 1.0 - b1 - b2;
+    let geometric_normal = (v1 - v0).cross(v2 - v0).unit();
+    let normal = normals
+        .map(|[n0, n1, n2]| {
+            let normal = (b0 * n0 + b1 * n1 + b2 * n2).unit();
+            if normal.dot(geometric_normal) < 0.0 {
+                -normal
+            } else {
+                normal
+            }
+        })
+        .unwrap_or(geometric_normal);
+    let (u, v) = uvs
+        .map(|[uv0, uv1, uv2]| {
+            (
+                b0 * uv0.0 + b1 * uv1.0 + b2 * uv2.0,
+                b0 * uv0.1 + b1 * uv1.1 + b2 * uv2.1,
+            )
+        })
+        .unwrap_or((b1, b2));
+    let front_face = ray.direction().dot(geometric_normal) < 0.0;
+    HitRecord {
+        t,
+        p: ray.point_at_parameter(t),
+        normal: if front_face { normal } else { -normal },
+        material,
+        u,
+        v,
+        front_face,
+    }
+}
+
+fn triangle_bound(vertices: [Vec3f<Position>; 3]) -> Bound {
+    let [v0, v1, v2] = vertices;
+    let min = Vec3f::new(
+        v0.x().min(v1.x()).min(v2.x()),
+        v0.y().min(v1.y()).min(v2.y()),
+        v0.z().min(v1.z()).min(v2.z()),
+    );
+    let max = Vec3f::new(
+        v0.x().max(v1.x()).max(v2.x()),
+        v0.y().max(v1.y()).max(v2.y()),
+        v0.z().max(v1.z()).max(v2.z()),
+    );
+    Bound { min, max }.padded(Bound::FLAT_PADDING)
+}
+
+/// A single triangle. Without per-vertex texture coordinates
+/// `(u, v)` are the barycentric coordinates of the 2nd and 3rd vertices.
+pub struct Triangle {
+    pub vertices: [Vec3f<Position>; 3],
+    /// Per-vertex normals, interpolated across the face.
+    pub normals: Option<[Vec3f<Position>; 3]>,
+    /// Per-vertex texture coordinates.
+    pub uvs: Option<[(f64, f64); 3]>,
+    pub material: Arc<dyn Material>,
+}
+
+impl Triangle {
+    pub fn new(vertices: [Vec3f<Position>; 3], material: Arc<dyn Material>) -> Self {
+        Self {
+            vertices,
+            normals: None,
+            uvs: None,
+            material,
+        }
+    }
+}
+
+impl Hittable for Triangle {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (t, b1, b2) = intersect(ray, self.vertices, t_min, t_max)?;
+        Some(record(
+            ray,
+            t,
+            (b1, b2),
+            self.vertices,
+            self.normals,
+            self.uvs,
+            self.material.as_ref(),
+        ))
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        Some(triangle_bound(self.vertices))
+    }
+}
+
+/// An indexed triangle mesh. The vertex buffers are shared by all the triangles,
+/// which are kept in a bounding volume hierarchy of their own.
+pub struct TriangleMesh {
+    positions: Vec<Vec3f<Position>>,
+    normals: Option<Vec<Vec3f<Position>>>,
+    uvs: Option<Vec<(f64, f64)>>,
+    /// Indices of the vertices of each triangle, ordered by the hierarchy.
+    triangles: Vec<[usize; 3]>,
+    bvh: Bvh,
+    material: Arc<dyn Material>,
+}
+
+impl TriangleMesh {
+    /// # Panics
+    /// If `triangles` is empty or an index is out of the bounds of `positions`.
+    pub fn new(
+        positions: Vec<Vec3f<Position>>,
+        triangles: Vec<[usize; 3]>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        assert!(!triangles.is_empty(), "A mesh needs at least one triangle");
+        assert!(
+            triangles.iter().flatten().all(|&i| i < positions.len()),
+            "Mesh index out of bounds"
+        );
+        let bounds = triangles
+            .par_iter()
+            .map(|triangle| triangle_bound(triangle.map(|i| positions[i])))
+            .collect::<Vec<_>>();
+        let (bvh, order) = Bvh::build(&bounds, Default::default());
+        Self {
+            positions,
+            normals: None,
+            uvs: None,
+            triangles: order.into_iter().map(|i| triangles[i]).collect(),
+            bvh,
+            material,
+        }
+    }
+
+    /// Per-vertex normals, indexed like the positions.
+    ///
+    /// # Panics
+    /// If there is not one normal per position.
+    pub fn with_normals(mut self, normals: Vec<Vec3f<Position>>) -> Self {
+        assert_eq!(normals.len(), self.positions.len());
+        self.normals = Some(normals);
+        self
+    }
+
+    /// Per-vertex texture coordinates, indexed like the positions.
+    ///
+    /// # Panics
+    /// If there are not one coordinates per position.
+    pub fn with_uvs(mut self, uvs: Vec<(f64, f64)>) -> Self {
+        assert_eq!(uvs.len(), self.positions.len());
+        self.uvs = Some(uvs);
+        self
+    }
+
+    pub fn len(&self) -> usize {
+        self.triangles.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.triangles.is_empty()
+    }
+
+    fn vertices(&self, triangle: usize) -> [Vec3f<Position>; 3] {
+        self.triangles[triangle].map(|i| self.positions[i])
+    }
+
+    pub fn stats(&self) -> &BvhStats {
+        self.bvh.stats()
+    }
+}
+
+impl Hittable for TriangleMesh {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (triangle, t, b1, b2) = self.bvh.closest(ray, t_min, t_max, |triangle, t_max| {
+            let (t, b1, b2) = intersect(ray, self.vertices(triangle), t_min, t_max)?;
+            Some((t, (triangle, t, b1, b2)))
+        })?;
+        let indices = self.triangles[triangle];
+        Some(record(
+            ray,
+            t,
+            (b1, b2),
+            self.vertices(triangle),
+            self.normals.as_ref().map(|normals| indices.map(|i| normals[i])),
+            self.uvs.as_ref().map(|uvs| indices.map(|i| uvs[i])),
+            self.material.as_ref(),
+        ))
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        Some(self.bvh.bound())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::gray;
+
+    const VERTICES: [Vec3f<Position>; 3] = [
+        Vec3f::new(0.0, 0.0, 0.0),
+        Vec3f::new(2.0, 0.0, 0.0),
+        Vec3f::new(0.0, 2.0, 0.0),
+    ];
+
+    /// The triangle, and a mesh of it, with normals tilted towards the 2nd and 3rd vertices.
+    fn triangles() -> (Triangle, TriangleMesh) {
+        let normals = [
+            Vec3f::new(0.0, 0.0, 1.0),
+            Vec3f::new(1.0, 0.0, 1.0).unit(),
+            Vec3f::new(0.0, 1.0, 1.0).unit(),
+        ];
+        let uvs = [(0.0, 0.0), (1.0, 0.5), (0.25, 1.0)];
+        let triangle = Triangle {
+            normals: Some(normals),
+            uvs: Some(uvs),
+            ..Triangle::new(VERTICES, gray())
+        };
+        let mesh = TriangleMesh::new(VERTICES.to_vec(), vec![[0, 1, 2]], gray())
+            .with_normals(normals.to_vec())
+            .with_uvs(uvs.to_vec());
+        (triangle, mesh)
+    }
+
+    fn vertical_ray(x: f64, y: f64, z: f64) -> Ray {
+        Ray {
+            a: Vec3f::new(x, y, z),
+            b: Vec3f::new(0.0, 0.0, -z.signum()),
+            ..Ray::default()
+        }
+    }
+
+    #[test]
+    fn edges_are_hit() {
+        let (triangle, mesh) = triangles();
+        let mut sampler = Sampler::new(0);
+        for item in [&triangle as &dyn Hittable, &mesh] {
+            // On the hypotenuse, and on the other two edges
+            for (x, y) in [(1.0, 1.0), (1.0, 0.0), (0.0, 0.5)] {
+                let ray = vertical_ray(x, y, 1.0);
+                let record = item.hit(ray, 0.001, f64::INFINITY, &mut sampler);
+                assert!(record.is_some(), "({}, {})", x, y);
+            }
+            let outside = vertical_ray(1.01, 1.0, 1.0);
+            assert!(item.hit(outside, 0.001, f64::INFINITY, &mut sampler).is_none());
+        }
+    }
+
+    #[test]
+    fn back_faces_are_hit() {
+        let (triangle, mesh) = triangles();
+        let mut sampler = Sampler::new(0);
+        for item in [&triangle as &dyn Hittable, &mesh] {
+            let front = vertical_ray(0.5, 0.5, 1.0);
+            let record = item.hit(front, 0.001, f64::INFINITY, &mut sampler).unwrap();
+            assert!(record.front_face && record.normal.z() > 0.0);
+            let back = vertical_ray(0.5, 0.5, -1.0);
+            let record = item.hit(back, 0.001, f64::INFINITY, &mut sampler).unwrap();
+            // The normal still faces the ray
+            assert!(!record.front_face && record.normal.z() < 0.0);
+            assert!((record.t - 1.0).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn normals_and_uvs_are_interpolated() {
+        let (triangle, mesh) = triangles();
+        let mut sampler = Sampler::new(0);
+        for item in [&triangle as &dyn Hittable, &mesh] {
+            // Barycentric coordinates of 1/4 for the 2nd vertex and 1/2 for the 3rd
+            let ray = vertical_ray(0.5, 1.0, 1.0);
+            let record = item.hit(ray, 0.001, f64::INFINITY, &mut sampler).unwrap();
+            let normal = (0.25 * Vec3f::new(0.0, 0.0, 1.0)
+                + 0.25 * Vec3f::new(1.0, 0.0, 1.0).unit()
+                + 0.5 * Vec3f::new(0.0, 1.0, 1.0).unit())
+            .unit();
+            assert!((record.normal - normal).magnitude() < 1e-12);
+            assert!((record.u - (0.25 + 0.125)).abs() < 1e-12);
+            assert!((record.v - (0.125 + 0.5)).abs() < 1e-12);
+        }
+    }
+
+    #[test]
+    fn hierarchy_finds_the_closest_triangle() {
+        let mut sampler = Sampler::new(0);
+        let positions = (0..300)
+            .map(|_| Vec3f::random(-5.0..5.0, &mut sampler))
+            .collect::<Vec<_>>();
+        let indices = (0..100).map(|i| [3 * i, 3 * i + 1, 3 * i + 2]).collect();
+        let mesh = TriangleMesh::new(positions.clone(), indices, gray());
+        let triangles = positions
+            .chunks(3)
+            .map(|vertices| Triangle::new([vertices[0], vertices[1], vertices[2]], gray()))
+            .collect::<Vec<_>>();
+        let mut hits = 0;
+        for _ in 0..1000 {
+            let ray = Ray {
+                a: Vec3f::random(-10.0..10.0, &mut sampler),
+                b: Vec3f::random(-1.0..1.0, &mut sampler),
+                ..Ray::default()
+            };
+            let closest = triangles
+                .iter()
+                .filter_map(|triangle| triangle.hit(ray, 0.001, f64::INFINITY, &mut sampler))
+                .map(|record| record.t)
+                .min_by(f64::total_cmp);
+            let record = mesh.hit(ray, 0.001, f64::INFINITY, &mut sampler);
+            assert_eq!(record.map(|record| record.t), closest);
+            hits += record.is_some() as usize;
+        }
+        assert!(hits > 100, "{}", hits);
+    }
+}
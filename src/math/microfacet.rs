@@ -0,0 +1,527 @@
+//! Rough surfaces made of tiny mirrors, with the GGX (Trowbridge-Reitz) distribution
+//! of their normals.
+//!
+//! Directions are expressed in the basis of the shading normal, with `z` pointing to the
+//! side the ray comes from, and `x` along the tangent of the material projected on the
+//! surface, which anisotropic roughness stretches the highlights along.
+
+use rand::Rng;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::material::absorbing;
+use super::{BsdfSample, Color, HitRecord, Material, Onb, Position, Ray, Sampler, Vec3f};
+
+/// Roughness below which a surface is a perfect mirror.
+const SMOOTH: f64 = 1e-3;
+
+/// Default direction of the tangent of the materials, which only matters to anisotropic ones.
+pub(super) const TANGENT: Vec3f<Position> = Vec3f::new(0.0, 1.0, 0.0);
+
+/// GGX distribution of microfacet normals, with roughness `alpha_x` and `alpha_y`
+/// along the two tangents.
+#[derive(Copy, Clone)]
+pub struct Ggx {
+    pub alpha_x: f64,
+    pub alpha_y: f64,
+}
+
+impl Ggx {
+    pub fn new(alpha_x: f64, alpha_y: f64) -> Self {
+        Self { alpha_x, alpha_y }
+    }
+
+    pub fn isotropic(alpha: f64) -> Self {
+        Self::new(alpha, alpha)
+    }
+
+    /// Perceptual `roughness` from 0 to 1, squared into `alpha` so it changes linearly
+    /// to the eye. `anisotropy` from 0 to 1 makes the first tangent, `x`, rougher.
+    pub fn from_roughness(roughness: f64, anisotropy: f64) -> Self {
+        let alpha = roughness.clamp(0.0, 1.0).powi(2);
+        let aspect = (1.0 - 0.9 * anisotropy.clamp(0.0, 1.0)).sqrt();
+        Self::new(alpha / aspect, alpha * aspect)
+    }
+
+    /// The surface is so smooth it's a mirror.
+    pub fn is_smooth(&self) -> bool {
+        self.alpha_x.max(self.alpha_y) < SMOOTH
+    }
+
+    /// Density of microfacets with normal `h`.
+    pub fn d(&self, h: Vec3f<Position>) -> f64 {
+        if h.z() <= 0.0 {
+            return 0.0;
+        }
+        let x = h.x() / self.alpha_x;
+        let y = h.y() / self.alpha_y;
+        let e = x * x + y * y + h.z() * h.z();
+        1.0 / (PI * self.alpha_x * self.alpha_y * e * e)
+    }
+
+    /// Smith's auxiliary function, the shadowed area over the visible area.
+    fn lambda(&self, w: Vec3f<Position>) -> f64 {
+        let x = self.alpha_x * w.x();
+        let y = self.alpha_y * w.y();
+        let tan2 = (x * x + y * y) / (w.z() * w.z());
+        if !tan2.is_finite() {
+            return 0.0;
+        }
+        ((1.0 + tan2).sqrt() - 1.0) / 2.0
+    }
+
+    /// Fraction of the microfacets seen from `w` that aren't hidden by others.
+    pub fn g1(&self, w: Vec3f<Position>) -> f64 {
+        1.0 / (1.0 + self.lambda(w))
+    }
+
+    /// Fraction of the microfacets visible from both `wo` and `wi`.
+    pub fn g(&self, wo: Vec3f<Position>, wi: Vec3f<Position>) -> f64 {
+        1.0 / (1.0 + self.lambda(wo) + self.lambda(wi))
+    }
+
+    /// Density with which [`Ggx::sample`] picks `h` when seen from `wo`.
+    pub fn visible_pdf(&self, wo: Vec3f<Position>, h: Vec3f<Position>) -> f64 {
+        self.g1(wo) * wo.dot(h).max(0.0) * self.d(h) / wo.z().abs()
+    }
+
+    /// A microfacet normal visible from `wo`, by Heitz's sampling of visible normals.
+    pub fn sample(&self, wo: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position> {
+        let (u1, u2) = (sampler.gen::<f64>(), sampler.gen::<f64>());
+        // Stretch the view so the distribution becomes the hemisphere
+        let v = Vec3f::<Position>::new(self.alpha_x * wo.x(), self.alpha_y * wo.y(), wo.z()).unit();
+        let length2 = v.x() * v.x() + v.y() * v.y();
+        let t1 = if length2 > 0.0 {
+            Vec3f::new(-v.y(), v.x(), 0.0) / length2.sqrt()
+        } else {
+            Vec3f::new(1.0, 0.0, 0.0)
+        };
+        let t2 = v.cross(t1);
+        // A point of the disk, warped to the part of the hemisphere that's visible
+        let r = u1.sqrt();
+        let phi = 2.0 * PI * u2;
+        let p1 = r * phi.cos();
+        let s = 0.5 * (1.0 + v.z());
+        let p2 = (1.0 - s) * (1.0 - p1 * p1).sqrt() + s * r * phi.sin();
+        let n = p1 * t1 + p2 * t2 + (1.0 - p1 * p1 - p2 * p2).max(0.0).sqrt() * v;
+        // And back to the ellipsoid
+        Vec3f::new(self.alpha_x * n.x(), self.alpha_y * n.y(), n.z().max(1e-6)).unit()
+    }
+}
+
+/// Fraction of the light reflected by a metal of complex refraction index `eta + ik`,
+/// at `cosine` from the normal.
+pub fn fresnel_conductor(cosine: f64, eta: f64, k: f64) -> f64 {
+    let cos2 = cosine * cosine;
+    let sin2 = 1.0 - cos2;
+    let t0 = eta * eta - k * k - sin2;
+    let a2_plus_b2 = (t0 * t0 + 4.0 * eta * eta * k * k).sqrt();
+    let a = (0.5 * (a2_plus_b2 + t0)).max(0.0).sqrt();
+    let t1 = a2_plus_b2 + cos2;
+    let t2 = 2.0 * cosine * a;
+    let s = (t1 - t2) / (t1 + t2);
+    let t3 = cos2 * a2_plus_b2 + sin2 * sin2;
+    let t4 = t2 * sin2;
+    let p = s * (t3 - t4) / (t3 + t4);
+    0.5 * (s + p)
+}
+
+/// Fraction of the light reflected when it arrives at `cosine` from the normal to a surface
+/// with refraction index `eta` on the other side, relative to this side.
+pub fn fresnel_dielectric(cosine: f64, eta: f64) -> f64 {
+    let sin2_t = (1.0 - cosine * cosine) / (eta * eta);
+    if sin2_t >= 1.0 {
+        // Total internal reflection
+        return 1.0;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    let parallel = (eta * cosine - cos_t) / (eta * cosine + cos_t);
+    let perpendicular = (cosine - eta * cos_t) / (cosine + eta * cos_t);
+    (parallel * parallel + perpendicular * perpendicular) / 2.0
+}
+
+pub(super) fn reflect(wo: Vec3f<Position>, h: Vec3f<Position>) -> Vec3f<Position> {
+    -wo + 2.0 * wo.dot(h) * h
+}
+
+/// `wo` refracted through the microfacet `h`, into a medium `eta` times denser.
+/// `None` on total internal reflection.
+fn refract(wo: Vec3f<Position>, h: Vec3f<Position>, eta: f64) -> Option<Vec3f<Position>> {
+    let cos_i = wo.dot(h);
+    let sin2_t = (1.0 - cos_i * cos_i).max(0.0) / (eta * eta);
+    if sin2_t >= 1.0 {
+        return None;
+    }
+    let cos_t = (1.0 - sin2_t).sqrt();
+    Some(-wo / eta + (cos_i / eta - cos_t) * h)
+}
+
+/// The shading basis of a hit, with `x` along `tangent`, and the direction to where the
+/// ray comes from in it.
+pub(super) fn frame(
+    ray: Ray,
+    record: HitRecord,
+    tangent: Vec3f<Position>,
+) -> (Onb, Vec3f<Position>) {
+    let onb = Onb::from_w_u(record.normal, tangent);
+    let wo = onb.to_local(-ray.direction().unit());
+    (onb, wo)
+}
+
+/// Rough metal, described by its complex refraction index `eta + ik` for red, green and blue.
+#[derive(Copy, Clone)]
+pub struct Conductor {
+    eta: Vec3f<Color>,
+    k: Vec3f<Color>,
+    distribution: Ggx,
+    tangent: Vec3f<Position>,
+}
+
+impl Conductor {
+    pub const GOLD: (Vec3f<Color>, Vec3f<Color>) = (
+        Vec3f::new(0.143, 0.374, 1.442),
+        Vec3f::new(3.983, 2.385, 1.603),
+    );
+    pub const COPPER: (Vec3f<Color>, Vec3f<Color>) = (
+        Vec3f::new(0.200, 0.924, 1.102),
+        Vec3f::new(3.912, 2.452, 2.142),
+    );
+    pub const ALUMINIUM: (Vec3f<Color>, Vec3f<Color>) = (
+        Vec3f::new(1.657, 0.880, 0.521),
+        Vec3f::new(9.224, 6.270, 4.837),
+    );
+    pub const SILVER: (Vec3f<Color>, Vec3f<Color>) = (
+        Vec3f::new(0.155, 0.117, 0.138),
+        Vec3f::new(4.828, 3.122, 2.147),
+    );
+
+    pub fn new(eta: Vec3f<Color>, k: Vec3f<Color>, distribution: Ggx) -> Self {
+        Self {
+            eta,
+            k,
+            distribution,
+            tangent: TANGENT,
+        }
+    }
+
+    /// Stretches anisotropic highlights along `tangent`, projected on the surface.
+    /// By default it's the Y axis.
+    pub fn with_tangent(self, tangent: Vec3f<Position>) -> Self {
+        Self { tangent, ..self }
+    }
+
+    pub fn gold(distribution: Ggx) -> Self {
+        let (eta, k) = Self::GOLD;
+        Self::new(eta, k, distribution)
+    }
+
+    pub fn copper(distribution: Ggx) -> Self {
+        let (eta, k) = Self::COPPER;
+        Self::new(eta, k, distribution)
+    }
+
+    pub fn aluminium(distribution: Ggx) -> Self {
+        let (eta, k) = Self::ALUMINIUM;
+        Self::new(eta, k, distribution)
+    }
+
+    pub fn silver(distribution: Ggx) -> Self {
+        let (eta, k) = Self::SILVER;
+        Self::new(eta, k, distribution)
+    }
+
+    fn fresnel(&self, cosine: f64) -> Vec3f<Color> {
+        Vec3f::new(
+            fresnel_conductor(cosine, self.eta.r(), self.k.r()),
+            fresnel_conductor(cosine, self.eta.g(), self.k.g()),
+            fresnel_conductor(cosine, self.eta.b(), self.k.b()),
+        )
+    }
+}
+
+impl Material for Conductor {
+    /// Reflects on a visible microfacet, or on the surface itself if it's smooth.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        if self.distribution.is_smooth() {
+            return Some(BsdfSample {
+                direction: onb.local(-wo.x(), -wo.y(), wo.z()),
+                bsdf: self.fresnel(wo.z()),
+                pdf: 1.0,
+                specular: true,
+            });
+        }
+        let h = self.distribution.sample(wo, sampler);
+        let wi = reflect(wo, h);
+        if wi.z() <= 0.0 {
+            return None;
+        }
+        let direction = onb.local(wi.x(), wi.y(), wi.z());
+        Some(BsdfSample {
+            direction,
+            bsdf: self.eval(ray, record, direction),
+            pdf: self.scattering_pdf(ray, record, direction),
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> Vec3f<Color> {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        let wi = onb.to_local(direction.unit());
+        if self.distribution.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return Vec3f::repeat(0.0);
+        }
+        let h = (wo + wi).unit();
+        let d = self.distribution.d(h);
+        let g = self.distribution.g(wo, wi);
+        // The cosine of `wi` cancels out
+        self.fresnel(wo.dot(h).abs()) * (d * g / (4.0 * wo.z()))
+    }
+
+    fn scattering_pdf(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> f64 {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        let wi = onb.to_local(direction.unit());
+        if self.distribution.is_smooth() || wo.z() <= 0.0 || wi.z() <= 0.0 {
+            return 0.0;
+        }
+        let h = (wo + wi).unit();
+        // Jacobian of the reflection, from normals to directions
+        self.distribution.visible_pdf(wo, h) / (4.0 * wo.dot(h))
+    }
+}
+
+/// Rough glass, like frosted glass.
+///
+/// Like [`Dielectric`](super::Dielectric), the radiance isn't scaled by the change
+/// of refraction index, so both look the same when the roughness is 0.
+#[derive(Copy, Clone)]
+pub struct RoughDielectric {
+    refraction_index: f64,
+    distribution: Ggx,
+    absorption: Vec3f<Color>,
+    tangent: Vec3f<Position>,
+}
+
+impl RoughDielectric {
+    pub fn new(refraction_index: f64, distribution: Ggx) -> Self {
+        Self {
+            refraction_index,
+            distribution,
+            absorption: Vec3f::repeat(0.0),
+            tangent: TANGENT,
+        }
+    }
+
+    pub fn arc(refraction_index: f64, distribution: Ggx) -> Arc<Self> {
+        Arc::new(Self::new(refraction_index, distribution))
+    }
+
+    /// Absorbs `absorption` of each color per unit of distance inside, like
+    /// [`Dielectric::with_absorption`](super::Dielectric::with_absorption).
+    pub fn with_absorption(self, absorption: Vec3f<Color>) -> Self {
+        Self { absorption, ..self }
+    }
+
+    /// Stretches anisotropic highlights along `tangent`, like [`Conductor::with_tangent`].
+    pub fn with_tangent(self, tangent: Vec3f<Position>) -> Self {
+        Self { tangent, ..self }
+    }
+
+    /// Refraction index of the other side of the surface, relative to the side of the ray.
+    fn eta(&self, record: HitRecord) -> f64 {
+        if record.front_face {
+            self.refraction_index
+        } else {
+            1.0 / self.refraction_index
+        }
+    }
+
+    /// The microfacet that scatters `wo` into `wi`, facing `wo`, and whether it's a
+    /// reflection. `None` if no microfacet can.
+    fn half_vector(
+        wo: Vec3f<Position>,
+        wi: Vec3f<Position>,
+        eta: f64,
+    ) -> Option<(Vec3f<Position>, bool)> {
+        let reflection = wi.z() > 0.0;
+        let h = if reflection { wo + wi } else { wo + eta * wi };
+        if h.near_zero() {
+            return None;
+        }
+        let h = if h.z() < 0.0 { -h.unit() } else { h.unit() };
+        // The microfacet must face `wo`, and face away from `wi` unless reflecting
+        if wo.dot(h) <= 0.0 || (wi.dot(h) > 0.0) != reflection {
+            return None;
+        }
+        Some((h, reflection))
+    }
+
+    /// BSDF times the cosine, and the density, of scattering `wo` into `wi`.
+    fn evaluate(&self, wo: Vec3f<Position>, wi: Vec3f<Position>, eta: f64) -> (f64, f64) {
+        if wo.z() <= 0.0 || wi.z() == 0.0 {
+            return (0.0, 0.0);
+        }
+        let (h, reflection) = match Self::half_vector(wo, wi, eta) {
+            Some(half_vector) => half_vector,
+            None => return (0.0, 0.0),
+        };
+        let fresnel = fresnel_dielectric(wo.dot(h), eta);
+        let d = self.distribution.d(h);
+        let g = self.distribution.g(wo, wi);
+        let visible_pdf = self.distribution.visible_pdf(wo, h);
+        if reflection {
+            let bsdf = fresnel * d * g / (4.0 * wo.z());
+            let pdf = fresnel * visible_pdf / (4.0 * wo.dot(h));
+            (bsdf, pdf)
+        } else {
+            let denominator = (eta * wi.dot(h) + wo.dot(h)).powi(2);
+            // Jacobian of the refraction, from normals to directions
+            let jacobian = eta * eta * wi.dot(h).abs() / denominator;
+            let bsdf = (1.0 - fresnel) * d * g * wo.dot(h) * jacobian / wo.z();
+            let pdf = (1.0 - fresnel) * visible_pdf * jacobian;
+            (bsdf, pdf)
+        }
+    }
+}
+
+impl Material for RoughDielectric {
+    /// Reflects on or refracts through a visible microfacet, chosen by its Fresnel reflectance.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let eta = self.eta(record);
+        let h = if self.distribution.is_smooth() {
+            Vec3f::new(0.0, 0.0, 1.0)
+        } else {
+            self.distribution.sample(wo, sampler)
+        };
+        let fresnel = fresnel_dielectric(wo.dot(h), eta);
+        let wi = if sampler.gen::<f64>() < fresnel {
+            reflect(wo, h)
+        } else {
+            refract(wo, h, eta)?
+        };
+        let direction = onb.local(wi.x(), wi.y(), wi.z());
+        if self.distribution.is_smooth() {
+            return Some(BsdfSample {
+                direction,
+                bsdf: Vec3f::repeat(1.0),
+                pdf: 1.0,
+                specular: true,
+            });
+        }
+        let (bsdf, pdf) = self.evaluate(wo, wi, eta);
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            bsdf: Vec3f::repeat(bsdf),
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> Vec3f<Color> {
+        if self.distribution.is_smooth() {
+            return Vec3f::repeat(0.0);
+        }
+        let (onb, wo) = frame(ray, record, self.tangent);
+        let wi = onb.to_local(direction.unit());
+        Vec3f::repeat(self.evaluate(wo, wi, self.eta(record)).0)
+    }
+
+    fn scattering_pdf(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> f64 {
+        if self.distribution.is_smooth() {
+            return 0.0;
+        }
+        let (onb, wo) = frame(ray, record, self.tangent);
+        let wi = onb.to_local(direction.unit());
+        self.evaluate(wo, wi, self.eta(record)).1
+    }
+
+    fn absorption(&self) -> Option<Vec3f<Color>> {
+        absorbing(self.absorption)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{above, assert_samples_match_eval, surface_hit};
+
+    #[test]
+    fn visible_normals_are_sampled_with_their_density() {
+        let distribution = Ggx::new(0.6, 0.3);
+        let wo = Vec3f::<Position>::new(0.5, 0.3, 0.6).unit();
+        // Bins of equal `z` and angle around it, of equal solid angle
+        let (rings, sectors) = (8, 8);
+        let bin = |h: Vec3f<Position>| {
+            let ring = ((h.z() * rings as f64) as usize).min(rings - 1);
+            let phi = h.y().atan2(h.x()) + PI;
+            let sector = ((phi / (2.0 * PI) * sectors as f64) as usize).min(sectors - 1);
+            ring * sectors + sector
+        };
+        let mut sampler = Sampler::new(0);
+        let samples = 200_000;
+        let mut histogram = vec![0.0; rings * sectors];
+        for _ in 0..samples {
+            histogram[bin(distribution.sample(wo, &mut sampler))] += 1.0 / samples as f64;
+        }
+
+        // The density integrated over each bin
+        let steps = 64;
+        let (dz, dphi) = (1.0 / steps as f64, 2.0 * PI / steps as f64);
+        let mut expected = vec![0.0; rings * sectors];
+        for i in 0..steps {
+            for j in 0..steps {
+                let z = (i as f64 + 0.5) * dz;
+                let phi = (j as f64 + 0.5) * dphi - PI;
+                let r = (1.0 - z * z).sqrt();
+                let h = Vec3f::new(r * phi.cos(), r * phi.sin(), z);
+                expected[bin(h)] += distribution.visible_pdf(wo, h) * dz * dphi;
+            }
+        }
+        assert!((expected.iter().sum::<f64>() - 1.0).abs() < 1e-2);
+        for (bin, (actual, expected)) in histogram.iter().zip(&expected).enumerate() {
+            let error = (actual - expected).abs();
+            assert!(error < 5e-3, "bin {}: {} != {}", bin, actual, expected);
+        }
+    }
+
+    #[test]
+    fn samples_match_eval() {
+        for alpha in [0.05, 0.3, 0.8] {
+            let distribution = Ggx::new(alpha, alpha / 2.0);
+            assert_samples_match_eval(&Conductor::gold(distribution));
+            assert_samples_match_eval(&RoughDielectric::new(1.5, distribution));
+        }
+    }
+
+    #[test]
+    fn reflection_is_reciprocal() {
+        let distribution = Ggx::new(0.4, 0.2);
+        let conductor = Conductor::copper(distribution);
+        let dielectric = RoughDielectric::new(1.5, distribution);
+        let mut sampler = Sampler::new(0);
+        for material in [&conductor as &dyn Material, &dielectric] {
+            for _ in 0..100 {
+                let (wo, wi) = (above(&mut sampler), above(&mut sampler));
+                let (ray, record) = surface_hit(material, wo);
+                let forward = material.eval(ray, record, wi) / wi.z();
+                let (ray, record) = surface_hit(material, wi);
+                let backward = material.eval(ray, record, wo) / wo.z();
+                for channel in 0..3 {
+                    let (f, b) = (forward[channel], backward[channel]);
+                    assert!((f - b).abs() <= 1e-9 * f.max(1.0), "{} != {}", f, b);
+                }
+            }
+        }
+    }
+}
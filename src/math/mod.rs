@@ -1,19 +1,54 @@
 pub mod bound;
+pub mod bvh;
 pub mod camera;
+pub mod csg;
 pub mod hittable;
 pub mod material;
+pub mod matrix;
+pub mod medium;
+pub mod mesh;
+pub mod microfacet;
 pub mod noise;
+pub mod onb;
+pub mod principled;
+pub mod quad;
 pub mod ray;
+pub mod sampler;
+pub mod sdf;
+pub mod shapes;
+pub mod spectrum;
+#[cfg(test)]
+pub(crate) mod test_util;
 pub mod texture;
+pub mod transform;
 pub mod vec3;
 
 pub use bound::Bound;
+pub use bvh::{BvhStats, BvhTree, SplitMethod};
 pub use camera::{Camera, CameraDescriptor};
+pub use csg::{Csg, CsgOperation};
 pub use hittable::{
-    BvhTree, HitRecord, Hittable, List, MovingSphere, Rectangle, Sphere, Xy, Xz, Yz, Plane, Cube, Translate, ConstantMedium
+    HitRecord, Hittable, Light, LightShape, LightTransform, Lights, List, MovingSphere, Rectangle, Sphere, Xy, Xz, Yz, Plane, Cube, Translate, YRotate, ConstantMedium
 };
-pub use material::{Dielectric, DiffuseLight, Lambertian, Material, Metal, Isotropic};
+pub use material::{
+    BsdfSample, Dielectric, DiffuseLight, DoubleHenyeyGreenstein, HenyeyGreenstein, Isotropic,
+    Lambertian, Material, Metal, RefractionIndex,
+};
+pub use matrix::{Matrix4, Quaternion};
+pub use medium::{Density, DensityFunction, HeterogeneousMedium, NoiseDensity, VoxelGrid};
+pub use mesh::{Triangle, TriangleMesh};
+pub use microfacet::{Conductor, Ggx, RoughDielectric};
 pub use noise::Perlin;
+pub use onb::Onb;
+pub use principled::Principled;
+pub use quad::{Quad, QuadShape};
 pub use ray::Ray;
+pub use sampler::Sampler;
+pub use sdf::{
+    Blend, Sdf, SdfBox, SdfCapsule, SdfShape, SdfSphere, SdfTorus, SmoothSubtraction, SmoothUnion,
+};
+pub use shapes::{Cone, Cylinder, Disk, Torus};
+pub use spectrum::{SampledSpectrum, SampledWavelengths};
 pub use texture::{CheckerTexture, ImageTexture, NoiseTexture, Texture};
+pub use transform::{AnimatedTransform, Keyframe, Transform};
 pub use vec3::{Color, Coordinate, Position, Vec3f};
@@ -12,37 +12,35 @@ pub struct Perlin {
 impl Perlin {
     const POINT_COUNT: usize = 256;
 
-    fn perlin_generate() -> Vec<Vec3f<Position>> {
+    fn perlin_generate<R: Rng + ?Sized>(rng: &mut R) -> Vec<Vec3f<Position>> {
         (0..Self::POINT_COUNT)
-            .map(|_| Vec3f::random(-1. ..1.))
+            .map(|_| Vec3f::random(-1. ..1., rng))
             .collect()
     }
 
-    fn generate_permutation() -> Vec<usize> {
-        let mut rng = rand::thread_rng();
+    fn generate_permutation<R: Rng + ?Sized>(rng: &mut R) -> Vec<usize> {
         let mut points: Vec<usize> = (0..Self::POINT_COUNT).collect();
 
-        points.shuffle(&mut rng);
-        // Self::permute(&mut points);
+        points.shuffle(rng);
+        // Self::permute(&mut points, rng);
 
         points
     }
 
     #[allow(dead_code)]
-    fn permute(points: &mut [usize]) {
-        let mut rng = rand::thread_rng();
+    fn permute<R: Rng + ?Sized>(points: &mut [usize], rng: &mut R) {
         for i in (0..points.len()).rev() {
             let target = rng.gen_range(0..i + 1);
             points.swap(i, target);
         }
     }
 
-    pub fn new() -> Self {
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
         Self {
-            random_points: Self::perlin_generate(),
-            x: Self::generate_permutation(),
-            y: Self::generate_permutation(),
-            z: Self::generate_permutation(),
+            random_points: Self::perlin_generate(rng),
+            x: Self::generate_permutation(rng),
+            y: Self::generate_permutation(rng),
+            z: Self::generate_permutation(rng),
         }
     }
 
@@ -0,0 +1,70 @@
+use super::{Position, Vec3f};
+
+/// Orthonormal basis, to express directions relative to a normal.
+#[derive(Copy, Clone)]
+pub struct Onb {
+    pub u: Vec3f<Position>,
+    pub v: Vec3f<Position>,
+    pub w: Vec3f<Position>,
+}
+
+impl Onb {
+    /// A basis whose `w` axis points to `n`.
+    pub fn from_w(n: Vec3f<Position>) -> Self {
+        let w = n.unit();
+        let a = if w.x().abs() > 0.9 {
+            Vec3f::new(0.0, 1.0, 0.0)
+        } else {
+            Vec3f::new(1.0, 0.0, 0.0)
+        };
+        let v = w.cross(a).unit();
+        let u = w.cross(v);
+        Self { u, v, w }
+    }
+
+    /// A basis whose `w` axis points to `n`, and `u` axis to the part of `tangent` along
+    /// the plane perpendicular to `n`. The same as [`Onb::from_w`] where they are parallel.
+    pub fn from_w_u(n: Vec3f<Position>, tangent: Vec3f<Position>) -> Self {
+        let w = n.unit();
+        let tangent = tangent.unit();
+        let u = tangent - w * tangent.dot(w);
+        if u.squared_length() < 1e-12 {
+            return Self::from_w(n);
+        }
+        let u = u.unit();
+        let v = w.cross(u);
+        Self { u, v, w }
+    }
+
+    /// The vector with coordinates `(x, y, z)` in this basis.
+    pub fn local(&self, x: f64, y: f64, z: f64) -> Vec3f<Position> {
+        x * self.u + y * self.v + z * self.w
+    }
+
+    /// The coordinates of `a` in this basis.
+    pub fn to_local(&self, a: Vec3f<Position>) -> Vec3f<Position> {
+        Vec3f::new(a.dot(self.u), a.dot(self.v), a.dot(self.w))
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn tangent_basis_is_continuous() {
+        let tangent = Vec3f::new(0.0, 1.0, 0.0);
+        let normal = |x: f64| Vec3f::new(x, 0.0, (1.0 - x * x).sqrt());
+        // `from_w` switches its helper axis between these two normals
+        let (a, b) = (
+            Onb::from_w_u(normal(0.899), tangent),
+            Onb::from_w_u(normal(0.901), tangent),
+        );
+        assert!((a.u - b.u).magnitude() < 1e-2);
+        assert!((a.v - b.v).magnitude() < 1e-2);
+        for onb in [a, b] {
+            assert!((onb.u.dot(onb.w)).abs() < 1e-12);
+            assert!((onb.u.cross(onb.v) - onb.w).magnitude() < 1e-12);
+        }
+    }
+}
@@ -0,0 +1,385 @@
+//! A Disney-style principled material, blending diffuse, specular and transmissive lobes.
+
+use rand::Rng;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::microfacet::{frame, reflect, TANGENT};
+use super::{
+    BsdfSample, Color, Ggx, HitRecord, Material, Position, Ray, RoughDielectric, Sampler, Texture,
+    Vec3f,
+};
+
+/// Smallest `alpha` of the lobes, so they are never perfect mirrors and can
+/// always be mixed with each other.
+const MIN_ALPHA: f64 = 2e-3;
+
+/// Roughness of the clear coat.
+const CLEARCOAT_ALPHA: f64 = 0.05;
+
+/// One material for most surfaces, from plastic and metal to glass, driven by artist-friendly
+/// parameters from 0 to 1. Every parameter is a texture; scalar parameters are the mean of
+/// the channels of their texture.
+///
+/// ```
+/// use rttnw::math::{Color, Principled, Vec3f};
+/// use std::sync::Arc;
+///
+/// let brushed_gold = Principled {
+///     metallic: Arc::new(Vec3f::<Color>::repeat(1.0)),
+///     roughness: Arc::new(Vec3f::<Color>::repeat(0.3)),
+///     ..Principled::new(Arc::new(Vec3f::<Color>::new(1.0, 0.78, 0.34)))
+/// };
+/// # let _ = brushed_gold;
+/// ```
+#[derive(Clone)]
+pub struct Principled {
+    pub base_color: Arc<dyn Texture>,
+    /// 0 for dielectrics and 1 for metals, tinted by the base color.
+    pub metallic: Arc<dyn Texture>,
+    pub roughness: Arc<dyn Texture>,
+    /// Strength of the reflections of dielectrics. 0.5 is a reflectance of 4%.
+    pub specular: Arc<dyn Texture>,
+    /// A second, white and glossy specular layer, like varnish.
+    pub clearcoat: Arc<dyn Texture>,
+    /// Soft white reflection at grazing angles, like cloth.
+    pub sheen: Arc<dyn Texture>,
+    /// 0 for opaque dielectrics and 1 for glass, tinted by the base color on the way through.
+    pub transmission: Arc<dyn Texture>,
+    pub emission: Option<Arc<dyn Texture>>,
+    /// Of the transmissive lobe.
+    pub refraction_index: f64,
+    /// From 0 to 1, stretches the specular highlights along `tangent`.
+    pub anisotropy: f64,
+    /// Projected on the surface. By default it's the Y axis.
+    pub tangent: Vec3f<Position>,
+}
+
+/// The parameters at a point of the surface.
+struct Parameters {
+    base_color: Vec3f<Color>,
+    metallic: f64,
+    specular: f64,
+    clearcoat: f64,
+    sheen: f64,
+    transmission: f64,
+    /// Roughness of the diffuse lobe
+    roughness: f64,
+    distribution: Ggx,
+}
+
+impl Parameters {
+    /// Weight of the diffuse and sheen lobes.
+    fn diffuse(&self) -> f64 {
+        (1.0 - self.metallic) * (1.0 - self.transmission)
+    }
+
+    /// Weight of the transmissive lobe.
+    fn transmissive(&self) -> f64 {
+        (1.0 - self.metallic) * self.transmission
+    }
+
+    /// Weight of the specular reflection lobe. The transmissive lobe reflects on its own.
+    fn reflective(&self) -> f64 {
+        1.0 - self.transmissive()
+    }
+
+    /// Weight of the clear coat lobe.
+    fn coat(&self) -> f64 {
+        0.25 * self.clearcoat
+    }
+
+    /// Probabilities of sampling the diffuse, reflective, transmissive and clear coat lobes.
+    fn probabilities(&self) -> [f64; 4] {
+        let weights = [
+            self.diffuse(),
+            self.reflective(),
+            self.transmissive(),
+            self.coat(),
+        ];
+        let total: f64 = weights.iter().sum();
+        weights.map(|weight| weight / total)
+    }
+
+    /// Reflectance at normal incidence of the specular lobe.
+    fn f0(&self) -> Vec3f<Color> {
+        let dielectric = Vec3f::repeat(0.08 * self.specular);
+        dielectric * (1.0 - self.metallic) + self.base_color * self.metallic
+    }
+
+    fn dielectric(&self, refraction_index: f64, tangent: Vec3f<Position>) -> RoughDielectric {
+        RoughDielectric::new(refraction_index, self.distribution).with_tangent(tangent)
+    }
+}
+
+/// Schlick's approximation of the Fresnel factor, without the reflectance at normal incidence.
+fn schlick_weight(cosine: f64) -> f64 {
+    (1.0 - cosine).clamp(0.0, 1.0).powi(5)
+}
+
+fn mean(color: Vec3f<Color>) -> f64 {
+    (color.r() + color.g() + color.b()) / 3.0
+}
+
+impl Principled {
+    /// A rough white dielectric with the color `base_color`.
+    pub fn new(base_color: Arc<dyn Texture>) -> Self {
+        let constant = |x: f64| -> Arc<dyn Texture> { Arc::new(Vec3f::<Color>::repeat(x)) };
+        Self {
+            base_color,
+            metallic: constant(0.0),
+            roughness: constant(0.5),
+            specular: constant(0.5),
+            clearcoat: constant(0.0),
+            sheen: constant(0.0),
+            transmission: constant(0.0),
+            emission: None,
+            refraction_index: 1.5,
+            anisotropy: 0.0,
+            tangent: TANGENT,
+        }
+    }
+
+    fn parameters(&self, record: HitRecord) -> Parameters {
+        let scalar = |texture: &Arc<dyn Texture>| {
+            mean(texture.value(record.u, record.v, record.p)).clamp(0.0, 1.0)
+        };
+        let roughness = scalar(&self.roughness);
+        let distribution = Ggx::from_roughness(roughness, self.anisotropy);
+        Parameters {
+            base_color: self.base_color.value(record.u, record.v, record.p),
+            metallic: scalar(&self.metallic),
+            specular: scalar(&self.specular),
+            clearcoat: scalar(&self.clearcoat),
+            sheen: scalar(&self.sheen),
+            transmission: scalar(&self.transmission),
+            roughness,
+            distribution: Ggx::new(
+                distribution.alpha_x.max(MIN_ALPHA),
+                distribution.alpha_y.max(MIN_ALPHA),
+            ),
+        }
+    }
+
+    /// BSDF times the cosine, and the density of sampling it, from `wo` to `wi` in the
+    /// shading basis. `dielectric` evaluates the same of the transmissive lobe, in world space.
+    fn evaluate(
+        &self,
+        parameters: &Parameters,
+        wo: Vec3f<Position>,
+        wi: Vec3f<Position>,
+        dielectric: impl Fn(RoughDielectric) -> (Vec3f<Color>, f64),
+    ) -> (Vec3f<Color>, f64) {
+        let mut bsdf = Vec3f::repeat(0.0);
+        let mut pdf = 0.0;
+        if wo.z() <= 0.0 {
+            return (bsdf, pdf);
+        }
+        let [p_diffuse, p_reflective, p_transmissive, p_coat] = parameters.probabilities();
+        // What the clear coat doesn't reflect reaches the layers under it, and what the
+        // specular layer of dielectrics doesn't reflect reaches the diffuse one.
+        let under_coat = 1.0 - parameters.coat() * (0.04 + 0.96 * schlick_weight(wo.z()));
+        let f0 = 0.08 * parameters.specular;
+        let under_specular = 1.0 - (f0 + (1.0 - f0) * schlick_weight(wo.z()));
+        if wi.z() > 0.0 {
+            let h = (wo + wi).unit();
+            let cos_d = wi.dot(h);
+
+            // Burley's diffuse, with its retro-reflection at grazing angles, renormalized
+            // as in Frostbite so it never reflects more light than it receives, and the sheen
+            let roughness = parameters.roughness;
+            let fd90 = 0.5 * roughness + 2.0 * roughness * cos_d * cos_d;
+            let fd = (1.0 + (fd90 - 1.0) * schlick_weight(wi.z()))
+                * (1.0 + (fd90 - 1.0) * schlick_weight(wo.z()))
+                * (1.0 - roughness * (1.0 - 1.0 / 1.51));
+            let diffuse = parameters.base_color * (fd / PI)
+                + Vec3f::repeat(parameters.sheen * schlick_weight(cos_d));
+            let weight = parameters.diffuse() * under_specular * under_coat;
+            bsdf = bsdf + diffuse * (weight * wi.z());
+            pdf += p_diffuse * wi.z() / PI;
+
+            // The cosine of `wi` cancels out
+            let distribution = parameters.distribution;
+            let f0 = parameters.f0();
+            let fresnel = f0 + (Vec3f::repeat(1.0) - f0) * schlick_weight(cos_d);
+            let specular = distribution.d(h) * distribution.g(wo, wi) / (4.0 * wo.z());
+            bsdf = bsdf + fresnel * (parameters.reflective() * under_coat * specular);
+            pdf += p_reflective * distribution.visible_pdf(wo, h) / (4.0 * cos_d);
+
+            let coat = Ggx::isotropic(CLEARCOAT_ALPHA);
+            let fresnel = 0.04 + 0.96 * schlick_weight(cos_d);
+            let specular = fresnel * coat.d(h) * coat.g(wo, wi) / (4.0 * wo.z());
+            bsdf = bsdf + Vec3f::repeat(parameters.coat() * specular);
+            pdf += p_coat * coat.visible_pdf(wo, h) / (4.0 * cos_d);
+        }
+        if p_transmissive > 0.0 {
+            let (transmitted, transmitted_pdf) =
+                dielectric(parameters.dielectric(self.refraction_index, self.tangent));
+            // Only the light that goes through is tinted, by half at each of the two
+            // interfaces of a solid object, so it's tinted by the base color once.
+            let tint = if wi.z() < 0.0 {
+                parameters.base_color.map(f64::sqrt)
+            } else {
+                Vec3f::repeat(1.0)
+            };
+            bsdf = bsdf + tint * transmitted * (parameters.transmissive() * under_coat);
+            pdf += p_transmissive * transmitted_pdf;
+        }
+        (bsdf, pdf)
+    }
+}
+
+impl Material for Principled {
+    /// Samples one of the lobes, chosen by their weights.
+    fn sample(&self, ray: Ray, record: HitRecord, sampler: &mut Sampler) -> Option<BsdfSample> {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        if wo.z() <= 0.0 {
+            return None;
+        }
+        let parameters = self.parameters(record);
+        let [p_diffuse, p_reflective, p_transmissive, _] = parameters.probabilities();
+        let choice = sampler.gen::<f64>();
+        let direction = if choice < p_diffuse {
+            let direction = record.normal + Vec3f::random_unit_vector(sampler);
+            if direction.near_zero() {
+                record.normal
+            } else {
+                direction
+            }
+        } else if choice < p_diffuse + p_reflective {
+            let h = parameters.distribution.sample(wo, sampler);
+            let wi = reflect(wo, h);
+            onb.local(wi.x(), wi.y(), wi.z())
+        } else if choice < p_diffuse + p_reflective + p_transmissive {
+            parameters
+                .dielectric(self.refraction_index, self.tangent)
+                .sample(ray, record, sampler)?
+                .direction
+        } else {
+            let h = Ggx::isotropic(CLEARCOAT_ALPHA).sample(wo, sampler);
+            let wi = reflect(wo, h);
+            onb.local(wi.x(), wi.y(), wi.z())
+        };
+        let wi = onb.to_local(direction.unit());
+        let (bsdf, pdf) = self.evaluate(&parameters, wo, wi, |dielectric| {
+            (
+                dielectric.eval(ray, record, direction),
+                dielectric.scattering_pdf(ray, record, direction),
+            )
+        });
+        if pdf <= 0.0 {
+            return None;
+        }
+        Some(BsdfSample {
+            direction,
+            bsdf,
+            pdf,
+            specular: false,
+        })
+    }
+
+    fn eval(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> Vec3f<Color> {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        let wi = onb.to_local(direction.unit());
+        let parameters = self.parameters(record);
+        self.evaluate(&parameters, wo, wi, |dielectric| {
+            (dielectric.eval(ray, record, direction), 0.0)
+        })
+        .0
+    }
+
+    fn scattering_pdf(&self, ray: Ray, record: HitRecord, direction: Vec3f<Position>) -> f64 {
+        let (onb, wo) = frame(ray, record, self.tangent);
+        let wi = onb.to_local(direction.unit());
+        let parameters = self.parameters(record);
+        self.evaluate(&parameters, wo, wi, |dielectric| {
+            (
+                Vec3f::repeat(0.0),
+                dielectric.scattering_pdf(ray, record, direction),
+            )
+        })
+        .1
+    }
+
+    fn emitted(&self, u: f64, v: f64, point: Vec3f<Position>) -> Vec3f<Color> {
+        match &self.emission {
+            Some(emission) => emission.value(u, v, point),
+            None => Vec3f::repeat(0.0),
+        }
+    }
+
+    fn is_emissive(&self) -> bool {
+        self.emission.is_some()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{albedo, assert_samples_match_eval};
+
+    fn constant(x: f64) -> Arc<dyn Texture> {
+        Arc::new(Vec3f::<Color>::repeat(x))
+    }
+
+    fn white() -> Principled {
+        Principled::new(constant(1.0))
+    }
+
+    /// White materials with each lobe, and the roughest and smoothest surfaces.
+    fn materials() -> Vec<Principled> {
+        vec![
+            white(),
+            Principled {
+                roughness: constant(0.0),
+                ..white()
+            },
+            Principled {
+                roughness: constant(1.0),
+                sheen: constant(1.0),
+                ..white()
+            },
+            Principled {
+                clearcoat: constant(1.0),
+                ..white()
+            },
+            Principled {
+                metallic: constant(1.0),
+                anisotropy: 0.8,
+                ..white()
+            },
+            Principled {
+                transmission: constant(1.0),
+                ..white()
+            },
+        ]
+    }
+
+    #[test]
+    fn samples_match_eval() {
+        for material in materials() {
+            assert_samples_match_eval(&material);
+        }
+    }
+
+    #[test]
+    fn white_furnace() {
+        let opaque = [0.0, 0.5, 1.0].map(|roughness| Principled {
+            roughness: constant(roughness),
+            ..white()
+        });
+        let coated = opaque.clone().map(|material| Principled {
+            clearcoat: constant(1.0),
+            ..material
+        });
+        for material in opaque.iter().chain(&coated) {
+            for cosine in [1.0f64, 0.5, 0.2, 0.05] {
+                let from = Vec3f::new((1.0 - cosine * cosine).sqrt(), 0.0, cosine);
+                let reflected = albedo(material, from).r();
+                // Within the error of the estimate
+                assert!(reflected < 1.01, "{} at {}", reflected, cosine);
+            }
+        }
+    }
+}
@@ -0,0 +1,301 @@
+use rand::Rng;
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::{
+    Bound, HitRecord, Hittable, Light, LightShape, Material, Position, Ray, Sampler, Vec3f,
+};
+
+/// The region of its plane that a [`Quad`] covers, in the coordinates `(a, b)`
+/// of the point `origin + a * u + b * v`.
+#[derive(Copy, Clone, Debug, Eq, PartialEq)]
+pub enum QuadShape {
+    /// `origin` is a corner and `u` and `v` are the edges from it.
+    Parallelogram,
+    /// `origin` is a vertex and `u` and `v` are the edges from it.
+    Triangle,
+    /// `origin` is the center and `u` and `v` the radii along two axes.
+    /// Perpendicular radii of the same length make a circle.
+    Disk,
+}
+
+impl QuadShape {
+    fn contains(self, a: f64, b: f64) -> bool {
+        match self {
+            QuadShape::Parallelogram => (0.0..=1.0).contains(&a) && (0.0..=1.0).contains(&b),
+            QuadShape::Triangle => a >= 0.0 && b >= 0.0 && a + b <= 1.0,
+            QuadShape::Disk => a * a + b * b <= 1.0,
+        }
+    }
+
+    /// Area of the shape, relative to the parallelogram of `u` and `v`.
+    fn relative_area(self) -> f64 {
+        match self {
+            QuadShape::Parallelogram => 1.0,
+            QuadShape::Triangle => 0.5,
+            QuadShape::Disk => PI,
+        }
+    }
+
+    /// Texture coordinates of a point of the shape. Disks use polar coordinates:
+    /// `u` is the angle from the `u` radius and `v` the distance to the center.
+    fn uv(self, a: f64, b: f64) -> (f64, f64) {
+        match self {
+            QuadShape::Parallelogram | QuadShape::Triangle => (a, b),
+            QuadShape::Disk => (
+                b.atan2(a).rem_euclid(2.0 * PI) / (2.0 * PI),
+                (a * a + b * b).sqrt(),
+            ),
+        }
+    }
+
+    /// A point uniformly distributed over the area of the shape.
+    fn sample(self, sampler: &mut Sampler) -> (f64, f64) {
+        let (a, b): (f64, f64) = (sampler.gen(), sampler.gen());
+        match self {
+            QuadShape::Parallelogram => (a, b),
+            // Points of the other half are reflected into the triangle
+            QuadShape::Triangle if a + b > 1.0 => (1.0 - a, 1.0 - b),
+            QuadShape::Triangle => (a, b),
+            QuadShape::Disk => {
+                let (radius, angle) = (a.sqrt(), 2.0 * PI * b);
+                (radius * angle.cos(), radius * angle.sin())
+            }
+        }
+    }
+}
+
+/// A flat shape in any orientation, described by a point and two edge vectors.
+pub struct Quad {
+    pub origin: Vec3f<Position>,
+    pub u: Vec3f<Position>,
+    pub v: Vec3f<Position>,
+    pub shape: QuadShape,
+    pub material: Arc<dyn Material>,
+    /// Unit normal of the plane, on the side of `u × v`.
+    normal: Vec3f<Position>,
+    /// Distance of the plane to the origin of the world along `normal`.
+    d: f64,
+    /// Projects a point of the plane into the coordinates of `u` and `v`.
+    w: Vec3f<Position>,
+    area: f64,
+}
+
+impl Quad {
+    /// A parallelogram with a corner at `origin` and edges `u` and `v`.
+    pub fn new(
+        origin: Vec3f<Position>,
+        u: Vec3f<Position>,
+        v: Vec3f<Position>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self::with_shape(QuadShape::Parallelogram, origin, u, v, material)
+    }
+
+    /// A triangle with a vertex at `origin` and edges `u` and `v`.
+    pub fn triangle(
+        origin: Vec3f<Position>,
+        u: Vec3f<Position>,
+        v: Vec3f<Position>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self::with_shape(QuadShape::Triangle, origin, u, v, material)
+    }
+
+    /// An ellipse centered at `center` with radii `u` and `v`.
+    pub fn disk(
+        center: Vec3f<Position>,
+        u: Vec3f<Position>,
+        v: Vec3f<Position>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        Self::with_shape(QuadShape::Disk, center, u, v, material)
+    }
+
+    pub fn with_shape(
+        shape: QuadShape,
+        origin: Vec3f<Position>,
+        u: Vec3f<Position>,
+        v: Vec3f<Position>,
+        material: Arc<dyn Material>,
+    ) -> Self {
+        let n = u.cross(v);
+        let normal = n.unit();
+        Self {
+            origin,
+            u,
+            v,
+            shape,
+            material,
+            normal,
+            d: normal.dot(origin),
+            w: n / n.dot(n),
+            area: n.magnitude() * shape.relative_area(),
+        }
+    }
+
+    pub fn area(&self) -> f64 {
+        self.area
+    }
+
+    pub fn normal(&self) -> Vec3f<Position> {
+        self.normal
+    }
+}
+
+impl Hittable for Quad {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let denominator = self.normal.dot(ray.direction());
+        // The ray is parallel to the plane
+        if denominator.abs() < 1e-12 {
+            return None;
+        }
+        let t = (self.d - self.normal.dot(ray.origin())) / denominator;
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = ray.point_at_parameter(t);
+        let planar = p - self.origin;
+        let a = self.w.dot(planar.cross(self.v));
+        let b = self.w.dot(self.u.cross(planar));
+        if !self.shape.contains(a, b) {
+            return None;
+        }
+        let (u, v) = self.shape.uv(a, b);
+        let (normal, front_face) = HitRecord::face_normal(ray, self.normal);
+        Some(HitRecord {
+            t,
+            p,
+            normal,
+            material: self.material.as_ref(),
+            u,
+            v,
+            front_face,
+        })
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        let (min, max) = match self.shape {
+            QuadShape::Parallelogram | QuadShape::Triangle => {
+                // Triangles have no fourth corner, their origin is counted twice instead
+                let fourth = if self.shape == QuadShape::Parallelogram {
+                    self.origin + self.u + self.v
+                } else {
+                    self.origin
+                };
+                let corners = [
+                    self.origin,
+                    self.origin + self.u,
+                    self.origin + self.v,
+                    fourth,
+                ];
+                let mut min = Vec3f::repeat(f64::INFINITY);
+                let mut max = Vec3f::repeat(f64::NEG_INFINITY);
+                for corner in corners {
+                    for coord in 0..3 {
+                        min[coord] = min[coord].min(corner[coord]);
+                        max[coord] = max[coord].max(corner[coord]);
+                    }
+                }
+                (min, max)
+            }
+            QuadShape::Disk => {
+                // Farthest the ellipse gets from its center along each axis
+                let extent = Vec3f::new(
+                    self.u.x().hypot(self.v.x()),
+                    self.u.y().hypot(self.v.y()),
+                    self.u.z().hypot(self.v.z()),
+                );
+                (self.origin - extent, self.origin + extent)
+            }
+        };
+        Some(Bound { min, max }.padded(Bound::FLAT_PADDING))
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        if self.material.is_emissive() {
+            lights.push(Light::new(self));
+        }
+    }
+}
+
+impl LightShape for Quad {
+    /// Uniform over the area of the shape.
+    fn pdf_value(&self, ray: Ray, sampler: &mut Sampler) -> f64 {
+        let record = match self.hit(ray, 0.001, f64::INFINITY, sampler) {
+            Some(record) => record,
+            None => return 0.0,
+        };
+        let distance_squared = record.t.powi(2) * ray.direction().squared_length();
+        let cosine = (self.normal.dot(ray.direction()) / ray.direction().magnitude()).abs();
+        distance_squared / (cosine * self.area)
+    }
+
+    fn random(&self, origin: Vec3f<Position>, sampler: &mut Sampler) -> Vec3f<Position> {
+        let (a, b) = self.shape.sample(sampler);
+        self.origin + a * self.u + b * self.v - origin
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::gray;
+
+    fn quads() -> [Quad; 3] {
+        let origin = Vec3f::new(-0.5, 0.25, 0.0);
+        let (u, v) = (Vec3f::new(1.0, 0.2, 0.0), Vec3f::new(-0.3, 0.8, 0.1));
+        [
+            Quad::new(origin, u, v, gray()),
+            Quad::triangle(origin, u, v, gray()),
+            Quad::disk(origin, u, v, gray()),
+        ]
+    }
+
+    #[test]
+    fn sampled_directions_hit_the_quad() {
+        let origin = Vec3f::new(0.5, -0.5, 2.0);
+        let mut sampler = Sampler::new(0);
+        for quad in quads() {
+            for _ in 0..1000 {
+                let ray = Ray {
+                    a: origin,
+                    b: quad.random(origin, &mut sampler),
+                    ..Ray::default()
+                };
+                let hit = quad.hit(ray, 0.001, f64::INFINITY, &mut sampler);
+                assert!(hit.is_some(), "{:?}", quad.shape);
+                assert!(quad.pdf_value(ray, &mut sampler) > 0.0);
+            }
+        }
+    }
+
+    #[test]
+    fn densities_integrate_to_one() {
+        let origin = Vec3f::new(0.5, -0.5, 1.0);
+        let mut sampler = Sampler::new(0);
+        let samples = 400_000;
+        for quad in quads() {
+            // Over the sphere of directions, sampled uniformly
+            let mut sum = 0.0;
+            for _ in 0..samples {
+                let ray = Ray {
+                    a: origin,
+                    b: Vec3f::random_unit_vector(&mut sampler),
+                    ..Ray::default()
+                };
+                sum += quad.pdf_value(ray, &mut sampler);
+            }
+            let integral = sum / samples as f64 * 4.0 * PI;
+            let error = (integral - 1.0).abs();
+            assert!(error < 2e-2, "{:?}: {}", quad.shape, integral);
+        }
+    }
+}
@@ -10,6 +10,8 @@ pub struct Ray {
     pub a: Vec3f<Position>,
     pub b: Vec3f<Position>,
     pub time: f64,
+    /// Wavelength in nanometers when rendering spectrally, `None` when rendering in RGB.
+    pub wavelength: Option<f64>,
 }
 
 impl Ray {
@@ -0,0 +1,51 @@
+use rand::rngs::SmallRng;
+use rand::{Error, RngCore, SeedableRng};
+
+/// Source of the random numbers used while rendering.
+///
+/// Every sample of every pixel has its own sampler, seeded from the pixel and the sample index,
+/// so the image does not depend on the order in which the threads render the pixels.
+#[derive(Clone)]
+pub struct Sampler {
+    rng: SmallRng,
+}
+
+impl Sampler {
+    pub fn new(seed: u64) -> Self {
+        Self {
+            rng: SmallRng::seed_from_u64(seed),
+        }
+    }
+
+    /// The sampler of the sample number `sample` of the pixel number `pixel`.
+    pub fn for_sample(seed: u64, pixel: u64, sample: u64) -> Self {
+        let seed = Self::mix(Self::mix(Self::mix(seed) ^ pixel) ^ sample);
+        Self::new(seed)
+    }
+
+    /// SplitMix64 finalizer, spreads consecutive integers over the whole `u64` range.
+    fn mix(x: u64) -> u64 {
+        let x = x.wrapping_add(0x9E37_79B9_7F4A_7C15);
+        let x = (x ^ (x >> 30)).wrapping_mul(0xBF58_476D_1CE4_E5B9);
+        let x = (x ^ (x >> 27)).wrapping_mul(0x94D0_49BB_1331_11EB);
+        x ^ (x >> 31)
+    }
+}
+
+impl RngCore for Sampler {
+    fn next_u32(&mut self) -> u32 {
+        self.rng.next_u32()
+    }
+
+    fn next_u64(&mut self) -> u64 {
+        self.rng.next_u64()
+    }
+
+    fn fill_bytes(&mut self, dest: &mut [u8]) {
+        self.rng.fill_bytes(dest)
+    }
+
+    fn try_fill_bytes(&mut self, dest: &mut [u8]) -> Result<(), Error> {
+        self.rng.try_fill_bytes(dest)
+    }
+}
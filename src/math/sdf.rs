@@ -0,0 +1,422 @@
+//! Shapes described by signed distance functions, intersected by sphere tracing.
+
+use std::sync::Arc;
+
+use super::{Bound, HitRecord, Hittable, Material, Position, Ray, Sampler, Vec3f};
+
+/// Most steps along a ray before it's considered to miss the shape.
+const MAX_STEPS: usize = 512;
+
+/// Directions of the finite differences that estimate the normal.
+const TETRAHEDRON: [(f64, f64, f64); 4] = [
+    (1.0, -1.0, -1.0),
+    (-1.0, -1.0, 1.0),
+    (-1.0, 1.0, -1.0),
+    (1.0, 1.0, 1.0),
+];
+
+/// Signed distance from a point to a surface: negative inside and positive outside.
+///
+/// The distance may be underestimated, but never overestimated, or sphere tracing
+/// could step through the surface.
+pub trait Sdf: Send + Sync {
+    fn distance(&self, p: Vec3f<Position>) -> f64;
+
+    /// A box that contains the whole shape, if it's known.
+    fn bound(&self) -> Option<Bound> {
+        None
+    }
+
+    /// Union that blends the surfaces where they are closer than `k`, which must be positive.
+    fn smooth_union<S: Sdf>(self, other: S, k: f64) -> SmoothUnion<Self, S>
+    where
+        Self: Sized,
+    {
+        SmoothUnion {
+            left: self,
+            right: other,
+            k,
+        }
+    }
+
+    /// Removes `other` from the shape, rounding the edges by a positive `k`.
+    fn smooth_subtraction<S: Sdf>(self, other: S, k: f64) -> SmoothSubtraction<Self, S>
+    where
+        Self: Sized,
+    {
+        SmoothSubtraction {
+            left: self,
+            right: other,
+            k,
+        }
+    }
+
+    /// Morphs the shape into `other`, from 0 to 1.
+    fn blend<S: Sdf>(self, other: S, t: f64) -> Blend<Self, S>
+    where
+        Self: Sized,
+    {
+        Blend {
+            left: self,
+            right: other,
+            t,
+        }
+    }
+}
+
+impl<F> Sdf for F
+where
+    F: Fn(Vec3f<Position>) -> f64 + Send + Sync,
+{
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        self(p)
+    }
+}
+
+impl Sdf for Arc<dyn Sdf> {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        self.as_ref().distance(p)
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        self.as_ref().bound()
+    }
+}
+
+fn cube(center: Vec3f<Position>, half_size: Vec3f<Position>) -> Bound {
+    Bound {
+        min: center - half_size,
+        max: center + half_size,
+    }
+}
+
+fn lerp(a: f64, b: f64, t: f64) -> f64 {
+    a + t * (b - a)
+}
+
+pub struct SdfSphere {
+    pub center: Vec3f<Position>,
+    pub radius: f64,
+}
+
+impl Sdf for SdfSphere {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        (p - self.center).magnitude() - self.radius
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        Some(cube(self.center, Vec3f::repeat(self.radius)))
+    }
+}
+
+/// An axis-aligned box, with the corners rounded by `radius`.
+/// The rounded box is as big as the box with sharp corners.
+pub struct SdfBox {
+    pub center: Vec3f<Position>,
+    /// Half of the size of each side
+    pub half_size: Vec3f<Position>,
+    pub radius: f64,
+}
+
+impl Sdf for SdfBox {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        let p = p - self.center;
+        let q = Vec3f::<Position>::new(
+            p.x().abs() - self.half_size.x() + self.radius,
+            p.y().abs() - self.half_size.y() + self.radius,
+            p.z().abs() - self.half_size.z() + self.radius,
+        );
+        let outside = Vec3f::<Position>::new(q.x().max(0.0), q.y().max(0.0), q.z().max(0.0));
+        let inside = q.x().max(q.y()).max(q.z()).min(0.0);
+        outside.magnitude() + inside - self.radius
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        Some(cube(self.center, self.half_size))
+    }
+}
+
+/// A torus around the Y axis.
+pub struct SdfTorus {
+    pub center: Vec3f<Position>,
+    pub major_radius: f64,
+    pub minor_radius: f64,
+}
+
+impl Sdf for SdfTorus {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        let p = p - self.center;
+        let ring = p.x().hypot(p.z()) - self.major_radius;
+        ring.hypot(p.y()) - self.minor_radius
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        let outer = self.major_radius + self.minor_radius;
+        Some(cube(
+            self.center,
+            Vec3f::new(outer, self.minor_radius, outer),
+        ))
+    }
+}
+
+/// The points within `radius` of the segment from `a` to `b`.
+pub struct SdfCapsule {
+    pub a: Vec3f<Position>,
+    pub b: Vec3f<Position>,
+    pub radius: f64,
+}
+
+impl Sdf for SdfCapsule {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        let (pa, ba) = (p - self.a, self.b - self.a);
+        let h = (pa.dot(ba) / ba.dot(ba)).clamp(0.0, 1.0);
+        (pa - h * ba).magnitude() - self.radius
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        let radius = Vec3f::repeat(self.radius);
+        Some(cube(self.a, radius).surrounding(cube(self.b, radius)))
+    }
+}
+
+pub struct SmoothUnion<A, B> {
+    pub left: A,
+    pub right: B,
+    pub k: f64,
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothUnion<A, B> {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        let (a, b) = (self.left.distance(p), self.right.distance(p));
+        let h = (0.5 + 0.5 * (b - a) / self.k).clamp(0.0, 1.0);
+        lerp(b, a, h) - self.k * h * (1.0 - h)
+    }
+
+    /// The blend can bulge out of the union by a quarter of `k`.
+    fn bound(&self) -> Option<Bound> {
+        let bound = self.left.bound()?.surrounding(self.right.bound()?);
+        let padding = Vec3f::repeat(self.k / 4.0);
+        Some(Bound {
+            min: bound.min - padding,
+            max: bound.max + padding,
+        })
+    }
+}
+
+pub struct SmoothSubtraction<A, B> {
+    pub left: A,
+    pub right: B,
+    pub k: f64,
+}
+
+impl<A: Sdf, B: Sdf> Sdf for SmoothSubtraction<A, B> {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        let (a, b) = (self.left.distance(p), self.right.distance(p));
+        let h = (0.5 - 0.5 * (a + b) / self.k).clamp(0.0, 1.0);
+        lerp(a, -b, h) + self.k * h * (1.0 - h)
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        self.left.bound()
+    }
+}
+
+pub struct Blend<A, B> {
+    pub left: A,
+    pub right: B,
+    pub t: f64,
+}
+
+impl<A: Sdf, B: Sdf> Sdf for Blend<A, B> {
+    fn distance(&self, p: Vec3f<Position>) -> f64 {
+        lerp(self.left.distance(p), self.right.distance(p), self.t)
+    }
+
+    fn bound(&self) -> Option<Bound> {
+        Some(self.left.bound()?.surrounding(self.right.bound()?))
+    }
+}
+
+/// An object with the surface of a signed distance function,
+/// where the ray is traced inside `bound`.
+pub struct SdfShape {
+    pub sdf: Arc<dyn Sdf>,
+    pub bound: Bound,
+    pub material: Arc<dyn Material>,
+    /// Distance to the surface at which it's considered hit, relative to the bound.
+    precision: f64,
+}
+
+impl SdfShape {
+    /// # Panics
+    /// If the bound of the function is unknown, like for closures.
+    pub fn new<S: Sdf + 'static>(sdf: S, material: Arc<dyn Material>) -> Self {
+        let bound = sdf
+            .bound()
+            .expect("The bound of the distance function must be given");
+        Self::with_bound(sdf, bound, material)
+    }
+
+    pub fn with_bound<S: Sdf + 'static>(sdf: S, bound: Bound, material: Arc<dyn Material>) -> Self {
+        Self {
+            sdf: Arc::new(sdf),
+            bound,
+            material,
+            precision: 1e-6 * (bound.max - bound.min).magnitude(),
+        }
+    }
+
+    /// Gradient of the distance, by the tetrahedron of finite differences.
+    fn normal(&self, p: Vec3f<Position>) -> Vec3f<Position> {
+        let h = self.precision * 10.0;
+        TETRAHEDRON
+            .iter()
+            .map(|&k| {
+                let k = Vec3f::<Position>::from(k);
+                k * self.sdf.distance(p + h * k)
+            })
+            .sum::<Vec3f<Position>>()
+            .unit()
+    }
+
+    /// Sphere tracing from `start`: steps as far as the distance to the surface, which
+    /// can't be crossed. Returns where the surface is hit before `end`.
+    ///
+    /// Distances are measured as positive on `side` of the surface, where the ray starts.
+    /// Right after a hit the side isn't known yet, and the ray must leave the surface to
+    /// learn it.
+    fn march(&self, ray: Ray, start: f64, end: f64, mut side: Option<f64>) -> Option<f64> {
+        let length = ray.direction().magnitude();
+        // Rays that start on the surface must leave it before they can hit it
+        let mut left_surface = false;
+        let mut t = start;
+        for _ in 0..MAX_STEPS {
+            let distance = self.sdf.distance(ray.point_at_parameter(t));
+            match side {
+                None if distance.abs() > 2.0 * self.precision => {
+                    side = Some(distance.signum());
+                    left_surface = true;
+                }
+                None => (),
+                Some(side) => {
+                    let distance = side * distance;
+                    if distance > 2.0 * self.precision {
+                        left_surface = true;
+                    } else if left_surface || distance < 0.0 {
+                        return Some(t);
+                    }
+                }
+            }
+            t += distance.abs().max(self.precision) / length;
+            if t > end {
+                return None;
+            }
+        }
+        None
+    }
+
+    /// Which side of the surface the ray is on at `t`, as the sign of the distance.
+    fn side(&self, ray: Ray, t: f64) -> f64 {
+        self.sdf.distance(ray.point_at_parameter(t)).signum()
+    }
+
+    fn record(&self, ray: Ray, t: f64) -> HitRecord<'_> {
+        let p = ray.point_at_parameter(t);
+        let (normal, front_face) = HitRecord::face_normal(ray, self.normal(p));
+        // Texture coordinates of the direction of the normal, as in `Sphere`
+        let outward = if front_face { normal } else { -normal };
+        let u = (f64::atan2(-outward.z(), outward.x()) + std::f64::consts::PI)
+            / (2.0 * std::f64::consts::PI);
+        let v = f64::acos((-outward.y()).clamp(-1.0, 1.0)) / std::f64::consts::PI;
+        HitRecord {
+            t,
+            p,
+            normal,
+            material: self.material.as_ref(),
+            u,
+            v,
+            front_face,
+        }
+    }
+}
+
+impl Hittable for SdfShape {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (start, end) = self.bound.clip(ray, t_min, t_max)?;
+        let t = self.march(ray, start, end, Some(self.side(ray, start)))?;
+        Some(self.record(ray, t))
+    }
+
+    /// Marches on after every hit, so each surface is crossed once.
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        let (start, end) = match self.bound.clip(ray, t_min, t_max) {
+            Some(range) => range,
+            None => return,
+        };
+        let mut hit = self.march(ray, start, end, Some(self.side(ray, start)));
+        while let Some(t) = hit {
+            crossings.push(self.record(ray, t));
+            hit = self.march(ray, t, end, None);
+        }
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        Some(self.bound)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{self, gray};
+
+    fn crossings(sdf: impl Sdf + 'static, origin: Vec3f<Position>) -> Vec<(f64, bool)> {
+        test_util::crossings(&SdfShape::new(sdf, gray()), origin)
+    }
+
+    /// Surfaces are only found within the precision of the march.
+    fn assert_crossings(actual: &[(f64, bool)], expected: &[(f64, bool)]) {
+        test_util::assert_crossings(actual, expected, 1e-4);
+    }
+
+    #[test]
+    fn each_surface_is_crossed_once() {
+        let sphere = SdfSphere {
+            center: Vec3f::repeat(0.0),
+            radius: 1.0,
+        };
+        let origin = Vec3f::new(-5.0, 0.0, 0.0);
+        assert_crossings(&crossings(sphere, origin), &[(4.0, true), (6.0, false)]);
+
+        let torus = SdfTorus {
+            center: Vec3f::repeat(0.0),
+            major_radius: 2.0,
+            minor_radius: 0.5,
+        };
+        let expected = [(2.5, true), (3.5, false), (6.5, true), (7.5, false)];
+        assert_crossings(&crossings(torus, origin), &expected);
+    }
+
+    #[test]
+    fn rays_starting_inside_leave_once() {
+        let sphere = SdfSphere {
+            center: Vec3f::repeat(0.0),
+            radius: 1.0,
+        };
+        let origin = Vec3f::repeat(0.0);
+        assert_crossings(&crossings(sphere, origin), &[(1.0, false)]);
+    }
+}
@@ -0,0 +1,581 @@
+//! Analytic shapes around the Y axis. They can be oriented with transforms.
+
+use std::f64::consts::PI;
+use std::sync::Arc;
+
+use super::{Bound, HitRecord, Hittable, Material, Position, Ray, Sampler, Vec3f};
+
+/// Real roots of `a * x^2 + b * x + c`, without the cancellation of the textbook formula.
+fn solve_quadratic(a: f64, b: f64, c: f64) -> Option<(f64, f64)> {
+    if a == 0.0 {
+        if b == 0.0 {
+            return None;
+        }
+        let root = -c / b;
+        return Some((root, root));
+    }
+    let discriminant = b * b - 4.0 * a * c;
+    if discriminant < 0.0 {
+        return None;
+    }
+    let q = -0.5 * (b + b.signum() * discriminant.sqrt());
+    if q == 0.0 {
+        return Some((0.0, 0.0));
+    }
+    let (x0, x1) = (q / a, c / q);
+    Some((x0.min(x1), x0.max(x1)))
+}
+
+/// Largest real root of `x^3 + a * x^2 + b * x + c`.
+fn largest_cubic_root(a: f64, b: f64, c: f64) -> f64 {
+    // Depressed to `t^3 + p * t + q` with `x = t - a / 3`
+    let p = b - a * a / 3.0;
+    let q = 2.0 * a.powi(3) / 27.0 - a * b / 3.0 + c;
+    let discriminant = (q / 2.0).powi(2) + (p / 3.0).powi(3);
+    let t = if discriminant >= 0.0 {
+        let sqrt = discriminant.sqrt();
+        (-q / 2.0 + sqrt).cbrt() + (-q / 2.0 - sqrt).cbrt()
+    } else {
+        // Three real roots, p < 0
+        let cos = (3.0 * q / (2.0 * p) * (-3.0 / p).sqrt()).clamp(-1.0, 1.0);
+        2.0 * (-p / 3.0).sqrt() * (cos.acos() / 3.0).cos()
+    };
+    t - a / 3.0
+}
+
+/// Real roots of `x^4 + a * x^3 + b * x^2 + c * x + d`, in any order, by Ferrari's method.
+/// Returns the roots and how many of them there are.
+fn solve_quartic(a: f64, b: f64, c: f64, d: f64) -> ([f64; 4], usize) {
+    // Depressed to `y^4 + p * y^2 + q * y + r` with `x = y - a / 4`
+    let shift = a / 4.0;
+    let p = b - 6.0 * shift * shift;
+    let q = c - 2.0 * b * shift + 8.0 * shift.powi(3);
+    let r = d - c * shift + b * shift * shift - 3.0 * shift.powi(4);
+
+    let mut roots = [0.0; 4];
+    let mut count = 0;
+    let mut push_quadratic = |b: f64, c: f64| {
+        if let Some((y0, y1)) = solve_quadratic(1.0, b, c) {
+            roots[count] = y0;
+            roots[count + 1] = y1;
+            count += 2;
+        }
+    };
+    if q.abs() < 1e-12 {
+        // Biquadratic, a quadratic in `y^2`
+        if let Some((z0, z1)) = solve_quadratic(1.0, p, r) {
+            for z in [z0, z1] {
+                if z >= 0.0 {
+                    push_quadratic(0.0, -z);
+                }
+            }
+        }
+    } else {
+        // Completes the square with a positive root of the resolvent cubic
+        let m = largest_cubic_root(p, p * p / 4.0 - r, -q * q / 8.0);
+        if m <= 0.0 {
+            return (roots, 0);
+        }
+        let s = (2.0 * m).sqrt();
+        push_quadratic(-s, p / 2.0 + m + q / (2.0 * s));
+        push_quadratic(s, p / 2.0 + m - q / (2.0 * s));
+    }
+
+    // Newton's method recovers the precision lost by the closed form
+    for root in &mut roots[..count] {
+        let mut x = *root - shift;
+        for _ in 0..2 {
+            let f = (((x + a) * x + b) * x + c) * x + d;
+            let df = ((4.0 * x + 3.0 * a) * x + 2.0 * b) * x + c;
+            if df != 0.0 {
+                x -= f / df;
+            }
+        }
+        *root = x;
+    }
+    (roots, count)
+}
+
+/// Angle of the point around the Y axis, from 0 to 1, as in `Sphere`.
+fn azimuth(p: Vec3f<Position>) -> f64 {
+    (f64::atan2(-p.z(), p.x()) + PI) / (2.0 * PI)
+}
+
+/// The part of a shape that a ray crossed.
+#[derive(Copy, Clone, Eq, PartialEq)]
+enum Part {
+    Side,
+    Bottom,
+    Top,
+}
+
+/// The points, of up to 4, where a ray crosses the surface of a shape.
+#[derive(Copy, Clone)]
+struct Crossings {
+    items: [(f64, Part); 4],
+    len: usize,
+}
+
+impl Crossings {
+    fn new() -> Self {
+        Self {
+            items: [(0.0, Part::Side); 4],
+            len: 0,
+        }
+    }
+
+    fn push(&mut self, t: f64, part: Part) {
+        self.items[self.len] = (t, part);
+        self.len += 1;
+    }
+
+    /// The crossings in the range, sorted.
+    fn in_range(mut self, t_min: f64, t_max: f64) -> impl Iterator<Item = (f64, Part)> {
+        self.items[..self.len].sort_by(|a, b| a.0.total_cmp(&b.0));
+        (0..self.len)
+            .map(move |i| self.items[i])
+            .filter(move |(t, _)| t_min <= *t && *t <= t_max)
+    }
+
+    /// The first crossing in the range.
+    fn closest(&self, t_min: f64, t_max: f64) -> Option<(f64, Part)> {
+        self.items[..self.len]
+            .iter()
+            .filter(|(t, _)| t_min <= *t && *t <= t_max)
+            .min_by(|a, b| a.0.total_cmp(&b.0))
+            .copied()
+    }
+}
+
+/// Builds the record of a hit at `t`, with the outward normal and the texture
+/// coordinates of the point relative to the shape.
+fn record<'a>(
+    ray: Ray,
+    t: f64,
+    (outward_normal, u, v): (Vec3f<Position>, f64, f64),
+    material: &'a dyn Material,
+) -> HitRecord<'a> {
+    let (normal, front_face) = HitRecord::face_normal(ray, outward_normal);
+    HitRecord {
+        t,
+        p: ray.point_at_parameter(t),
+        normal,
+        material,
+        u,
+        v,
+        front_face,
+    }
+}
+
+/// Adds the crossing of the plane `y = height` if it's within `radius` of the Y axis.
+fn cap(
+    crossings: &mut Crossings,
+    o: Vec3f<Position>,
+    d: Vec3f<Position>,
+    height: f64,
+    radius: f64,
+    part: Part,
+) {
+    if d.y() == 0.0 {
+        return;
+    }
+    let t = (height - o.y()) / d.y();
+    let (x, z) = (o.x() + t * d.x(), o.z() + t * d.z());
+    if x * x + z * z <= radius * radius {
+        crossings.push(t, part);
+    }
+}
+
+/// A cylinder standing on the XZ plane.
+#[derive(Clone)]
+pub struct Cylinder {
+    /// Center of the bottom
+    pub base: Vec3f<Position>,
+    pub radius: f64,
+    pub height: f64,
+    /// Closed by disks at the top and the bottom, or an open tube.
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+}
+
+impl Cylinder {
+    fn intersections(&self, ray: Ray) -> Crossings {
+        let mut crossings = Crossings::new();
+        let o = ray.origin() - self.base;
+        let d = ray.direction();
+        let a = d.x() * d.x() + d.z() * d.z();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z());
+        let c = o.x() * o.x() + o.z() * o.z() - self.radius * self.radius;
+        // Parallel to the axis, the ray can only cross the caps
+        if a > 0.0 {
+            if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+                for t in [t0, t1] {
+                    let y = o.y() + t * d.y();
+                    if (0.0..=self.height).contains(&y) {
+                        crossings.push(t, Part::Side);
+                    }
+                }
+            }
+        }
+        if self.capped {
+            cap(&mut crossings, o, d, 0.0, self.radius, Part::Bottom);
+            cap(&mut crossings, o, d, self.height, self.radius, Part::Top);
+        }
+        crossings
+    }
+
+    /// Caps are textured with `u` around the axis and `v` from the center to the edge.
+    fn surface(&self, p: Vec3f<Position>, part: Part) -> (Vec3f<Position>, f64, f64) {
+        let p = p - self.base;
+        let distance = p.x().hypot(p.z()) / self.radius;
+        match part {
+            Part::Side => (
+                Vec3f::new(p.x(), 0.0, p.z()) / self.radius,
+                azimuth(p),
+                p.y() / self.height,
+            ),
+            Part::Bottom => (Vec3f::new(0.0, -1.0, 0.0), azimuth(p), distance),
+            Part::Top => (Vec3f::new(0.0, 1.0, 0.0), azimuth(p), distance),
+        }
+    }
+}
+
+impl Hittable for Cylinder {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (t, part) = self.intersections(ray).closest(t_min, t_max)?;
+        let surface = self.surface(ray.point_at_parameter(t), part);
+        Some(record(ray, t, surface, self.material.as_ref()))
+    }
+
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        for (t, part) in self.intersections(ray).in_range(t_min, t_max) {
+            let surface = self.surface(ray.point_at_parameter(t), part);
+            crossings.push(record(ray, t, surface, self.material.as_ref()));
+        }
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        Some(Bound {
+            min: self.base - Vec3f::new(self.radius, 0.0, self.radius),
+            max: self.base + Vec3f::new(self.radius, self.height, self.radius),
+        })
+    }
+
+    fn is_convex(&self) -> bool {
+        self.capped
+    }
+}
+
+/// A cone standing on the XZ plane, with the apex above the center of the base.
+#[derive(Clone)]
+pub struct Cone {
+    /// Center of the bottom
+    pub base: Vec3f<Position>,
+    /// Radius of the bottom
+    pub radius: f64,
+    pub height: f64,
+    /// Closed by a disk at the bottom.
+    pub capped: bool,
+    pub material: Arc<dyn Material>,
+}
+
+impl Cone {
+    fn intersections(&self, ray: Ray) -> Crossings {
+        let mut crossings = Crossings::new();
+        let o = ray.origin() - self.base;
+        let d = ray.direction();
+        // x^2 + z^2 = (slope * (height - y))^2
+        let slope = (self.radius / self.height).powi(2);
+        let e = self.height - o.y();
+        let a = d.x() * d.x() + d.z() * d.z() - slope * d.y() * d.y();
+        let b = 2.0 * (o.x() * d.x() + o.z() * d.z() + slope * e * d.y());
+        let c = o.x() * o.x() + o.z() * o.z() - slope * e * e;
+        if let Some((t0, t1)) = solve_quadratic(a, b, c) {
+            // Both roots are the same if the equation is linear
+            let roots = if t0 == t1 { &[t0][..] } else { &[t0, t1][..] };
+            for &t in roots {
+                let y = o.y() + t * d.y();
+                // The other half of the double cone is above the apex
+                if (0.0..=self.height).contains(&y) {
+                    crossings.push(t, Part::Side);
+                }
+            }
+        }
+        if self.capped {
+            cap(&mut crossings, o, d, 0.0, self.radius, Part::Bottom);
+        }
+        crossings
+    }
+
+    fn surface(&self, p: Vec3f<Position>, part: Part) -> (Vec3f<Position>, f64, f64) {
+        let p = p - self.base;
+        match part {
+            Part::Side => {
+                let slope = (self.radius / self.height).powi(2);
+                let normal = Vec3f::new(p.x(), slope * (self.height - p.y()), p.z());
+                // The apex has no normal
+                let normal = if normal.near_zero() {
+                    Vec3f::new(0.0, 1.0, 0.0)
+                } else {
+                    normal.unit()
+                };
+                (normal, azimuth(p), p.y() / self.height)
+            }
+            Part::Bottom | Part::Top => (
+                Vec3f::new(0.0, -1.0, 0.0),
+                azimuth(p),
+                p.x().hypot(p.z()) / self.radius,
+            ),
+        }
+    }
+}
+
+impl Hittable for Cone {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (t, part) = self.intersections(ray).closest(t_min, t_max)?;
+        let surface = self.surface(ray.point_at_parameter(t), part);
+        Some(record(ray, t, surface, self.material.as_ref()))
+    }
+
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        for (t, part) in self.intersections(ray).in_range(t_min, t_max) {
+            let surface = self.surface(ray.point_at_parameter(t), part);
+            crossings.push(record(ray, t, surface, self.material.as_ref()));
+        }
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        Some(Bound {
+            min: self.base - Vec3f::new(self.radius, 0.0, self.radius),
+            max: self.base + Vec3f::new(self.radius, self.height, self.radius),
+        })
+    }
+
+    fn is_convex(&self) -> bool {
+        self.capped
+    }
+}
+
+/// A disk in the XZ plane facing up, or an annulus if it has a hole in the middle.
+#[derive(Clone)]
+pub struct Disk {
+    pub center: Vec3f<Position>,
+    pub radius: f64,
+    /// Radius of the hole, 0 for a full disk
+    pub inner_radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl Hittable for Disk {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let o = ray.origin() - self.center;
+        let d = ray.direction();
+        if d.y() == 0.0 {
+            return None;
+        }
+        let t = -o.y() / d.y();
+        if t < t_min || t > t_max {
+            return None;
+        }
+        let p = o + t * d;
+        let distance = p.x().hypot(p.z());
+        if distance > self.radius || distance < self.inner_radius {
+            return None;
+        }
+        let v = (distance - self.inner_radius) / (self.radius - self.inner_radius);
+        let surface = (Vec3f::new(0.0, 1.0, 0.0), azimuth(p), v);
+        Some(record(ray, t, surface, self.material.as_ref()))
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        let extent = Vec3f::new(self.radius, 0.0, self.radius);
+        let bound = Bound {
+            min: self.center - extent,
+            max: self.center + extent,
+        };
+        Some(bound.padded(Bound::FLAT_PADDING))
+    }
+}
+
+/// A torus around the Y axis.
+#[derive(Clone)]
+pub struct Torus {
+    pub center: Vec3f<Position>,
+    /// Distance from the center to the middle of the tube
+    pub major_radius: f64,
+    /// Radius of the tube
+    pub minor_radius: f64,
+    pub material: Arc<dyn Material>,
+}
+
+impl Torus {
+    fn intersections(&self, ray: Ray) -> Crossings {
+        let mut crossings = Crossings::new();
+        let length = ray.direction().magnitude();
+        let d = ray.direction() / length;
+        // Starting from the point of the ray closest to the center keeps
+        // the coefficients small for rays that start far away.
+        let start = -(ray.origin() - self.center).dot(d);
+        let o = ray.origin() - self.center + start * d;
+
+        // (|p|^2 - R^2 - r^2)^2 = 4 * R^2 * (r^2 - y^2)
+        let major = self.major_radius * self.major_radius;
+        let minor = self.minor_radius * self.minor_radius;
+        let e = o.dot(o) - major - minor;
+        let f = o.dot(d);
+        let (roots, count) = solve_quartic(
+            4.0 * f,
+            2.0 * e + 4.0 * f * f + 4.0 * major * d.y() * d.y(),
+            4.0 * f * e + 8.0 * major * o.y() * d.y(),
+            e * e - 4.0 * major * (minor - o.y() * o.y()),
+        );
+        for &s in &roots[..count] {
+            crossings.push((s + start) / length, Part::Side);
+        }
+        crossings
+    }
+
+    /// `u` goes around the Y axis and `v` around the tube.
+    fn surface(&self, p: Vec3f<Position>) -> (Vec3f<Position>, f64, f64) {
+        let p = p - self.center;
+        let distance = p.x().hypot(p.z());
+        // The closest point of the circle in the middle of the tube
+        let core = if distance > 0.0 {
+            Vec3f::new(p.x(), 0.0, p.z()) * (self.major_radius / distance)
+        } else {
+            Vec3f::new(self.major_radius, 0.0, 0.0)
+        };
+        let normal = (p - core) / self.minor_radius;
+        let v = f64::atan2(p.y(), distance - self.major_radius).rem_euclid(2.0 * PI) / (2.0 * PI);
+        (normal, azimuth(p), v)
+    }
+}
+
+impl Hittable for Torus {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (t, _) = self.intersections(ray).closest(t_min, t_max)?;
+        let surface = self.surface(ray.point_at_parameter(t));
+        Some(record(ray, t, surface, self.material.as_ref()))
+    }
+
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        _: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        for (t, _) in self.intersections(ray).in_range(t_min, t_max) {
+            let surface = self.surface(ray.point_at_parameter(t));
+            crossings.push(record(ray, t, surface, self.material.as_ref()));
+        }
+    }
+
+    fn bounding_box(&self, _: f64, _: f64) -> Option<Bound> {
+        let outer = self.major_radius + self.minor_radius;
+        let extent = Vec3f::new(outer, self.minor_radius, outer);
+        Some(Bound {
+            min: self.center - extent,
+            max: self.center + extent,
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{assert_close, gray};
+
+    fn sorted_roots(a: f64, b: f64, c: f64, d: f64) -> Vec<f64> {
+        let (roots, count) = solve_quartic(a, b, c, d);
+        let mut roots = roots[..count].to_vec();
+        roots.sort_by(f64::total_cmp);
+        roots
+    }
+
+    #[test]
+    fn quartic_roots() {
+        // (x - 1)(x - 2)(x - 3)(x - 4)
+        let roots = sorted_roots(-10.0, 35.0, -50.0, 24.0);
+        assert_close(&roots, &[1.0, 2.0, 3.0, 4.0]);
+        // (x^2 - 1)(x^2 - 4), which is biquadratic
+        assert_close(&sorted_roots(0.0, -5.0, 0.0, 4.0), &[-2.0, -1.0, 1.0, 2.0]);
+        // (x^2 + 1)(x - 1)(x + 2)
+        assert_close(&sorted_roots(1.0, -1.0, 1.0, -2.0), &[-2.0, 1.0]);
+        // (x^2 + 1)(x^2 + 4)
+        assert_close(&sorted_roots(0.0, 5.0, 0.0, 4.0), &[]);
+    }
+
+    fn torus_crossings(origin: Vec3f<Position>, direction: Vec3f<Position>) -> Vec<f64> {
+        let torus = Torus {
+            center: Vec3f::new(1.0, 2.0, 3.0),
+            major_radius: 2.0,
+            minor_radius: 0.5,
+            material: gray(),
+        };
+        let ray = Ray {
+            a: origin + torus.center,
+            b: direction,
+            ..Ray::default()
+        };
+        let mut sampler = Sampler::new(0);
+        let mut crossings = Vec::new();
+        torus.crossings(ray, 0.0, f64::INFINITY, &mut sampler, &mut crossings);
+        crossings.iter().map(|record| record.t).collect()
+    }
+
+    #[test]
+    fn torus_intersections() {
+        // Through both sides of the tube, on either side of the hole
+        let across = torus_crossings(Vec3f::new(-5.0, 0.0, 0.0), Vec3f::new(1.0, 0.0, 0.0));
+        assert_close(&across, &[2.5, 3.5, 6.5, 7.5]);
+        // A direction that isn't a unit vector scales the distances
+        let scaled = torus_crossings(Vec3f::new(-5.0, 0.0, 0.0), Vec3f::new(2.0, 0.0, 0.0));
+        assert_close(&scaled, &[1.25, 1.75, 3.25, 3.75]);
+        // Down through the tube
+        let down = torus_crossings(Vec3f::new(0.0, 5.0, 2.0), Vec3f::new(0.0, -1.0, 0.0));
+        assert_close(&down, &[4.5, 5.5]);
+        // Down through the hole
+        let hole = torus_crossings(Vec3f::new(0.0, 5.0, 0.0), Vec3f::new(0.0, -1.0, 0.0));
+        assert_close(&hole, &[]);
+    }
+}
@@ -0,0 +1,258 @@
+//! Light at sampled wavelengths, for spectral rendering.
+//!
+//! Every path carries a few wavelengths: a random hero wavelength that decides where the
+//! path goes, and others equally spaced across the visible range. Colors given in RGB are
+//! upsampled to spectra, and the spectra are converted back to RGB through the CIE color
+//! matching functions.
+
+use std::ops::{Add, Div, Mul};
+use std::sync::OnceLock;
+
+use super::{Color, Vec3f};
+
+/// Shortest wavelength rendered, in nanometers.
+pub const LAMBDA_MIN: f64 = 360.0;
+/// Longest wavelength rendered, in nanometers.
+pub const LAMBDA_MAX: f64 = 830.0;
+/// Wavelengths carried by a path.
+pub const SAMPLES: usize = 4;
+
+/// Values of a spectrum at the wavelengths of a [`SampledWavelengths`].
+#[derive(Copy, Clone, Debug, PartialEq)]
+pub struct SampledSpectrum(pub [f64; SAMPLES]);
+
+impl SampledSpectrum {
+    pub fn repeat(x: f64) -> Self {
+        Self([x; SAMPLES])
+    }
+
+    pub fn max_value(&self) -> f64 {
+        self.0.iter().fold(f64::NEG_INFINITY, |max, &x| max.max(x))
+    }
+
+    pub fn exp(self) -> Self {
+        Self(self.0.map(f64::exp))
+    }
+}
+
+impl Add for SampledSpectrum {
+    type Output = Self;
+
+    fn add(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] + rhs.0[i]))
+    }
+}
+
+impl Mul for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: Self) -> Self {
+        Self(std::array::from_fn(|i| self.0[i] * rhs.0[i]))
+    }
+}
+
+impl Mul<f64> for SampledSpectrum {
+    type Output = Self;
+
+    fn mul(self, rhs: f64) -> Self {
+        Self(self.0.map(|x| x * rhs))
+    }
+}
+
+impl Div<f64> for SampledSpectrum {
+    type Output = Self;
+
+    fn div(self, rhs: f64) -> Self {
+        Self(self.0.map(|x| x / rhs))
+    }
+}
+
+/// The wavelengths carried by a path, in nanometers, and their densities.
+#[derive(Copy, Clone, Debug)]
+pub struct SampledWavelengths {
+    lambda: [f64; SAMPLES],
+    pdf: [f64; SAMPLES],
+}
+
+impl SampledWavelengths {
+    /// A hero wavelength at `u` from 0 to 1 across the visible range, and the others
+    /// rotated from it by equal steps.
+    pub fn sample(u: f64) -> Self {
+        let range = LAMBDA_MAX - LAMBDA_MIN;
+        let hero = LAMBDA_MIN + u * range;
+        let lambda = std::array::from_fn(|i| {
+            let lambda = hero + i as f64 * range / SAMPLES as f64;
+            if lambda > LAMBDA_MAX {
+                lambda - range
+            } else {
+                lambda
+            }
+        });
+        Self {
+            lambda,
+            pdf: [1.0 / range; SAMPLES],
+        }
+    }
+
+    /// The wavelength that decides the directions of the path.
+    pub fn hero(&self) -> f64 {
+        self.lambda[0]
+    }
+
+    /// Drops every wavelength but the hero, for paths that took a direction only the hero
+    /// wavelength could take, like through a prism.
+    pub fn terminate_secondary(&mut self) {
+        if self.is_secondary_terminated() {
+            return;
+        }
+        self.pdf[1..].fill(0.0);
+        self.pdf[0] /= SAMPLES as f64;
+    }
+
+    pub fn is_secondary_terminated(&self) -> bool {
+        self.pdf[1..].iter().all(|&pdf| pdf == 0.0)
+    }
+
+    /// Spectrum of a color by Smits' method. White is a constant 1, and reflectances
+    /// from 0 to 1 stay in 0 to 1. Emitted colors are upsampled the same way, which
+    /// makes white lights white too after [`Self::to_rgb`].
+    pub fn upsample(&self, color: Vec3f<Color>) -> SampledSpectrum {
+        SampledSpectrum(self.lambda.map(|lambda| smits(color, lambda)))
+    }
+
+    /// Linear sRGB color of `spectrum`, white balanced so a constant 1 is white.
+    pub fn to_rgb(&self, spectrum: SampledSpectrum) -> Vec3f<Color> {
+        let mut xyz = [0.0; 3];
+        for i in 0..SAMPLES {
+            if self.pdf[i] == 0.0 {
+                continue;
+            }
+            let matching = color_matching(self.lambda[i]);
+            for (xyz, matching) in xyz.iter_mut().zip(matching) {
+                *xyz += matching * spectrum.0[i] / self.pdf[i];
+            }
+        }
+        let rgb = xyz_to_rgb(xyz.map(|x| x / SAMPLES as f64));
+        rgb / *white()
+    }
+}
+
+/// Linear sRGB of a constant spectrum of 1, which is slightly pink under the D65
+/// white point of sRGB.
+fn white() -> &'static Vec3f<Color> {
+    static WHITE: OnceLock<Vec3f<Color>> = OnceLock::new();
+    WHITE.get_or_init(|| {
+        let steps = 10 * (LAMBDA_MAX - LAMBDA_MIN) as usize;
+        let step = (LAMBDA_MAX - LAMBDA_MIN) / steps as f64;
+        let mut xyz = [0.0; 3];
+        for i in 0..steps {
+            let matching = color_matching(LAMBDA_MIN + (i as f64 + 0.5) * step);
+            for (xyz, matching) in xyz.iter_mut().zip(matching) {
+                *xyz += matching * step;
+            }
+        }
+        xyz_to_rgb(xyz)
+    })
+}
+
+fn xyz_to_rgb([x, y, z]: [f64; 3]) -> Vec3f<Color> {
+    Vec3f::new(
+        3.2406 * x - 1.5372 * y - 0.4986 * z,
+        -0.9689 * x + 1.8758 * y + 0.0415 * z,
+        0.0557 * x - 0.2040 * y + 1.0570 * z,
+    )
+}
+
+/// The CIE 1931 color matching functions at `lambda`, by the multi-lobe Gaussian fit of
+/// Wyman, Sloan and Shirley, "Simple Analytic Approximations to the CIE XYZ Color
+/// Matching Functions".
+fn color_matching(lambda: f64) -> [f64; 3] {
+    let gaussian = |mean: f64, below: f64, above: f64| {
+        let sigma = if lambda < mean { below } else { above };
+        let t = (lambda - mean) / sigma;
+        (-0.5 * t * t).exp()
+    };
+    [
+        1.056 * gaussian(599.8, 37.9, 31.0) + 0.362 * gaussian(442.0, 16.0, 26.7)
+            - 0.065 * gaussian(501.1, 20.4, 26.2),
+        0.821 * gaussian(568.8, 46.9, 40.5) + 0.286 * gaussian(530.9, 16.3, 31.1),
+        1.217 * gaussian(437.0, 11.8, 36.0) + 0.681 * gaussian(459.0, 26.0, 13.8),
+    ]
+}
+
+/// Basis spectra of Smits' method, in equal bins from 380 to 720 nm.
+const SMITS_WHITE: [f64; 10] = [
+    1.0000, 1.0000, 0.9999, 0.9993, 0.9992, 0.9998, 1.0000, 1.0000, 1.0000, 1.0000,
+];
+const SMITS_CYAN: [f64; 10] = [
+    0.9710, 0.9426, 1.0007, 1.0007, 1.0007, 1.0007, 0.1564, 0.0000, 0.0000, 0.0000,
+];
+const SMITS_MAGENTA: [f64; 10] = [
+    1.0000, 1.0000, 0.9685, 0.2229, 0.0000, 0.0458, 0.8369, 1.0000, 1.0000, 0.9959,
+];
+const SMITS_YELLOW: [f64; 10] = [
+    0.0001, 0.0000, 0.1088, 0.6651, 1.0000, 1.0000, 0.9996, 0.9586, 0.9685, 0.9840,
+];
+const SMITS_RED: [f64; 10] = [
+    0.1012, 0.0515, 0.0000, 0.0000, 0.0000, 0.0000, 0.8325, 1.0149, 1.0149, 1.0149,
+];
+const SMITS_GREEN: [f64; 10] = [
+    0.0000, 0.0000, 0.0273, 0.7937, 1.0000, 0.9418, 0.1719, 0.0000, 0.0000, 0.0025,
+];
+const SMITS_BLUE: [f64; 10] = [
+    1.0000, 1.0000, 0.8916, 0.3323, 0.0000, 0.0000, 0.0003, 0.0369, 0.0483, 0.0496,
+];
+
+/// Value of the spectrum of `color` at `lambda`: white for the smallest channel, then
+/// a secondary color for the middle one, and a primary for the largest.
+fn smits(color: Vec3f<Color>, lambda: f64) -> f64 {
+    // Linear between the centers of the bins, and constant past the first and last
+    let position = (lambda - 380.0) / 34.0 - 0.5;
+    let index = (position.floor().max(0.0) as usize).min(8);
+    let fraction = (position - index as f64).clamp(0.0, 1.0);
+    let basis = |table: &[f64; 10]| table[index] * (1.0 - fraction) + table[index + 1] * fraction;
+
+    let (r, g, b) = (color.r(), color.g(), color.b());
+    if r <= g && r <= b {
+        let spectrum = r * basis(&SMITS_WHITE);
+        if g <= b {
+            spectrum + (g - r) * basis(&SMITS_CYAN) + (b - g) * basis(&SMITS_BLUE)
+        } else {
+            spectrum + (b - r) * basis(&SMITS_CYAN) + (g - b) * basis(&SMITS_GREEN)
+        }
+    } else if g <= r && g <= b {
+        let spectrum = g * basis(&SMITS_WHITE);
+        if r <= b {
+            spectrum + (r - g) * basis(&SMITS_MAGENTA) + (b - r) * basis(&SMITS_BLUE)
+        } else {
+            spectrum + (b - g) * basis(&SMITS_MAGENTA) + (r - b) * basis(&SMITS_RED)
+        }
+    } else {
+        let spectrum = b * basis(&SMITS_WHITE);
+        if r <= g {
+            spectrum + (r - b) * basis(&SMITS_YELLOW) + (g - r) * basis(&SMITS_GREEN)
+        } else {
+            spectrum + (g - b) * basis(&SMITS_YELLOW) + (r - g) * basis(&SMITS_RED)
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn white_stays_white() {
+        let white = Vec3f::<Color>::repeat(1.0);
+        let steps = 1000;
+        let mut sum = Vec3f::<Color>::repeat(0.0);
+        for i in 0..steps {
+            let wavelengths = SampledWavelengths::sample((i as f64 + 0.5) / steps as f64);
+            sum = sum + wavelengths.to_rgb(wavelengths.upsample(white));
+        }
+        let mean = sum / steps as f64;
+        let channels = [mean.r(), mean.g(), mean.b()];
+        let is_white = channels.iter().all(|c| (c - 1.0).abs() < 1e-3);
+        assert!(is_white, "{:?}", channels);
+    }
+}
@@ -0,0 +1,124 @@
+//! Fixtures and assertions shared by the tests of the shapes and materials.
+
+use std::sync::Arc;
+
+use super::{
+    Color, HitRecord, Hittable, Lambertian, Material, Position, Ray, Sampler, Sphere, Vec3f,
+};
+
+/// Random directions on the side of the surface that `+Z` points to.
+pub fn above(sampler: &mut Sampler) -> Vec3f<Position> {
+    let w = Vec3f::<Position>::random_unit_vector(sampler);
+    Vec3f::new(w.x(), w.y(), w.z().abs().max(1e-3)).unit()
+}
+
+/// A ray coming from `from` and a hit of `material` at the origin, on a surface
+/// facing `+Z`.
+pub fn surface_hit(material: &dyn Material, from: Vec3f<Position>) -> (Ray, HitRecord<'_>) {
+    let ray = Ray {
+        a: from,
+        b: -from,
+        ..Ray::default()
+    };
+    let record = HitRecord {
+        t: 1.0,
+        p: Vec3f::repeat(0.0),
+        normal: Vec3f::new(0.0, 0.0, 1.0),
+        material,
+        u: 0.0,
+        v: 0.0,
+        front_face: true,
+    };
+    (ray, record)
+}
+
+/// Asserts that `a` is within a relative `1e-9` of `b`.
+fn assert_near(a: f64, b: f64) {
+    assert!((a - b).abs() <= 1e-9 * b.abs().max(1.0), "{} != {}", a, b);
+}
+
+/// Asserts that the samples of `material`, from random directions, have the density and
+/// BSDF that `scattering_pdf` and `eval` give to their direction.
+pub fn assert_samples_match_eval(material: &dyn Material) {
+    let mut sampler = Sampler::new(0);
+    let mut checked = 0;
+    for _ in 0..1000 {
+        let (ray, record) = surface_hit(material, above(&mut sampler));
+        let sample = match material.sample(ray, record, &mut sampler) {
+            Some(sample) => sample,
+            None => continue,
+        };
+        assert!(!sample.specular);
+        let pdf = material.scattering_pdf(ray, record, sample.direction);
+        assert_near(sample.pdf, pdf);
+        let bsdf = material.eval(ray, record, sample.direction);
+        for channel in 0..3 {
+            assert_near(sample.bsdf[channel], bsdf[channel]);
+        }
+        checked += 1;
+    }
+    assert!(checked > 500);
+}
+
+/// Estimate of the fraction of each color that `material` scatters of the light
+/// coming from `from`, which conserves energy if it's at most 1.
+pub fn albedo(material: &dyn Material, from: Vec3f<Position>) -> Vec3f<Color> {
+    let mut sampler = Sampler::new(0);
+    let (ray, record) = surface_hit(material, from);
+    let samples = 100_000;
+    let mut sum = Vec3f::repeat(0.0);
+    for _ in 0..samples {
+        if let Some(sample) = material.sample(ray, record, &mut sampler) {
+            sum = sum + sample.weight();
+        }
+    }
+    sum / samples as f64
+}
+
+/// A plain diffuse material, for shapes whose material doesn't matter.
+pub fn gray() -> Arc<dyn Material> {
+    Lambertian::arc(Vec3f::<Color>::repeat(0.5))
+}
+
+pub fn sphere(center: Vec3f<Position>, radius: f64) -> Sphere {
+    Sphere {
+        center,
+        radius,
+        material: gray(),
+    }
+}
+
+/// Asserts that `actual` has as many values as `expected`, each within 1e-9.
+pub fn assert_close(actual: &[f64], expected: &[f64]) {
+    let message = format!("{:?} != {:?}", actual, expected);
+    assert_eq!(actual.len(), expected.len(), "{}", message);
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a - e).abs() < 1e-9, "{}", message);
+    }
+}
+
+/// The `t` and `front_face` of the crossings of a ray along X from `origin`.
+pub fn crossings(item: &dyn Hittable, origin: Vec3f<Position>) -> Vec<(f64, bool)> {
+    let ray = Ray {
+        a: origin,
+        b: Vec3f::new(1.0, 0.0, 0.0),
+        ..Ray::default()
+    };
+    let mut crossings = Vec::new();
+    let mut sampler = Sampler::new(0);
+    item.crossings(ray, 0.0, f64::INFINITY, &mut sampler, &mut crossings);
+    // The normals face the ray, whether it enters or leaves
+    let facing = |record: &HitRecord| record.normal.dot(ray.b) < 0.0;
+    assert!(crossings.iter().all(facing));
+    let face = |record: &HitRecord| (record.t, record.front_face);
+    crossings.iter().map(face).collect()
+}
+
+/// Asserts that the crossings enter and leave as expected, at distances within `tolerance`.
+pub fn assert_crossings(actual: &[(f64, bool)], expected: &[(f64, bool)], tolerance: f64) {
+    let message = format!("{:?} != {:?}", actual, expected);
+    assert_eq!(actual.len(), expected.len(), "{}", message);
+    for (a, e) in actual.iter().zip(expected) {
+        assert!((a.0 - e.0).abs() < tolerance && a.1 == e.1, "{}", message);
+    }
+}
@@ -1,4 +1,5 @@
 use super::{Color, Perlin, Position, Vec3f};
+use rand::Rng;
 use std::path::Path;
 use std::sync::Arc;
 
@@ -36,15 +37,15 @@ pub struct NoiseTexture {
 
 impl NoiseTexture {
     #[allow(dead_code)]
-    pub fn new() -> Self {
+    pub fn new<R: Rng + ?Sized>(rng: &mut R) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale: 1.0,
         }
     }
-    pub fn scaled(scale: f64) -> Self {
+    pub fn scaled<R: Rng + ?Sized>(scale: f64, rng: &mut R) -> Self {
         Self {
-            noise: Perlin::new(),
+            noise: Perlin::new(rng),
             scale,
         }
     }
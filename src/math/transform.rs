@@ -0,0 +1,456 @@
+use super::{
+    Bound, HitRecord, Hittable, Light, LightTransform, Matrix4, Position, Quaternion, Ray, Sampler,
+    Vec3f,
+};
+
+/// Largest rotation, in degrees, between the poses sampled to bound an animated object.
+const MAX_STEP_ANGLE: f64 = 5.0;
+
+fn corners(bound: Bound) -> [Vec3f<Position>; 8] {
+    let (min, max) = (bound.min, bound.max);
+    let pick =
+        |i: usize, bit: usize, coord: usize| if i & bit == 0 { min[coord] } else { max[coord] };
+    let mut corners = [min; 8];
+    for (i, corner) in corners.iter_mut().enumerate() {
+        *corner = Vec3f::new(pick(i, 1, 0), pick(i, 2, 1), pick(i, 4, 2));
+    }
+    corners
+}
+
+/// The box around the transformed corners of `bound`.
+fn transform_bound(matrix: &Matrix4, bound: Bound) -> Bound {
+    let mut min = Vec3f::repeat(f64::INFINITY);
+    let mut max = Vec3f::repeat(f64::NEG_INFINITY);
+    for corner in corners(bound).iter() {
+        let corner = matrix.transform_point(*corner);
+        for coord in 0..3 {
+            min[coord] = min[coord].min(corner[coord]);
+            max[coord] = max[coord].max(corner[coord]);
+        }
+    }
+    Bound { min, max }
+}
+
+/// Moves a hit in object space back to the world, where `ray` was cast.
+fn to_world<'a>(inverse: &Matrix4, ray: Ray, record: HitRecord<'a>) -> HitRecord<'a> {
+    // The inverse transpose keeps the normal perpendicular to the surface
+    // and on the same side of the ray, so `front_face` doesn't change.
+    HitRecord {
+        p: ray.point_at_parameter(record.t),
+        normal: inverse.transform_normal(record.normal).unit(),
+        ..record
+    }
+}
+
+/// An object moved by an affine transform: rotated, scaled, sheared and translated.
+pub struct Transform {
+    item: Box<dyn Hittable>,
+    /// From object space to world space.
+    matrix: Matrix4,
+    inverse: Matrix4,
+}
+
+impl Transform {
+    /// # Panics
+    /// If the matrix can't be inverted.
+    pub fn new(item: Box<dyn Hittable>, matrix: Matrix4) -> Self {
+        let inverse = matrix.inverse().expect("A transform must be invertible");
+        Self {
+            item,
+            matrix,
+            inverse,
+        }
+    }
+
+    pub fn matrix(&self) -> Matrix4 {
+        self.matrix
+    }
+
+    fn to_object(&self, ray: Ray) -> Ray {
+        Ray {
+            a: self.inverse.transform_point(ray.origin()),
+            b: self.inverse.transform_vector(ray.direction()),
+            ..ray
+        }
+    }
+}
+
+impl LightTransform for Transform {
+    fn ray_to_object(&self, ray: Ray) -> Ray {
+        self.to_object(ray)
+    }
+
+    fn point_to_object(&self, point: Vec3f<Position>) -> Vec3f<Position> {
+        self.inverse.transform_point(point)
+    }
+
+    fn direction_to_world(&self, direction: Vec3f<Position>) -> Vec3f<Position> {
+        self.matrix.transform_vector(direction)
+    }
+
+    /// Change of solid angle of the directions, which scaling and shearing don't keep.
+    fn pdf_scale(&self, ray: Ray) -> f64 {
+        let direction = self.inverse.transform_vector(ray.direction().unit());
+        self.inverse.determinant3().abs() / direction.magnitude().powi(3)
+    }
+}
+
+impl Hittable for Transform {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        // The direction is not normalized, so `t` is the same in both spaces
+        let record = self.item.hit(self.to_object(ray), t_min, t_max, sampler)?;
+        Some(to_world(&self.inverse, ray, record))
+    }
+
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        let start = crossings.len();
+        let object_ray = self.to_object(ray);
+        self.item.crossings(object_ray, t_min, t_max, sampler, crossings);
+        for record in &mut crossings[start..] {
+            *record = to_world(&self.inverse, ray, *record);
+        }
+    }
+
+    fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
+        let bound = self.item.bounding_box(initial_time, final_time)?;
+        Some(transform_bound(&self.matrix, bound))
+    }
+
+    fn is_convex(&self) -> bool {
+        self.item.is_convex()
+    }
+
+    fn lights<'a>(&'a self, lights: &mut Vec<Light<'a>>) {
+        let start = lights.len();
+        self.item.lights(lights);
+        for light in &mut lights[start..] {
+            light.push_transform(self);
+        }
+    }
+
+    /// Chained transforms are folded into a single matrix.
+    fn transform(self, matrix: Matrix4) -> Transform {
+        let inverse = matrix.inverse().expect("A transform must be invertible");
+        Self {
+            item: self.item,
+            matrix: matrix * self.matrix,
+            inverse: self.inverse * inverse,
+        }
+    }
+}
+
+/// The pose of an animated object at a point in time.
+/// The object is scaled, then rotated and then translated.
+#[derive(Copy, Clone)]
+pub struct Keyframe {
+    pub time: f64,
+    pub translation: Vec3f<Position>,
+    pub rotation: Quaternion,
+    pub scale: Vec3f<Position>,
+}
+
+impl Keyframe {
+    /// The pose of the object without any transform.
+    pub fn new(time: f64) -> Self {
+        Self {
+            time,
+            translation: Vec3f::default(),
+            rotation: Quaternion::IDENTITY,
+            scale: Vec3f::repeat(1.0),
+        }
+    }
+
+    fn lerp(&self, other: &Self, s: f64) -> Self {
+        Self {
+            time: self.time + s * (other.time - self.time),
+            translation: self.translation + s * (other.translation - self.translation),
+            rotation: self.rotation.slerp(other.rotation, s),
+            scale: self.scale + s * (other.scale - self.scale),
+        }
+    }
+
+    fn matrix(&self) -> Matrix4 {
+        Matrix4::translation(self.translation)
+            * self.rotation.to_matrix()
+            * Matrix4::scaling(self.scale)
+    }
+
+    fn inverse(&self) -> Matrix4 {
+        let scale = Vec3f::new(
+            1.0 / self.scale.x(),
+            1.0 / self.scale.y(),
+            1.0 / self.scale.z(),
+        );
+        Matrix4::scaling(scale)
+            * self.rotation.conjugate().to_matrix()
+            * Matrix4::translation(-self.translation)
+    }
+}
+
+/// An object that moves along keyframes, interpolated at the time of each ray.
+/// Between two keyframes the translation and scale change linearly and the rotation
+/// at a constant angular speed, so more keyframes describe curved or uneven motion.
+/// Before the first keyframe and after the last one the object doesn't move.
+///
+/// Animated objects are not sampled as lights.
+pub struct AnimatedTransform {
+    item: Box<dyn Hittable>,
+    /// Sorted by time
+    keyframes: Vec<Keyframe>,
+}
+
+impl AnimatedTransform {
+    /// # Panics
+    /// If there are no keyframes or a scale is 0.
+    pub fn new(item: Box<dyn Hittable>, mut keyframes: Vec<Keyframe>) -> Self {
+        assert!(
+            !keyframes.is_empty(),
+            "An animation needs at least one keyframe"
+        );
+        assert!(
+            keyframes
+                .iter()
+                .all(|k| k.scale.x() * k.scale.y() * k.scale.z() != 0.0),
+            "An animated object can't be scaled by 0"
+        );
+        keyframes.sort_by(|a, b| a.time.total_cmp(&b.time));
+        Self { item, keyframes }
+    }
+
+    pub fn keyframes(&self) -> &[Keyframe] {
+        &self.keyframes
+    }
+
+    /// The interpolated pose at `time`.
+    pub fn pose(&self, time: f64) -> Keyframe {
+        let next = self.keyframes.partition_point(|k| k.time <= time);
+        if next == 0 {
+            return Keyframe {
+                time,
+                ..self.keyframes[0]
+            };
+        }
+        if next == self.keyframes.len() {
+            return Keyframe {
+                time,
+                ..self.keyframes[next - 1]
+            };
+        }
+        let (a, b) = (&self.keyframes[next - 1], &self.keyframes[next]);
+        a.lerp(b, (time - a.time) / (b.time - a.time))
+    }
+
+    /// The inverse transform at the time of the ray, and the ray in object space.
+    fn to_object(&self, ray: Ray) -> (Matrix4, Ray) {
+        let inverse = self.pose(ray.time).inverse();
+        let object_ray = Ray {
+            a: inverse.transform_point(ray.origin()),
+            b: inverse.transform_vector(ray.direction()),
+            ..ray
+        };
+        (inverse, object_ray)
+    }
+}
+
+impl Hittable for AnimatedTransform {
+    fn hit(
+        &self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+    ) -> Option<HitRecord<'_>> {
+        let (inverse, object_ray) = self.to_object(ray);
+        let record = self.item.hit(object_ray, t_min, t_max, sampler)?;
+        Some(to_world(&inverse, ray, record))
+    }
+
+    fn crossings<'a>(
+        &'a self,
+        ray: Ray,
+        t_min: f64,
+        t_max: f64,
+        sampler: &mut Sampler,
+        crossings: &mut Vec<HitRecord<'a>>,
+    ) {
+        let start = crossings.len();
+        let (inverse, object_ray) = self.to_object(ray);
+        self.item.crossings(object_ray, t_min, t_max, sampler, crossings);
+        for record in &mut crossings[start..] {
+            *record = to_world(&inverse, ray, *record);
+        }
+    }
+
+    /// Bounds the object at poses sampled along the motion, close enough that the
+    /// rotation between them is small, padded by how far a corner of the bound of the
+    /// object can get from the straight line between two poses.
+    fn bounding_box(&self, initial_time: f64, final_time: f64) -> Option<Bound> {
+        let bound = self.item.bounding_box(initial_time, final_time)?;
+        let corners = corners(bound);
+        // The keyframes inside the interval split it into pieces of a single motion
+        let mut times = vec![initial_time];
+        times.extend(
+            self.keyframes
+                .iter()
+                .map(|k| k.time)
+                .filter(|&time| initial_time < time && time < final_time),
+        );
+        times.push(final_time);
+
+        let mut min = Vec3f::repeat(f64::INFINITY);
+        let mut max = Vec3f::repeat(f64::NEG_INFINITY);
+        let mut padding: f64 = 0.0;
+        for interval in times.windows(2) {
+            let (start, end) = (self.pose(interval[0]), self.pose(interval[1]));
+            let angle = start.rotation.angle_to(end.rotation);
+            let steps = (angle.to_degrees() / MAX_STEP_ANGLE).ceil().max(1.0) as usize;
+            let step_angle = angle / steps as f64;
+            let mut previous: Option<Keyframe> = None;
+            for step in 0..=steps {
+                let pose = start.lerp(&end, step as f64 / steps as f64);
+                let matrix = pose.matrix();
+                for &corner in corners.iter() {
+                    let corner = matrix.transform_point(corner);
+                    for coord in 0..3 {
+                        min[coord] = min[coord].min(corner[coord]);
+                        max[coord] = max[coord].max(corner[coord]);
+                    }
+                }
+                if let Some(previous) = previous.filter(|_| step_angle > 0.0) {
+                    // A corner turns along an arc, and its distance to the center grows
+                    // or shrinks with the scale while it turns.
+                    for &corner in corners.iter() {
+                        let (a, b) = (previous.scale * corner, pose.scale * corner);
+                        let radius = a.magnitude().max(b.magnitude());
+                        let arc = radius * (1.0 - (step_angle / 2.0).cos());
+                        padding = padding.max(arc + (b - a).magnitude() * step_angle);
+                    }
+                }
+                previous = Some(pose);
+            }
+        }
+        let padding = Vec3f::repeat(padding);
+        Some(Bound {
+            min: min - padding,
+            max: max + padding,
+        })
+    }
+
+    fn is_convex(&self) -> bool {
+        self.item.is_convex()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::test_util::{self, gray};
+    use crate::math::{Cube, Sphere};
+    use rand::Rng;
+
+    fn sphere() -> Sphere {
+        test_util::sphere(Vec3f::new(1.0, 0.0, 0.0), 1.0)
+    }
+
+    /// A box away from the origin, that turns 170° and then 60° back as it moves and grows.
+    fn animation() -> AnimatedTransform {
+        let cube = Cube::new(Vec3f::new(1.0, 0.0, 0.0), Vec3f::new(2.0, 0.5, 1.0), gray());
+        let axis = Vec3f::new(1.0, 1.0, 0.0);
+        let keyframes = vec![
+            Keyframe::new(0.0),
+            Keyframe {
+                translation: Vec3f::new(0.0, 2.0, -1.0),
+                rotation: Quaternion::from_axis_angle(axis, 170.0),
+                scale: Vec3f::new(2.0, 1.0, 1.5),
+                ..Keyframe::new(1.0)
+            },
+            Keyframe {
+                rotation: Quaternion::from_axis_angle(axis, 110.0),
+                ..Keyframe::new(2.0)
+            },
+        ];
+        AnimatedTransform::new(Box::new(cube), keyframes)
+    }
+
+    #[test]
+    fn animated_bounds_hold_every_pose() {
+        let animation = animation();
+        let corners = corners(animation.item.bounding_box(0.0, 0.0).unwrap());
+        let mut sampler = Sampler::new(0);
+        for (start, end) in [(0.0, 2.0), (0.2, 0.9), (0.5, 1.5), (-1.0, 3.0)] {
+            let bound = animation.bounding_box(start, end).unwrap();
+            for _ in 0..1000 {
+                let time = sampler.gen_range(start..end);
+                let matrix = animation.pose(time).matrix();
+                for &corner in corners.iter() {
+                    let corner = matrix.transform_point(corner);
+                    let inside = (0..3).all(|coord| {
+                        bound.min[coord] <= corner[coord] && corner[coord] <= bound.max[coord]
+                    });
+                    assert!(inside, "at {} in {}..{}", time, start, end);
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn poses_hold_outside_of_the_keyframes() {
+        let animation = animation();
+        let (first, last) = (animation.keyframes[0], animation.keyframes[2]);
+        for (time, keyframe) in [(-5.0, first), (0.0, first), (2.0, last), (100.0, last)] {
+            let pose = animation.pose(time);
+            assert_eq!(pose.time, time);
+            let expected = keyframe.matrix();
+            for (row, expected) in pose.matrix().m.iter().zip(&expected.m) {
+                assert_eq!(row, expected);
+            }
+        }
+    }
+
+    #[test]
+    fn nested_transforms_fold() {
+        let a = Matrix4::rotation(Vec3f::new(0.0, 1.0, 1.0), 40.0);
+        let b = Matrix4::translation(Vec3f::new(0.0, 2.0, -3.0))
+            * Matrix4::scaling(Vec3f::new(2.0, 1.0, 1.0));
+        let nested = sphere().transform(a).transform(b);
+        let single = Transform::new(Box::new(sphere()), b * a);
+        for (row, expected) in nested.matrix().m.iter().zip(&single.matrix().m) {
+            for (x, y) in row.iter().zip(expected) {
+                assert!((x - y).abs() < 1e-12);
+            }
+        }
+
+        let mut sampler = Sampler::new(0);
+        let mut hits = 0;
+        for i in 0..16 {
+            let angle = i as f64 / 16.0 * std::f64::consts::TAU;
+            let ray = Ray {
+                a: Vec3f::new(10.0 * angle.cos(), 10.0 * angle.sin(), -3.0),
+                b: Vec3f::new(-angle.cos(), -angle.sin() + 0.2, 0.0),
+                ..Ray::default()
+            };
+            let nested = nested.hit(ray, 0.001, f64::INFINITY, &mut sampler);
+            let single = single.hit(ray, 0.001, f64::INFINITY, &mut sampler);
+            assert_eq!(nested.is_some(), single.is_some());
+            if let (Some(nested), Some(single)) = (nested, single) {
+                assert!((nested.t - single.t).abs() < 1e-9);
+                assert!((nested.normal - single.normal).magnitude() < 1e-9);
+                hits += 1;
+            }
+        }
+        assert!(hits > 0);
+    }
+}
@@ -16,6 +16,8 @@ type Precision = f64;
 /// by not allowing operations in two conceptually different vectors
 ///
 /// ```
+/// use rttnw::math::{Color, Position, Vec3f};
+///
 /// let v1: Vec3f<Color> = (1.0, 0.0, 0.0).into();
 /// let v2: Vec3f<Color> = (0.0, 2.0, 0.0).into();
 /// let v3: Vec3f<Position> = (0.0, 0.0, 3.0).into();
@@ -23,6 +25,7 @@ type Precision = f64;
 /// let v4 = v1 + v2;
 /// // This is not fine
 /// // let v4 = v1 + v3;
+/// # let _ = (v3, v4);
 /// ```
 pub struct Vec3f<T> {
     items: [Precision; 3],
@@ -36,6 +39,11 @@ impl<T: Phantom> Clone for Vec3f<T> {
         *self
     }
 }
+impl<T: Phantom> PartialEq for Vec3f<T> {
+    fn eq(&self, other: &Self) -> bool {
+        self.items == other.items
+    }
+}
 
 impl<T> Vec3f<T>
 where
@@ -49,14 +57,13 @@ where
             items[2] as Precision * scale,
         )
     }
-    pub fn new(x: Precision, y: Precision, z: Precision) -> Self {
+    pub const fn new(x: Precision, y: Precision, z: Precision) -> Self {
         Self {
             items: [x, y, z],
             _phantom: PhantomData::<T>,
         }
     }
-    pub fn random(range: std::ops::Range<f64>) -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random<R: Rng + ?Sized>(range: std::ops::Range<f64>, rng: &mut R) -> Self {
         let x = rng.gen_range(range.clone());
         let y = rng.gen_range(range.clone());
         let z = rng.gen_range(range);
@@ -147,8 +154,7 @@ impl<T: Phantom> Default for Vec3f<T> {
 }
 
 impl Vec3f<Position> {
-    pub fn random_in_unit_space() -> Self {
-        let mut rng = rand::thread_rng();
+    pub fn random_in_unit_space<R: Rng + ?Sized>(rng: &mut R) -> Self {
         loop {
             // Random point where (x, y, z) belong to -1..1
             let vector = 2.0 * Vec3f::new(rng.gen(), rng.gen(), rng.gen()) - Vec3f::repeat(1.0);
@@ -157,6 +163,17 @@ impl Vec3f<Position> {
             }
         }
     }
+
+    /// Random direction, uniformly distributed over the unit sphere.
+    pub fn random_unit_vector<R: Rng + ?Sized>(rng: &mut R) -> Self {
+        loop {
+            let vector = Self::random_in_unit_space(rng);
+            let squared_length = vector.squared_length();
+            if squared_length > 1e-12 {
+                return vector / squared_length.sqrt();
+            }
+        }
+    }
 }
 impl<T> Vec3f<T>
 where
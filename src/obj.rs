@@ -0,0 +1,470 @@
+//! Wavefront OBJ and MTL loader.
+//!
+//! Every group (`g` or `o`) is split into one [`TriangleMesh`] per material (`usemtl`).
+//! Polygons are triangulated as fans. The MTL parameters are mapped onto the existing materials:
+//!
+//! - Any of the PBR extensions `Pr` (roughness), `Pm` (metallic), `Ps` (sheen), `Pc` (clearcoat),
+//!   `map_Pr` or `map_Pm`: [`Principled`] with the `Kd` color or the `map_Kd` image, a
+//!   transmission of `1 - d`, the `Ke` emission and the `Ni` refraction index.
+//! - `Ke` (emission) other than black: [`DiffuseLight`].
+//! - `d` (dissolve) lower than 1, `Tr` greater than 0, or `illum` 4, 6, 7 or 9: [`Dielectric`]
+//!   with the `Ni` refraction index.
+//! - `illum` 3 or 5, or a black `Kd` and a non-black `Ks`: [`Metal`] with the `Ks` albedo
+//!   and a fuzz derived from the `Ns` specular exponent.
+//! - Anything else: [`Lambertian`] with the `Kd` color, or the `map_Kd` image.
+
+use std::collections::{BTreeMap, HashMap};
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+use std::sync::Arc;
+
+use crate::math::{
+    BvhTree, Color, Dielectric, DiffuseLight, ImageTexture, Lambertian, List, Material, Metal,
+    Position, Principled, Texture, TriangleMesh, Vec3f,
+};
+
+#[derive(Debug)]
+pub enum ObjError {
+    Io(PathBuf, std::io::Error),
+    /// Malformed line of a file, counting from 1.
+    Parse {
+        path: PathBuf,
+        line: usize,
+        message: String,
+    },
+}
+
+impl Display for ObjError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            ObjError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            ObjError::Parse {
+                path,
+                line,
+                message,
+            } => write!(f, "{}:{}: {}", path.display(), line, message),
+        }
+    }
+}
+
+impl std::error::Error for ObjError {}
+
+/// Position in a file, to report errors.
+struct Line<'a> {
+    path: &'a Path,
+    number: usize,
+}
+
+impl Line<'_> {
+    fn error<T>(&self, message: String) -> Result<T, ObjError> {
+        Err(ObjError::Parse {
+            path: self.path.to_owned(),
+            line: self.number,
+            message,
+        })
+    }
+
+    fn number<T: FromStr>(&self, token: Option<&str>) -> Result<T, ObjError> {
+        match token {
+            Some(token) => token
+                .parse()
+                .or_else(|_| self.error(format!("Invalid number {:?}", token))),
+            None => self.error(String::from("Missing number")),
+        }
+    }
+
+    fn triple<'t, I: Iterator<Item = &'t str>>(
+        &self,
+        tokens: &mut I,
+    ) -> Result<[f64; 3], ObjError> {
+        Ok([
+            self.number(tokens.next())?,
+            self.number(tokens.next())?,
+            self.number(tokens.next())?,
+        ])
+    }
+}
+
+fn read(path: &Path) -> Result<String, ObjError> {
+    std::fs::read_to_string(path).map_err(|e| ObjError::Io(path.to_owned(), e))
+}
+
+/// Parameters of a `newmtl` entry.
+struct MtlMaterial {
+    diffuse: [f64; 3],
+    specular: [f64; 3],
+    emission: [f64; 3],
+    specular_exponent: f64,
+    dissolve: f64,
+    refraction_index: f64,
+    illumination: u32,
+    diffuse_map: Option<PathBuf>,
+    /// Parameters of the PBR extension, `None` if they are not given.
+    roughness: Option<f64>,
+    metallic: Option<f64>,
+    sheen: Option<f64>,
+    clearcoat: Option<f64>,
+    roughness_map: Option<PathBuf>,
+    metallic_map: Option<PathBuf>,
+}
+
+impl Default for MtlMaterial {
+    fn default() -> Self {
+        Self {
+            diffuse: [0.8; 3],
+            specular: [0.0; 3],
+            emission: [0.0; 3],
+            specular_exponent: 0.0,
+            dissolve: 1.0,
+            refraction_index: 1.5,
+            illumination: 2,
+            diffuse_map: None,
+            roughness: None,
+            metallic: None,
+            sheen: None,
+            clearcoat: None,
+            roughness_map: None,
+            metallic_map: None,
+        }
+    }
+}
+
+impl MtlMaterial {
+    fn is_principled(&self) -> bool {
+        self.roughness.is_some()
+            || self.metallic.is_some()
+            || self.sheen.is_some()
+            || self.clearcoat.is_some()
+            || self.roughness_map.is_some()
+            || self.metallic_map.is_some()
+    }
+
+    /// The texture of a parameter: its map, its value, or else `default`.
+    fn parameter(
+        value: Option<f64>,
+        map: &Option<PathBuf>,
+        default: &Arc<dyn Texture>,
+    ) -> Arc<dyn Texture> {
+        match (value, map) {
+            (_, Some(map)) => Arc::new(ImageTexture::new(map)),
+            (Some(value), None) => Arc::new(Vec3f::<Color>::repeat(value)),
+            (None, None) => Arc::clone(default),
+        }
+    }
+
+    fn principled(&self) -> Principled {
+        let color = |[r, g, b]: [f64; 3]| Vec3f::<Color>::new(r, g, b);
+        let base_color: Arc<dyn Texture> = match &self.diffuse_map {
+            Some(map) => Arc::new(ImageTexture::new(map)),
+            None => Arc::new(color(self.diffuse)),
+        };
+        // The parameters that aren't given keep the defaults of the material
+        let defaults = Principled::new(base_color);
+        let transmission = if matches!(self.illumination, 4 | 6 | 7 | 9) {
+            1.0
+        } else {
+            1.0 - self.dissolve
+        };
+        Principled {
+            metallic: Self::parameter(self.metallic, &self.metallic_map, &defaults.metallic),
+            roughness: Self::parameter(self.roughness, &self.roughness_map, &defaults.roughness),
+            sheen: Self::parameter(self.sheen, &None, &defaults.sheen),
+            clearcoat: Self::parameter(self.clearcoat, &None, &defaults.clearcoat),
+            transmission: Self::parameter(Some(transmission), &None, &defaults.transmission),
+            emission: if self.emission.iter().all(|&c| c <= 0.0) {
+                None
+            } else {
+                Some(Arc::new(color(self.emission)))
+            },
+            refraction_index: self.refraction_index,
+            ..defaults
+        }
+    }
+
+    fn material(&self) -> Arc<dyn Material> {
+        let black = |x: [f64; 3]| x.iter().all(|&c| c <= 0.0);
+        let color = |[r, g, b]: [f64; 3]| Vec3f::<Color>::new(r, g, b);
+        if self.is_principled() {
+            Arc::new(self.principled())
+        } else if !black(self.emission) {
+            DiffuseLight::arc(color(self.emission))
+        } else if self.dissolve < 1.0 || matches!(self.illumination, 4 | 6 | 7 | 9) {
+            Dielectric::arc(self.refraction_index)
+        } else if matches!(self.illumination, 3 | 5)
+            || (black(self.diffuse) && !black(self.specular))
+        {
+            // Phong exponent to roughness, as in Walter et al. 2007
+            let fuzz = (2.0 / (self.specular_exponent + 2.0)).sqrt();
+            Metal::arc(color(self.specular), fuzz)
+        } else if let Some(map) = &self.diffuse_map {
+            Lambertian::<ImageTexture>::arc(ImageTexture::new(map))
+        } else {
+            Lambertian::arc(color(self.diffuse))
+        }
+    }
+}
+
+/// Reads the materials of a MTL file.
+fn load_mtl(path: &Path) -> Result<BTreeMap<String, Arc<dyn Material>>, ObjError> {
+    let text = read(path)?;
+    let directory = path.parent().unwrap_or_else(|| Path::new(""));
+    let mut materials = Vec::<(String, MtlMaterial)>::new();
+    for (number, content) in text.lines().enumerate() {
+        let line = Line {
+            path,
+            number: number + 1,
+        };
+        let content = content.split('#').next().unwrap_or_default();
+        let mut tokens = content.split_whitespace();
+        let keyword = match tokens.next() {
+            Some(keyword) => keyword,
+            None => continue,
+        };
+        if keyword == "newmtl" {
+            match tokens.next() {
+                Some(name) => materials.push((name.to_owned(), MtlMaterial::default())),
+                None => return line.error(String::from("Missing material name")),
+            }
+            continue;
+        }
+        let material = match materials.last_mut() {
+            Some((_, material)) => material,
+            None => return line.error(format!("{} before newmtl", keyword)),
+        };
+        match keyword {
+            "Kd" => material.diffuse = line.triple(&mut tokens)?,
+            "Ks" => material.specular = line.triple(&mut tokens)?,
+            "Ke" => material.emission = line.triple(&mut tokens)?,
+            "Ns" => material.specular_exponent = line.number(tokens.next())?,
+            "d" => material.dissolve = line.number(tokens.next())?,
+            "Tr" => material.dissolve = 1.0 - line.number::<f64>(tokens.next())?,
+            "Ni" => material.refraction_index = line.number(tokens.next())?,
+            "illum" => material.illumination = line.number(tokens.next())?,
+            "Pr" => material.roughness = Some(line.number(tokens.next())?),
+            "Pm" => material.metallic = Some(line.number(tokens.next())?),
+            "Ps" => material.sheen = Some(line.number(tokens.next())?),
+            "Pc" => material.clearcoat = Some(line.number(tokens.next())?),
+            // The options of the map come before the file name
+            "map_Kd" | "map_Pr" | "map_Pm" => {
+                let file = match tokens.last() {
+                    Some(file) => directory.join(file),
+                    None => return line.error(String::from("Missing texture file")),
+                };
+                match keyword {
+                    "map_Kd" => material.diffuse_map = Some(file),
+                    "map_Pr" => material.roughness_map = Some(file),
+                    _ => material.metallic_map = Some(file),
+                }
+            }
+            _ => {}
+        }
+    }
+    Ok(materials
+        .into_iter()
+        .map(|(name, material)| (name, material.material()))
+        .collect())
+}
+
+/// Triangles of a group with a single material.
+/// The vertices are the distinct `position/uv/normal` triples used by the faces.
+#[derive(Default)]
+struct MeshBuilder {
+    vertices: HashMap<(usize, Option<usize>, Option<usize>), usize>,
+    positions: Vec<Vec3f<Position>>,
+    uvs: Vec<Option<(f64, f64)>>,
+    normals: Vec<Option<Vec3f<Position>>>,
+    triangles: Vec<[usize; 3]>,
+}
+
+/// A mesh of an OBJ file.
+pub struct ObjMesh {
+    /// Name of the group, empty for faces outside any group.
+    pub group: String,
+    /// Name of the material, empty for faces without `usemtl`.
+    pub material: String,
+    pub mesh: TriangleMesh,
+}
+
+/// The contents of an OBJ file.
+pub struct Obj {
+    pub meshes: Vec<ObjMesh>,
+}
+
+impl Obj {
+    /// Reads an OBJ file and the MTL files it references.
+    /// Faces without a material use `default_material`.
+    pub fn load<P: AsRef<Path>>(
+        path: P,
+        default_material: Arc<dyn Material>,
+    ) -> Result<Self, ObjError> {
+        let path = path.as_ref();
+        let text = read(path)?;
+        let directory = path.parent().unwrap_or_else(|| Path::new(""));
+
+        let mut materials = BTreeMap::new();
+        let mut positions = Vec::<Vec3f<Position>>::new();
+        let mut uvs = Vec::<(f64, f64)>::new();
+        let mut normals = Vec::<Vec3f<Position>>::new();
+        // In order of appearance, and indexed by group and material.
+        let mut meshes = Vec::<((String, String), MeshBuilder)>::new();
+        let mut mesh_indices = HashMap::<(String, String), usize>::new();
+        let mut group = String::new();
+        let mut material = String::new();
+        // The mesh of the current group and material, once a face uses it
+        let mut current = None;
+
+        for (number, content) in text.lines().enumerate() {
+            let line = Line {
+                path,
+                number: number + 1,
+            };
+            let content = content.split('#').next().unwrap_or_default();
+            let mut tokens = content.split_whitespace();
+            match tokens.next() {
+                Some("v") => {
+                    let [x, y, z] = line.triple(&mut tokens)?;
+                    positions.push(Vec3f::new(x, y, z));
+                }
+                Some("vt") => {
+                    let u = line.number(tokens.next())?;
+                    let v = tokens.next().map_or(Ok(0.0), |v| line.number(Some(v)))?;
+                    uvs.push((u, v));
+                }
+                Some("vn") => {
+                    let [x, y, z] = line.triple(&mut tokens)?;
+                    normals.push(Vec3f::new(x, y, z));
+                }
+                Some("g") | Some("o") => {
+                    group = tokens.collect::<Vec<_>>().join(" ");
+                    current = None;
+                }
+                Some("usemtl") => match tokens.next() {
+                    Some(name) if materials.contains_key(name) => {
+                        material = name.to_owned();
+                        current = None;
+                    }
+                    Some(name) => return line.error(format!("Unknown material {:?}", name)),
+                    None => return line.error(String::from("Missing material name")),
+                },
+                Some("mtllib") => {
+                    for file in tokens {
+                        materials.append(&mut load_mtl(&directory.join(file))?);
+                    }
+                }
+                Some("f") => {
+                    let index = *current.get_or_insert_with(|| {
+                        let key = (group.clone(), material.clone());
+                        *mesh_indices.entry(key.clone()).or_insert_with(|| {
+                            meshes.push((key, MeshBuilder::default()));
+                            meshes.len() - 1
+                        })
+                    });
+                    let mesh = &mut meshes[index].1;
+                    let mut face = Vec::new();
+                    for vertex in tokens {
+                        let mut indices = vertex.split('/');
+                        let index = |token: Option<&str>, length: usize| match token {
+                            None | Some("") => Ok(None),
+                            Some(token) => {
+                                let index: i64 = line.number(Some(token))?;
+                                // Negative indices count from the end
+                                let resolved = if index < 0 {
+                                    length as i64 + index
+                                } else {
+                                    index - 1
+                                };
+                                if index == 0 || resolved < 0 || resolved >= length as i64 {
+                                    line.error(format!("Index {} out of bounds", index))
+                                } else {
+                                    Ok(Some(resolved as usize))
+                                }
+                            }
+                        };
+                        let position = match index(indices.next(), positions.len())? {
+                            Some(position) => position,
+                            None => return line.error(format!("Invalid vertex {:?}", vertex)),
+                        };
+                        let uv = index(indices.next(), uvs.len())?;
+                        let normal = index(indices.next(), normals.len())?;
+                        let key = (position, uv, normal);
+                        let next = mesh.positions.len();
+                        let vertex = *mesh.vertices.entry(key).or_insert(next);
+                        if vertex == next {
+                            mesh.positions.push(positions[position]);
+                            mesh.uvs.push(uv.map(|i| uvs[i]));
+                            mesh.normals.push(normal.map(|i| normals[i]));
+                        }
+                        face.push(vertex);
+                    }
+                    if face.len() < 3 {
+                        return line.error(String::from("A face needs at least 3 vertices"));
+                    }
+                    for i in 1..face.len() - 1 {
+                        mesh.triangles.push([face[0], face[i], face[i + 1]]);
+                    }
+                }
+                // Smoothing groups, comments and unsupported statements
+                _ => {}
+            }
+        }
+
+        let meshes = meshes
+            .into_iter()
+            .map(|((group, material), builder)| {
+                let material_arc = materials
+                    .get(&material)
+                    .cloned()
+                    .unwrap_or_else(|| default_material.clone());
+                let mut mesh =
+                    TriangleMesh::new(builder.positions, builder.triangles, material_arc);
+                // Attributes are only used if every vertex has them
+                if let Some(normals) = builder.normals.into_iter().collect() {
+                    mesh = mesh.with_normals(normals);
+                }
+                if let Some(uvs) = builder.uvs.into_iter().collect() {
+                    mesh = mesh.with_uvs(uvs);
+                }
+                ObjMesh {
+                    group,
+                    material,
+                    mesh,
+                }
+            })
+            .collect();
+        Ok(Self { meshes })
+    }
+
+    /// All the meshes in a single hierarchy.
+    ///
+    /// # Panics
+    /// If there are no meshes.
+    pub fn into_bvh(self) -> BvhTree {
+        let mut list = List::with_capacity(self.meshes.len());
+        for mesh in self.meshes {
+            list.push(mesh.mesh);
+        }
+        BvhTree::from(list)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn faces_are_grouped_by_group_and_material() {
+        let path = std::env::temp_dir().join(format!("rttnw-groups-{}.obj", std::process::id()));
+        let text = "v 0 0 0\nv 1 0 0\nv 0 1 0\nv 0 0 1\n\
+                    g a\nf 1 2 3\ng b\nf 1 2 4\ng a\nf 1 3 4\nf 2 3 4\n";
+        std::fs::write(&path, text).unwrap();
+        let obj = Obj::load(&path, Lambertian::arc(Vec3f::<Color>::repeat(0.5)));
+        std::fs::remove_file(&path).unwrap();
+        let meshes = obj.unwrap().meshes;
+        let groups: Vec<_> = meshes
+            .iter()
+            .map(|mesh| (mesh.group.as_str(), mesh.mesh.len()))
+            .collect();
+        assert_eq!(groups, [("a", 3), ("b", 1)]);
+    }
+}
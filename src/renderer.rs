@@ -0,0 +1,536 @@
+use indicatif::{ParallelProgressIterator, ProgressBar, ProgressStyle};
+use rand::Rng;
+use rayon::iter::{IndexedParallelIterator, IntoParallelIterator, ParallelIterator};
+
+use std::ops::{Add, Div, Mul};
+use std::path::Path;
+
+use crate::math::{
+    Camera, CameraDescriptor, Color, HitRecord, Hittable, Lights, Material, Ray, SampledSpectrum,
+    SampledWavelengths, Sampler, Vec3f,
+};
+use crate::scene::Scene;
+
+#[repr(C)]
+#[derive(Copy, Clone, bytemuck::Pod, bytemuck::Zeroable)]
+struct Rgba {
+    r: u8,
+    g: u8,
+    b: u8,
+    a: u8,
+}
+
+/// Weight of a sample taken with density `pdf`, when another technique
+/// could have taken it with density `other`, by the power heuristic.
+fn power_heuristic(pdf: f64, other: f64) -> f64 {
+    let (pdf, other) = (pdf * pdf, other * other);
+    if pdf + other > 0.0 {
+        pdf / (pdf + other)
+    } else {
+        0.0
+    }
+}
+
+/// How the light along a path is represented: as RGB, or at a few sampled wavelengths.
+trait LightModel {
+    type Value: Copy
+        + Add<Output = Self::Value>
+        + Mul<Output = Self::Value>
+        + Mul<f64, Output = Self::Value>
+        + Div<f64, Output = Self::Value>;
+
+    fn repeat(&self, x: f64) -> Self::Value;
+    /// Light, or a fraction of it, given as a color.
+    fn upsample(&self, color: Vec3f<Color>) -> Self::Value;
+    fn to_rgb(&self, value: Self::Value) -> Vec3f<Color>;
+    fn max_value(value: Self::Value) -> f64;
+    /// Exponential of each channel or wavelength.
+    fn exp(value: Self::Value) -> Self::Value;
+    /// The path went through a dispersive material, in a direction chosen for its ray.
+    fn disperse(&mut self) {}
+}
+
+struct Rgb;
+
+impl LightModel for Rgb {
+    type Value = Vec3f<Color>;
+
+    fn repeat(&self, x: f64) -> Vec3f<Color> {
+        Vec3f::repeat(x)
+    }
+
+    fn upsample(&self, color: Vec3f<Color>) -> Vec3f<Color> {
+        color
+    }
+
+    fn to_rgb(&self, value: Vec3f<Color>) -> Vec3f<Color> {
+        value
+    }
+
+    fn max_value(value: Vec3f<Color>) -> f64 {
+        value.r().max(value.g()).max(value.b())
+    }
+
+    fn exp(value: Vec3f<Color>) -> Vec3f<Color> {
+        value.map(f64::exp)
+    }
+}
+
+impl LightModel for SampledWavelengths {
+    type Value = SampledSpectrum;
+
+    fn repeat(&self, x: f64) -> SampledSpectrum {
+        SampledSpectrum::repeat(x)
+    }
+
+    fn upsample(&self, color: Vec3f<Color>) -> SampledSpectrum {
+        SampledWavelengths::upsample(self, color)
+    }
+
+    fn to_rgb(&self, value: SampledSpectrum) -> Vec3f<Color> {
+        SampledWavelengths::to_rgb(self, value)
+    }
+
+    fn max_value(value: SampledSpectrum) -> f64 {
+        value.max_value()
+    }
+
+    fn exp(value: SampledSpectrum) -> SampledSpectrum {
+        value.exp()
+    }
+
+    /// Only the hero wavelength was refracted in the direction of the ray.
+    fn disperse(&mut self) {
+        self.terminate_secondary();
+    }
+}
+
+/// Renders scenes into a [`Framebuffer`].
+pub struct Renderer {
+    pub width: u32,
+    pub height: u32,
+    /// Samples per pixel
+    pub samples: usize,
+    /// Maximum number of bounces of a ray
+    pub max_depth: i32,
+    /// Bounces after which Russian roulette may end a path, with a probability
+    /// that grows as less light can be carried by it.
+    pub roulette_depth: i32,
+    /// Seed of the random numbers. The same seed always renders the same image.
+    pub seed: u64,
+    /// Show a progress bar in the terminal while rendering
+    pub progress: bool,
+    /// Trace each path at a few random wavelengths instead of in RGB, so dispersive
+    /// materials split light into its colors.
+    pub spectral: bool,
+}
+
+impl Renderer {
+    pub fn new(width: u32, height: u32, samples: usize) -> Self {
+        Self {
+            width,
+            height,
+            samples,
+            max_depth: 50,
+            roulette_depth: 5,
+            seed: 0,
+            progress: false,
+            spectral: false,
+        }
+    }
+
+    /// Renders the scene with the scene's camera, adjusted to the aspect ratio of the image.
+    pub fn render(&self, scene: &Scene) -> Framebuffer {
+        let camera = Camera::new(&CameraDescriptor {
+            aspect_ratio: self.width as f64 / self.height as f64,
+            ..scene.camera
+        });
+        self.render_with(&scene.world, &camera, scene.background)
+    }
+
+    /// The color of a camera ray, traced in RGB or at random wavelengths.
+    fn trace<T: Hittable>(
+        &self,
+        ray: Ray,
+        background: Vec3f<Color>,
+        world: &T,
+        lights: &Lights,
+        sampler: &mut Sampler,
+    ) -> Vec3f<Color> {
+        if self.spectral {
+            let mut wavelengths = SampledWavelengths::sample(sampler.gen());
+            let ray = Ray {
+                wavelength: Some(wavelengths.hero()),
+                ..ray
+            };
+            self.color(ray, background, world, lights, &mut wavelengths, sampler)
+        } else {
+            self.color(ray, background, world, lights, &mut Rgb, sampler)
+        }
+    }
+
+    /// The resulting color of a ray pointing to a direction.
+    ///
+    /// At every non-specular bounce a light is sampled directly, and both that sample and
+    /// the scattered ray are weighted by multiple importance sampling.
+    /// Inside absorbing dielectrics, the light is attenuated by the distance travelled.
+    /// Only the objects entered by the path count, so a camera inside absorbing glass
+    /// sees no absorption until the path enters it again.
+    fn color<T: Hittable, M: LightModel>(
+        &self,
+        mut ray: Ray,
+        background: Vec3f<Color>,
+        world: &T,
+        lights: &Lights,
+        model: &mut M,
+        sampler: &mut Sampler,
+    ) -> Vec3f<Color> {
+        let mut radiance = model.repeat(0.0);
+        // Fraction of the light at the current bounce that reaches the camera
+        let mut throughput = model.repeat(1.0);
+        // Density with which `ray` was scattered, `None` for camera rays and specular bounces
+        let mut scattering_pdf = None;
+        // Materials and absorption of the objects the ray is inside of, the innermost last
+        let mut interior = Vec::<(&dyn Material, Vec3f<Color>)>::new();
+        // If the ray bounce limit is reached, no more light is gathered.
+        for depth in 0..self.max_depth {
+            // If the ray hits something
+            // `t_min` is not 0.0 to avoid the shadow acne problem
+            let record = match world.hit(ray, 0.001, f64::MAX, sampler) {
+                Some(record) => record,
+                None => {
+                    radiance = radiance + throughput * model.upsample(background);
+                    break;
+                }
+            };
+            if let Some(&(_, absorption)) = interior.last() {
+                // Beer-Lambert law, at each wavelength of the absorption
+                let distance = record.t * ray.direction().magnitude();
+                throughput = throughput * M::exp(model.upsample(absorption) * -distance);
+            }
+            let mut emitted = record.material.emitted(record.u, record.v, record.p);
+            if let Some(pdf) = scattering_pdf {
+                if record.material.is_emissive() {
+                    emitted = emitted * power_heuristic(pdf, lights.pdf_value(ray, sampler));
+                }
+            }
+            radiance = radiance + throughput * model.upsample(emitted);
+
+            // New random point at a random direction. Where the ray is reflected.
+            let sample = match record.material.sample(ray, record, sampler) {
+                Some(sample) => sample,
+                None => break,
+            };
+            scattering_pdf = if sample.specular || lights.is_empty() {
+                None
+            } else {
+                let direct = Self::direct_light(ray, record, world, lights, model, sampler);
+                radiance = radiance + throughput * direct;
+                Some(sample.pdf)
+            };
+            // The ray enters or leaves a closed object through its surface
+            if let Some(absorption) = record.material.absorption() {
+                if sample.direction.dot(record.normal) < 0.0 {
+                    if record.front_face {
+                        interior.push((record.material, absorption));
+                    } else {
+                        // Overlapping objects aren't always left in the order they were entered
+                        let leaving = |&(material, _): &(&dyn Material, _)| {
+                            std::ptr::addr_eq(material, record.material)
+                        };
+                        if let Some(index) = interior.iter().rposition(leaving) {
+                            interior.remove(index);
+                        }
+                    }
+                }
+            }
+            if record.material.is_dispersive() {
+                model.disperse();
+            }
+            throughput = throughput * model.upsample(sample.weight());
+            ray = Ray {
+                a: record.p,
+                b: sample.direction,
+                ..ray
+            };
+
+            if depth + 1 >= self.roulette_depth {
+                let survival = M::max_value(throughput).min(0.95);
+                if sampler.gen::<f64>() >= survival {
+                    break;
+                }
+                throughput = throughput / survival;
+            }
+        }
+        model.to_rgb(radiance)
+    }
+
+    /// Light arriving at `record` straight from a random light, weighted against
+    /// finding the light by sampling the material.
+    fn direct_light<T: Hittable, M: LightModel>(
+        ray: Ray,
+        record: HitRecord,
+        world: &T,
+        lights: &Lights,
+        model: &M,
+        sampler: &mut Sampler,
+    ) -> M::Value {
+        let shadow_ray = Ray {
+            a: record.p,
+            b: lights.random(record.p, sampler),
+            ..ray
+        };
+        let light_pdf = lights.pdf_value(shadow_ray, sampler);
+        match world.hit(shadow_ray, 0.001, f64::MAX, sampler) {
+            Some(light) if light_pdf > 0.0 && light.material.is_emissive() => {
+                let direction = shadow_ray.direction();
+                let bsdf = record.material.eval(ray, record, direction);
+                let bsdf_pdf = record.material.scattering_pdf(ray, record, direction);
+                let radiance = light.material.emitted(light.u, light.v, light.p);
+                let weight = power_heuristic(light_pdf, bsdf_pdf) / light_pdf;
+                model.upsample(bsdf) * model.upsample(radiance) * weight
+            }
+            _ => model.repeat(0.0),
+        }
+    }
+
+    /// Renders any world seen from `camera`.
+    pub fn render_with<T: Hittable>(
+        &self,
+        world: &T,
+        camera: &Camera,
+        background: Vec3f<Color>,
+    ) -> Framebuffer {
+        let Self {
+            width,
+            height,
+            samples,
+            seed,
+            ..
+        } = *self;
+        let lights = Lights::new(world);
+        let progress = if self.progress {
+            ProgressBar::new(height as u64).with_style(
+                ProgressStyle::default_spinner().template("{pos}/{len} {spinner:.dim.bold}"),
+            )
+        } else {
+            ProgressBar::hidden()
+        };
+        // For each pixel
+        let pixels = (0..height)
+            .into_par_iter()
+            .rev()
+            .progress_with(progress)
+            .flat_map(|j| {
+                (0..width)
+                    .into_par_iter()
+                    .map(|i| {
+                        let pixel = (height - 1 - j) as u64 * width as u64 + i as u64;
+                        // Calculate the color `samples` times and average the result
+                        (0..samples).fold(Vec3f::<Color>::repeat(0.0), |acc, sample| {
+                            let mut sampler = Sampler::for_sample(seed, pixel, sample as u64);
+                            let u = (i as f64 + sampler.gen::<f64>()) / width as f64;
+                            let v = (j as f64 + sampler.gen::<f64>()) / height as f64;
+                            let ray = camera.ray(u, v, &mut sampler);
+                            acc + self.trace(ray, background, world, &lights, &mut sampler)
+                        }) / samples as f64
+                    })
+                    .collect::<Vec<_>>()
+            })
+            .collect();
+        Framebuffer {
+            width,
+            height,
+            pixels,
+        }
+    }
+}
+
+/// An image in linear color space, stored row by row from the top left corner.
+pub struct Framebuffer {
+    pub width: u32,
+    pub height: u32,
+    pub pixels: Vec<Vec3f<Color>>,
+}
+
+impl Framebuffer {
+    pub fn pixel(&self, x: u32, y: u32) -> Vec3f<Color> {
+        self.pixels[(y * self.width + x) as usize]
+    }
+
+    /// Gamma corrected 8-bit RGBA bytes.
+    pub fn to_rgba8(&self) -> Vec<u8> {
+        let image: Vec<Rgba> = self
+            .pixels
+            .iter()
+            .map(|col| {
+                // Gamma correction
+                let col = col.map(|x| x.sqrt().clamp(0.0, 0.999) * 256.);
+                Rgba {
+                    r: col.x() as u8,
+                    g: col.y() as u8,
+                    b: col.z() as u8,
+                    a: 255,
+                }
+            })
+            .collect();
+        bytemuck::cast_slice(&image).to_vec()
+    }
+
+    /// Saves the image, the format is deduced from the extension of `path`.
+    pub fn save<P: AsRef<Path>>(&self, path: P) -> image::ImageResult<()> {
+        image::save_buffer(
+            path,
+            &self.to_rgba8(),
+            self.width,
+            self.height,
+            image::ColorType::Rgba8,
+        )
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::math::{BsdfSample, Cube, Dielectric, List, Position, Sphere};
+    use std::sync::Arc;
+
+    /// Glows white, and sends the light back where it comes from, attenuated by `albedo`.
+    struct Retroreflector {
+        albedo: f64,
+    }
+
+    impl Material for Retroreflector {
+        fn sample(&self, ray: Ray, _: HitRecord, _: &mut Sampler) -> Option<BsdfSample> {
+            Some(BsdfSample {
+                direction: -ray.direction(),
+                bsdf: Vec3f::repeat(self.albedo),
+                pdf: 1.0,
+                specular: true,
+            })
+        }
+
+        fn emitted(&self, _: f64, _: f64, _: Vec3f<Position>) -> Vec3f<Color> {
+            Vec3f::repeat(1.0)
+        }
+
+        fn is_emissive(&self) -> bool {
+            true
+        }
+    }
+
+    /// The light gathered by a path bouncing back and forth inside a retroreflecting
+    /// sphere, which is `albedo` to the power of each bounce before the last, summed.
+    fn bounces(renderer: &Renderer, albedo: f64, seed: u64) -> f64 {
+        let mut world = List::new();
+        world.push(Sphere {
+            center: Vec3f::repeat(0.0),
+            radius: 1.0,
+            material: Arc::new(Retroreflector { albedo }),
+        });
+        let ray = Ray {
+            a: Vec3f::repeat(0.0),
+            b: Vec3f::new(1.0, 0.0, 0.0),
+            ..Ray::default()
+        };
+        let (black, lights) = (Vec3f::repeat(0.0), Lights::new(&world));
+        let mut sampler = Sampler::new(seed);
+        let color = renderer.color(ray, black, &world, &lights, &mut Rgb, &mut sampler);
+        color.r()
+    }
+
+    /// `1 + albedo + ... + albedo^(bounces - 1)`
+    fn geometric_sum(albedo: f64, bounces: i32) -> f64 {
+        (1.0 - albedo.powi(bounces)) / (1.0 - albedo)
+    }
+
+    /// Glass that doesn't bend or reflect rays straight into it, only absorbs.
+    fn slab(x: std::ops::Range<f64>, absorption: Vec3f<Color>) -> Cube {
+        let material = Arc::new(Dielectric::new(1.0).with_absorption(absorption));
+        let min = Vec3f::new(x.start, -1.0, -1.0);
+        let max = Vec3f::new(x.end, 1.0, 1.0);
+        Cube::new(min, max, material)
+    }
+
+    /// Light of a white background that is left after looking along the x axis through `world`.
+    fn transmittance(world: &List) -> Vec3f<Color> {
+        let mut renderer = Renderer::new(1, 1, 1);
+        renderer.roulette_depth = renderer.max_depth;
+        let ray = Ray {
+            a: Vec3f::<Position>::new(-1.0, 0.0, 0.0),
+            b: Vec3f::new(1.0, 0.0, 0.0),
+            ..Ray::default()
+        };
+        let white = Vec3f::repeat(1.0);
+        let lights = Lights::new(world);
+        let mut sampler = Sampler::new(0);
+        renderer.color(ray, white, world, &lights, &mut Rgb, &mut sampler)
+    }
+
+    fn assert_transmittance(world: &List, expected: [f64; 3]) {
+        let color = transmittance(world);
+        for (channel, expected) in expected.iter().enumerate() {
+            let error = (color[channel] - expected).abs();
+            assert!(error < 1e-9, "{} != {}", color[channel], expected);
+        }
+    }
+
+    #[test]
+    fn slabs_absorb_exponentially_with_thickness() {
+        let absorption = Vec3f::new(0.5, 1.0, 2.0);
+        let mut world = List::new();
+        world.push(slab(0.0..1.5, absorption));
+        let expected = [0.5, 1.0, 2.0].map(|sigma: f64| (-sigma * 1.5).exp());
+        assert_transmittance(&world, expected);
+    }
+
+    #[test]
+    fn leaving_overlapping_objects_removes_their_own_absorption() {
+        let (a, b) = (Vec3f::repeat(0.25), Vec3f::repeat(1.0));
+        let mut world = List::new();
+        world.push(slab(0.0..2.0, a));
+        world.push(slab(1.0..3.0, b));
+        // 1 unit inside only `a`, then 2 inside `b`, which is entered last but left last too
+        let expected = [(-0.25 - 2.0f64).exp(); 3];
+        assert_transmittance(&world, expected);
+    }
+
+    #[test]
+    fn russian_roulette_waits_for_its_depth() {
+        let mut renderer = Renderer::new(1, 1, 1);
+        renderer.roulette_depth = renderer.max_depth;
+        let (albedo, all) = (0.01, renderer.max_depth);
+        let expected = geometric_sum(albedo, all);
+        assert!((bounces(&renderer, albedo, 0) - expected).abs() < 1e-12);
+
+        // Paths that carry so little light are almost always ended by the roulette,
+        // but never before it starts.
+        renderer.roulette_depth = 4;
+        let guaranteed = geometric_sum(albedo, renderer.roulette_depth);
+        for seed in 0..1000 {
+            let light = bounces(&renderer, albedo, seed);
+            assert!(light >= guaranteed - 1e-12, "{} < {}", light, guaranteed);
+        }
+    }
+
+    #[test]
+    fn russian_roulette_is_unbiased() {
+        let mut renderer = Renderer::new(1, 1, 1);
+        renderer.roulette_depth = 2;
+        let albedo = 0.5;
+        let expected = geometric_sum(albedo, renderer.max_depth);
+        let samples = 100_000;
+        let mut survived = 0;
+        let mut sum = 0.0;
+        for seed in 0..samples {
+            let light = bounces(&renderer, albedo, seed);
+            // Paths that survive carry more light, to make up for the ones that are ended
+            survived += (light > expected) as usize;
+            sum += light;
+        }
+        let mean = sum / samples as f64;
+        assert!(survived > 0);
+        let error = (mean - expected).abs() / expected;
+        assert!(error < 1e-2, "{} != {}", mean, expected);
+    }
+}
@@ -0,0 +1,1297 @@
+//! Declarative scene descriptions.
+//!
+//! A scene file is a [RON](https://github.com/ron-rs/ron) document describing the camera,
+//! the background, a set of named materials and the objects of the world.
+//! Objects refer to materials by name, so several objects can share the same `Arc<dyn Material>`.
+//!
+//! ```ron
+//! (
+//!     camera: (lookfrom: (13.0, 2.0, 3.0), lookat: (0.0, 0.0, 0.0), vertical_fov: 20.0),
+//!     background: (0.7, 0.8, 1.0),
+//!     materials: {
+//!         "ground": Lambertian(albedo: Solid((0.5, 0.5, 0.5))),
+//!     },
+//!     objects: [
+//!         Sphere(center: (0.0, -1000.0, 0.0), radius: 1000.0, material: "ground"),
+//!     ],
+//! )
+//! ```
+
+use rand::rngs::SmallRng;
+use rand::SeedableRng;
+use serde::{Deserialize, Serialize};
+
+use std::collections::BTreeMap;
+use std::fmt::{self, Display, Formatter};
+use std::path::{Path, PathBuf};
+use std::sync::Arc;
+
+use crate::math::{
+    AnimatedTransform, Bound, BvhTree, CameraDescriptor, CheckerTexture, Color, Conductor, Cone,
+    ConstantMedium, Csg, Cube, Cylinder, Density, Dielectric, DiffuseLight, Disk,
+    DoubleHenyeyGreenstein, Ggx, HenyeyGreenstein, HeterogeneousMedium, Hittable, ImageTexture,
+    Isotropic, Keyframe, Lambertian, List, Material, Matrix4, Metal, MovingSphere, NoiseDensity,
+    NoiseTexture, Plane, Principled, Quad, QuadShape, Quaternion, RefractionIndex, RoughDielectric,
+    Sdf, SdfBox, SdfCapsule, SdfShape, SdfSphere, SdfTorus, Sphere, Texture, Torus, Transform,
+    Triangle, TriangleMesh, Vec3f, VoxelGrid, Xy, Xz, Yz,
+};
+use crate::obj::{Obj, ObjError};
+
+type Triple = (f64, f64, f64);
+type Uv = (f64, f64);
+
+/// The contents of a scene file.
+#[derive(Serialize, Deserialize)]
+pub struct SceneDescription {
+    pub camera: CameraDescription,
+    pub background: Triple,
+    #[serde(default)]
+    pub image: ImageDescription,
+    #[serde(default)]
+    pub materials: BTreeMap<String, MaterialDescription>,
+    pub objects: Vec<ObjectDescription>,
+}
+
+/// Default render settings of a scene.
+#[derive(Clone, Copy, Serialize, Deserialize)]
+#[serde(default)]
+pub struct ImageDescription {
+    pub width: u32,
+    pub aspect_ratio: f64,
+    pub samples: usize,
+}
+
+impl Default for ImageDescription {
+    fn default() -> Self {
+        Self {
+            width: 400,
+            aspect_ratio: 16.0 / 9.0,
+            samples: 100,
+        }
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub struct CameraDescription {
+    pub lookfrom: Triple,
+    pub lookat: Triple,
+    #[serde(default = "CameraDescription::default_view_up")]
+    pub view_up: Triple,
+    /// Degrees
+    pub vertical_fov: f64,
+    #[serde(default)]
+    pub aperture: f64,
+    #[serde(default = "CameraDescription::default_focus_distance")]
+    pub focus_distance: f64,
+    #[serde(default = "CameraDescription::default_time")]
+    pub time: (f64, f64),
+}
+
+impl CameraDescription {
+    fn default_view_up() -> Triple {
+        (0.0, 1.0, 0.0)
+    }
+    fn default_focus_distance() -> f64 {
+        10.0
+    }
+    fn default_time() -> (f64, f64) {
+        (0.0, 1.0)
+    }
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum TextureDescription {
+    Solid(Triple),
+    Checker {
+        odd: Box<TextureDescription>,
+        even: Box<TextureDescription>,
+    },
+    /// Perlin noise. The seed chooses the random gradients of the noise.
+    Noise {
+        scale: f64,
+        #[serde(default)]
+        seed: u64,
+    },
+    /// The path is relative to the scene file.
+    Image {
+        path: PathBuf,
+    },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum MaterialDescription {
+    Lambertian { albedo: TextureDescription },
+    Metal { albedo: Triple, fuzz: f64 },
+    /// Glass, that absorbs `absorption` of each color per unit of distance inside.
+    /// With a `dispersion`, the refraction index depends on the wavelength, which
+    /// splits light into its colors when rendering spectrally.
+    Dielectric {
+        #[serde(default = "MaterialDescription::default_refraction_index")]
+        refraction_index: f64,
+        #[serde(default)]
+        dispersion: Option<DispersionDescription>,
+        #[serde(default)]
+        absorption: Triple,
+    },
+    DiffuseLight { emit: TextureDescription },
+    Isotropic { albedo: TextureDescription },
+    /// A rough metal. `roughness` goes from 0, a mirror, to 1, and `anisotropy`
+    /// from 0 to 1 stretches the highlights along `tangent`.
+    Conductor {
+        metal: MetalDescription,
+        roughness: f64,
+        #[serde(default)]
+        anisotropy: f64,
+        #[serde(default = "MaterialDescription::default_tangent")]
+        tangent: Triple,
+    },
+    /// Rough glass, like frosted glass.
+    RoughDielectric {
+        refraction_index: f64,
+        roughness: f64,
+        #[serde(default)]
+        anisotropy: f64,
+        #[serde(default = "MaterialDescription::default_tangent")]
+        tangent: Triple,
+        #[serde(default)]
+        absorption: Triple,
+    },
+    /// One material for plastic, metal, glass and anything in between. The parameters go
+    /// from 0 to 1, and each one can be replaced by the mean of the channels of a texture.
+    Principled {
+        base_color: TextureDescription,
+        #[serde(default)]
+        metallic: f64,
+        #[serde(default)]
+        metallic_texture: Option<Box<TextureDescription>>,
+        #[serde(default = "MaterialDescription::default_half")]
+        roughness: f64,
+        #[serde(default)]
+        roughness_texture: Option<Box<TextureDescription>>,
+        #[serde(default = "MaterialDescription::default_half")]
+        specular: f64,
+        #[serde(default)]
+        specular_texture: Option<Box<TextureDescription>>,
+        #[serde(default)]
+        clearcoat: f64,
+        #[serde(default)]
+        clearcoat_texture: Option<Box<TextureDescription>>,
+        #[serde(default)]
+        sheen: f64,
+        #[serde(default)]
+        sheen_texture: Option<Box<TextureDescription>>,
+        #[serde(default)]
+        transmission: f64,
+        #[serde(default)]
+        transmission_texture: Option<Box<TextureDescription>>,
+        #[serde(default)]
+        emission: Option<Box<TextureDescription>>,
+        #[serde(default = "MaterialDescription::default_refraction_index")]
+        refraction_index: f64,
+        #[serde(default)]
+        anisotropy: f64,
+        #[serde(default = "MaterialDescription::default_tangent")]
+        tangent: Triple,
+    },
+}
+
+impl MaterialDescription {
+    fn default_half() -> f64 {
+        0.5
+    }
+    fn default_refraction_index() -> f64 {
+        1.5
+    }
+    fn default_tangent() -> Triple {
+        (0.0, 1.0, 0.0)
+    }
+}
+
+/// The complex refraction index of a metal, for red, green and blue.
+#[derive(Serialize, Deserialize)]
+pub enum MetalDescription {
+    Gold,
+    Copper,
+    Aluminium,
+    Silver,
+    Custom { eta: Triple, k: Triple },
+}
+
+/// The refraction index of a dispersive material, which replaces its `refraction_index`.
+/// Wavelengths are in micrometers.
+#[derive(Serialize, Deserialize)]
+pub enum DispersionDescription {
+    Bk7,
+    FusedSilica,
+    Diamond,
+    Cauchy { a: f64, b: f64 },
+    Sellmeier { b: Triple, c: Triple },
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum PlaneDescription {
+    Xy,
+    Xz,
+    Yz,
+}
+
+#[derive(Serialize, Deserialize, Default)]
+pub enum QuadShapeDescription {
+    #[default]
+    Parallelogram,
+    Triangle,
+    Disk,
+}
+
+#[derive(Serialize, Deserialize)]
+pub enum ObjectDescription {
+    Sphere {
+        center: Triple,
+        radius: f64,
+        material: String,
+    },
+    MovingSphere {
+        center: (Triple, Triple),
+        time: (f64, f64),
+        radius: f64,
+        material: String,
+    },
+    Rectangle {
+        plane: PlaneDescription,
+        p0: (f64, f64),
+        p1: (f64, f64),
+        k: f64,
+        material: String,
+    },
+    Cube {
+        min: Triple,
+        max: Triple,
+        material: String,
+    },
+    /// A cylinder standing on `base`, the center of its bottom.
+    Cylinder {
+        base: Triple,
+        radius: f64,
+        height: f64,
+        #[serde(default = "ObjectDescription::default_capped")]
+        capped: bool,
+        material: String,
+    },
+    /// A cone standing on `base`, the center of its bottom, with the apex above it.
+    Cone {
+        base: Triple,
+        radius: f64,
+        height: f64,
+        #[serde(default = "ObjectDescription::default_capped")]
+        capped: bool,
+        material: String,
+    },
+    /// A horizontal disk, or an annulus with a hole of `inner_radius` below `radius`.
+    Disk {
+        center: Triple,
+        radius: f64,
+        #[serde(default)]
+        inner_radius: f64,
+        material: String,
+    },
+    /// A torus around the vertical axis through `center`.
+    Torus {
+        center: Triple,
+        major_radius: f64,
+        minor_radius: f64,
+        material: String,
+    },
+    /// A flat shape in any orientation: a parallelogram or a triangle with a corner at
+    /// `origin` and edges `u` and `v`, or a disk centered at `origin` with radii `u` and `v`.
+    Quad {
+        origin: Triple,
+        u: Triple,
+        v: Triple,
+        #[serde(default)]
+        shape: QuadShapeDescription,
+        material: String,
+    },
+    Translate {
+        offset: Triple,
+        object: Box<ObjectDescription>,
+    },
+    YRotate {
+        angle: f64,
+        object: Box<ObjectDescription>,
+    },
+    RotateX {
+        angle: f64,
+        object: Box<ObjectDescription>,
+    },
+    RotateZ {
+        angle: f64,
+        object: Box<ObjectDescription>,
+    },
+    /// Counterclockwise rotation around `axis`, in degrees.
+    Rotate {
+        axis: Triple,
+        angle: f64,
+        object: Box<ObjectDescription>,
+    },
+    Scale {
+        factor: Triple,
+        object: Box<ObjectDescription>,
+    },
+    ConstantMedium {
+        boundary: Box<ObjectDescription>,
+        density: f64,
+        albedo: TextureDescription,
+        #[serde(default)]
+        phase: PhaseDescription,
+    },
+    /// A volume inside `boundary` whose density changes from point to point.
+    HeterogeneousMedium {
+        boundary: Box<ObjectDescription>,
+        density: DensityDescription,
+        albedo: TextureDescription,
+        #[serde(default)]
+        phase: PhaseDescription,
+    },
+    Triangle {
+        vertices: (Triple, Triple, Triple),
+        #[serde(default)]
+        normals: Option<(Triple, Triple, Triple)>,
+        #[serde(default)]
+        uvs: Option<(Uv, Uv, Uv)>,
+        material: String,
+    },
+    /// An indexed triangle mesh. The normals and texture coordinates are per position.
+    Mesh {
+        positions: Vec<Triple>,
+        triangles: Vec<(usize, usize, usize)>,
+        #[serde(default)]
+        normals: Option<Vec<Triple>>,
+        #[serde(default)]
+        uvs: Option<Vec<Uv>>,
+        material: String,
+    },
+    /// A Wavefront OBJ file, relative to the scene file. Faces without a material
+    /// in the file use `material`, or a gray diffuse material if it's not given.
+    Obj {
+        path: PathBuf,
+        #[serde(default)]
+        material: Option<String>,
+    },
+    /// An object that moves along keyframes while the shutter is open.
+    Animated {
+        keyframes: Vec<KeyframeDescription>,
+        object: Box<ObjectDescription>,
+    },
+    /// A surface described by a signed distance function. Functions that combine
+    /// others are bounded by them, and `bound` can make the box smaller.
+    Sdf {
+        function: SdfDescription,
+        #[serde(default)]
+        bound: Option<(Triple, Triple)>,
+        material: String,
+    },
+    /// Constructive solid geometry of two closed objects: the space inside either of them.
+    Union {
+        left: Box<ObjectDescription>,
+        right: Box<ObjectDescription>,
+    },
+    /// The space inside both objects.
+    Intersection {
+        left: Box<ObjectDescription>,
+        right: Box<ObjectDescription>,
+    },
+    /// The space inside `left` but not inside `right`.
+    Difference {
+        left: Box<ObjectDescription>,
+        right: Box<ObjectDescription>,
+    },
+    BvhTree(Vec<ObjectDescription>),
+    List(Vec<ObjectDescription>),
+}
+
+impl ObjectDescription {
+    fn default_capped() -> bool {
+        true
+    }
+}
+
+/// How a medium scatters light.
+#[derive(Serialize, Deserialize, Default)]
+pub enum PhaseDescription {
+    /// Equally in every direction.
+    #[default]
+    Isotropic,
+    /// Forward if `g` is positive, backward if negative.
+    HenyeyGreenstein { g: f64 },
+    /// A mix of two Henyey-Greenstein lobes, `weight` being the share of `forward`.
+    DoubleHenyeyGreenstein {
+        forward: f64,
+        backward: f64,
+        weight: f64,
+    },
+}
+
+impl PhaseDescription {
+    fn build(&self, albedo: Arc<dyn Texture>) -> Arc<dyn Material> {
+        match self {
+            PhaseDescription::Isotropic => Arc::new(Isotropic { albedo }),
+            PhaseDescription::HenyeyGreenstein { g } => Arc::new(HenyeyGreenstein::new(albedo, *g)),
+            PhaseDescription::DoubleHenyeyGreenstein {
+                forward,
+                backward,
+                weight,
+            } => Arc::new(DoubleHenyeyGreenstein::new(
+                albedo, *forward, *backward, *weight,
+            )),
+        }
+    }
+}
+
+/// The density of a heterogeneous medium.
+#[derive(Serialize, Deserialize)]
+pub enum DensityDescription {
+    /// Perlin turbulence, at most `density`. The seed chooses the random gradients of the noise.
+    Noise {
+        density: f64,
+        scale: f64,
+        #[serde(default = "DensityDescription::default_depth")]
+        depth: u32,
+        #[serde(default)]
+        seed: u64,
+    },
+    /// Densities at the corners of a grid spanning `min..max`, X changing fastest.
+    Grid {
+        min: Triple,
+        max: Triple,
+        resolution: (usize, usize, usize),
+        values: Vec<f64>,
+    },
+}
+
+impl DensityDescription {
+    fn default_depth() -> u32 {
+        7
+    }
+
+    fn build(&self) -> Result<Arc<dyn Density>, SceneError> {
+        match self {
+            DensityDescription::Noise {
+                density,
+                scale,
+                depth,
+                seed,
+            } => {
+                if *density < 0.0 {
+                    return Err(SceneError::InvalidDensity);
+                }
+                let mut rng = SmallRng::seed_from_u64(*seed);
+                let mut noise = NoiseDensity::new(*density, *scale, &mut rng);
+                noise.depth = *depth;
+                Ok(Arc::new(noise))
+            }
+            DensityDescription::Grid {
+                min,
+                max,
+                resolution: (x, y, z),
+                values,
+            } => {
+                let bound = Bound {
+                    min: (*min).into(),
+                    max: (*max).into(),
+                };
+                let grid = VoxelGrid::new(bound, [*x, *y, *z], values.clone())
+                    .ok_or(SceneError::InvalidDensityGrid)?;
+                Ok(Arc::new(grid))
+            }
+        }
+    }
+}
+
+/// A signed distance function.
+#[derive(Serialize, Deserialize)]
+pub enum SdfDescription {
+    Sphere {
+        center: Triple,
+        radius: f64,
+    },
+    /// A box with its corners rounded by `radius`.
+    Box {
+        center: Triple,
+        size: Triple,
+        #[serde(default)]
+        radius: f64,
+    },
+    Torus {
+        center: Triple,
+        major_radius: f64,
+        minor_radius: f64,
+    },
+    Capsule {
+        a: Triple,
+        b: Triple,
+        radius: f64,
+    },
+    /// Blends the surfaces where they are closer than `k`, which must be positive.
+    SmoothUnion {
+        left: Box<SdfDescription>,
+        right: Box<SdfDescription>,
+        k: f64,
+    },
+    /// Removes `right` from `left`, rounding the edges by a positive `k`.
+    SmoothSubtraction {
+        left: Box<SdfDescription>,
+        right: Box<SdfDescription>,
+        k: f64,
+    },
+    /// Morphs `left` into `right` as `t` goes from 0 to 1.
+    Blend {
+        left: Box<SdfDescription>,
+        right: Box<SdfDescription>,
+        t: f64,
+    },
+}
+
+/// The blends divide by `k`, so they need a positive one.
+fn smoothing(k: f64) -> Result<f64, SceneError> {
+    if k > 0.0 {
+        Ok(k)
+    } else {
+        Err(SceneError::InvalidSmoothing)
+    }
+}
+
+/// Constant media divide the distances between scatterings by their density, so they
+/// need a positive one.
+fn constant_density(density: f64) -> Result<f64, SceneError> {
+    if density > 0.0 {
+        Ok(density)
+    } else {
+        Err(SceneError::InvalidDensity)
+    }
+}
+
+impl SdfDescription {
+    fn build(&self) -> Result<Arc<dyn Sdf>, SceneError> {
+        let function: Arc<dyn Sdf> = match self {
+            SdfDescription::Sphere { center, radius } => Arc::new(SdfSphere {
+                center: (*center).into(),
+                radius: *radius,
+            }),
+            SdfDescription::Box {
+                center,
+                size,
+                radius,
+            } => Arc::new(SdfBox {
+                center: (*center).into(),
+                half_size: Vec3f::from(*size) / 2.0,
+                radius: *radius,
+            }),
+            SdfDescription::Torus {
+                center,
+                major_radius,
+                minor_radius,
+            } => Arc::new(SdfTorus {
+                center: (*center).into(),
+                major_radius: *major_radius,
+                minor_radius: *minor_radius,
+            }),
+            SdfDescription::Capsule { a, b, radius } => Arc::new(SdfCapsule {
+                a: (*a).into(),
+                b: (*b).into(),
+                radius: *radius,
+            }),
+            SdfDescription::SmoothUnion { left, right, k } => {
+                let k = smoothing(*k)?;
+                Arc::new(left.build()?.smooth_union(right.build()?, k))
+            }
+            SdfDescription::SmoothSubtraction { left, right, k } => {
+                let k = smoothing(*k)?;
+                Arc::new(left.build()?.smooth_subtraction(right.build()?, k))
+            }
+            SdfDescription::Blend { left, right, t } => {
+                Arc::new(left.build()?.blend(right.build()?, *t))
+            }
+        };
+        Ok(function)
+    }
+}
+
+/// The pose of an animated object at `time`. The object is scaled,
+/// then rotated `angle` degrees around `axis` and then translated.
+#[derive(Serialize, Deserialize)]
+pub struct KeyframeDescription {
+    pub time: f64,
+    #[serde(default)]
+    pub translation: Triple,
+    #[serde(default = "KeyframeDescription::default_rotation")]
+    pub rotation: (Triple, f64),
+    #[serde(default = "KeyframeDescription::default_scale")]
+    pub scale: Triple,
+}
+
+impl KeyframeDescription {
+    fn default_rotation() -> (Triple, f64) {
+        ((0.0, 1.0, 0.0), 0.0)
+    }
+    fn default_scale() -> Triple {
+        (1.0, 1.0, 1.0)
+    }
+}
+
+/// A scene ready to be rendered.
+/// The aspect ratio of the camera is chosen by the [`Renderer`](crate::Renderer).
+pub struct Scene {
+    pub background: Vec3f<Color>,
+    pub world: List,
+    pub camera: CameraDescriptor,
+}
+
+#[derive(Debug)]
+pub enum SceneError {
+    Io(PathBuf, std::io::Error),
+    Parse(PathBuf, ron::error::SpannedError),
+    UnknownMaterial(String),
+    EmptyBvhTree,
+    /// An object in a BvhTree without a bounding box, like an empty list.
+    UnboundedBvhObject,
+    InvalidMesh(String),
+    /// A transform that flattens the object, like a scale by 0.
+    SingularTransform,
+    EmptyAnimation,
+    UnboundedSdf,
+    /// The number of values of a density grid doesn't match its resolution.
+    InvalidDensityGrid,
+    /// A disk whose hole is negative or not smaller than the disk.
+    InvalidDisk,
+    /// A smooth union or subtraction that doesn't blend by a positive distance.
+    InvalidSmoothing,
+    /// A constant medium whose density isn't positive, or a negative density of any medium.
+    InvalidDensity,
+    Obj(ObjError),
+}
+
+impl Display for SceneError {
+    fn fmt(&self, f: &mut Formatter<'_>) -> fmt::Result {
+        match self {
+            SceneError::Io(path, error) => write!(f, "{}: {}", path.display(), error),
+            SceneError::Parse(path, error) => write!(f, "{}:{}", path.display(), error),
+            SceneError::UnknownMaterial(name) => write!(f, "There is no material {:?}", name),
+            SceneError::EmptyBvhTree => f.write_str("A BvhTree must contain at least one object"),
+            SceneError::UnboundedBvhObject => {
+                f.write_str("The objects of a BvhTree must be bounded")
+            }
+            SceneError::InvalidMesh(reason) => write!(f, "Invalid mesh: {}", reason),
+            SceneError::SingularTransform => f.write_str("A transform can't be inverted"),
+            SceneError::EmptyAnimation => f.write_str("An animation needs at least one keyframe"),
+            SceneError::UnboundedSdf => f.write_str("A distance function needs a bound"),
+            SceneError::InvalidDensityGrid => {
+                f.write_str("A density grid needs one value per point of its resolution")
+            }
+            SceneError::InvalidDisk => {
+                f.write_str("The hole of a disk must be at least 0 and smaller than the disk")
+            }
+            SceneError::InvalidSmoothing => {
+                f.write_str("A smooth union or subtraction needs a positive k")
+            }
+            SceneError::InvalidDensity => {
+                f.write_str("A medium can't have a negative density, nor a constant one of 0")
+            }
+            SceneError::Obj(error) => write!(f, "{}", error),
+        }
+    }
+}
+
+impl std::error::Error for SceneError {}
+
+impl SceneDescription {
+    /// Reads a scene file.
+    pub fn load<P: AsRef<Path>>(path: P) -> Result<Self, SceneError> {
+        let path = path.as_ref();
+        let text =
+            std::fs::read_to_string(path).map_err(|e| SceneError::Io(path.to_owned(), e))?;
+        ron::from_str(&text).map_err(|e| SceneError::Parse(path.to_owned(), e))
+    }
+
+    /// Creates the world and the camera.
+    /// Relative texture paths are resolved against `directory`.
+    pub fn build(&self, directory: &Path) -> Result<Scene, SceneError> {
+        let materials = self
+            .materials
+            .iter()
+            .map(|(name, material)| (name.as_str(), material.build(directory)))
+            .collect();
+        let builder = Builder {
+            materials,
+            directory,
+        };
+        let mut world = List::with_capacity(self.objects.len());
+        for object in &self.objects {
+            world.list.push(builder.object(object)?);
+        }
+
+        let camera = &self.camera;
+        let camera = CameraDescriptor {
+            lookfrom: camera.lookfrom.into(),
+            lookat: camera.lookat.into(),
+            view_up: camera.view_up.into(),
+            vertical_fov: camera.vertical_fov,
+            aspect_ratio: self.image.aspect_ratio,
+            aperture: camera.aperture,
+            focus_distance: camera.focus_distance,
+            open_time: camera.time.0,
+            close_time: camera.time.1,
+        };
+        Ok(Scene {
+            background: self.background.into(),
+            world,
+            camera,
+        })
+    }
+}
+
+impl TextureDescription {
+    fn build(&self, directory: &Path) -> Arc<dyn Texture> {
+        match self {
+            TextureDescription::Solid(color) => Arc::new(Vec3f::<Color>::from(*color)),
+            TextureDescription::Checker { odd, even } => Arc::new(CheckerTexture {
+                odd: odd.build(directory),
+                even: even.build(directory),
+            }),
+            TextureDescription::Noise { scale, seed } => {
+                let mut rng = SmallRng::seed_from_u64(*seed);
+                Arc::new(NoiseTexture::scaled(*scale, &mut rng))
+            }
+            TextureDescription::Image { path } => Arc::new(ImageTexture::new(directory.join(path))),
+        }
+    }
+}
+
+impl MaterialDescription {
+    fn build(&self, directory: &Path) -> Arc<dyn Material> {
+        match self {
+            MaterialDescription::Lambertian { albedo } => {
+                Lambertian::<dyn Texture>::arc(albedo.build(directory))
+            }
+            MaterialDescription::Metal { albedo, fuzz } => Metal::arc((*albedo).into(), *fuzz),
+            MaterialDescription::Dielectric {
+                refraction_index,
+                dispersion,
+                absorption,
+            } => {
+                let refraction_index = match dispersion {
+                    None => RefractionIndex::Constant(*refraction_index),
+                    Some(DispersionDescription::Bk7) => RefractionIndex::BK7,
+                    Some(DispersionDescription::FusedSilica) => RefractionIndex::FUSED_SILICA,
+                    Some(DispersionDescription::Diamond) => RefractionIndex::DIAMOND,
+                    Some(DispersionDescription::Cauchy { a, b }) => {
+                        RefractionIndex::Cauchy { a: *a, b: *b }
+                    }
+                    Some(DispersionDescription::Sellmeier { b, c }) => RefractionIndex::Sellmeier {
+                        b: [b.0, b.1, b.2],
+                        c: [c.0, c.1, c.2],
+                    },
+                };
+                Arc::new(
+                    Dielectric::dispersive(refraction_index).with_absorption((*absorption).into()),
+                )
+            }
+            MaterialDescription::DiffuseLight { emit } => Arc::new(DiffuseLight {
+                emit: emit.build(directory),
+            }),
+            MaterialDescription::Isotropic { albedo } => Arc::new(Isotropic {
+                albedo: albedo.build(directory),
+            }),
+            MaterialDescription::Conductor {
+                metal,
+                roughness,
+                anisotropy,
+                tangent,
+            } => {
+                let (eta, k) = match metal {
+                    MetalDescription::Gold => Conductor::GOLD,
+                    MetalDescription::Copper => Conductor::COPPER,
+                    MetalDescription::Aluminium => Conductor::ALUMINIUM,
+                    MetalDescription::Silver => Conductor::SILVER,
+                    MetalDescription::Custom { eta, k } => ((*eta).into(), (*k).into()),
+                };
+                let distribution = Ggx::from_roughness(*roughness, *anisotropy);
+                Arc::new(Conductor::new(eta, k, distribution).with_tangent((*tangent).into()))
+            }
+            MaterialDescription::RoughDielectric {
+                refraction_index,
+                roughness,
+                anisotropy,
+                tangent,
+                absorption,
+            } => {
+                let distribution = Ggx::from_roughness(*roughness, *anisotropy);
+                Arc::new(
+                    RoughDielectric::new(*refraction_index, distribution)
+                        .with_absorption((*absorption).into())
+                        .with_tangent((*tangent).into()),
+                )
+            }
+            MaterialDescription::Principled {
+                base_color,
+                metallic,
+                metallic_texture,
+                roughness,
+                roughness_texture,
+                specular,
+                specular_texture,
+                clearcoat,
+                clearcoat_texture,
+                sheen,
+                sheen_texture,
+                transmission,
+                transmission_texture,
+                emission,
+                refraction_index,
+                anisotropy,
+                tangent,
+            } => {
+                let parameter =
+                    |value: f64, texture: &Option<Box<TextureDescription>>| match texture {
+                        Some(texture) => texture.build(directory),
+                        None => Arc::new(Vec3f::<Color>::repeat(value)),
+                    };
+                Arc::new(Principled {
+                    base_color: base_color.build(directory),
+                    metallic: parameter(*metallic, metallic_texture),
+                    roughness: parameter(*roughness, roughness_texture),
+                    specular: parameter(*specular, specular_texture),
+                    clearcoat: parameter(*clearcoat, clearcoat_texture),
+                    sheen: parameter(*sheen, sheen_texture),
+                    transmission: parameter(*transmission, transmission_texture),
+                    emission: emission.as_ref().map(|emission| emission.build(directory)),
+                    refraction_index: *refraction_index,
+                    anisotropy: *anisotropy,
+                    tangent: (*tangent).into(),
+                })
+            }
+        }
+    }
+}
+
+/// Turns object descriptions into hittables, sharing the materials by name.
+struct Builder<'a> {
+    materials: BTreeMap<&'a str, Arc<dyn Material>>,
+    directory: &'a Path,
+}
+
+impl Builder<'_> {
+    fn material(&self, name: &str) -> Result<Arc<dyn Material>, SceneError> {
+        self.materials
+            .get(name)
+            .cloned()
+            .ok_or_else(|| SceneError::UnknownMaterial(name.to_owned()))
+    }
+
+    fn list(&self, objects: &[ObjectDescription]) -> Result<List, SceneError> {
+        let mut list = List::with_capacity(objects.len());
+        for object in objects {
+            list.list.push(self.object(object)?);
+        }
+        Ok(list)
+    }
+
+    /// The innermost object of nested transforms, and the transforms folded into one matrix.
+    fn transformed(
+        &self,
+        object: &ObjectDescription,
+    ) -> Result<(Box<dyn Hittable>, Matrix4), SceneError> {
+        let (matrix, inner) = match object {
+            ObjectDescription::Translate { offset, object } => {
+                (Matrix4::translation((*offset).into()), object)
+            }
+            ObjectDescription::YRotate { angle, object } => (Matrix4::rotation_y(*angle), object),
+            ObjectDescription::RotateX { angle, object } => (Matrix4::rotation_x(*angle), object),
+            ObjectDescription::RotateZ { angle, object } => (Matrix4::rotation_z(*angle), object),
+            ObjectDescription::Rotate {
+                axis,
+                angle,
+                object,
+            } => {
+                // There is no direction to turn around
+                if *axis == (0.0, 0.0, 0.0) {
+                    return Err(SceneError::SingularTransform);
+                }
+                (Matrix4::rotation((*axis).into(), *angle), object)
+            }
+            ObjectDescription::Scale { factor, object } => {
+                (Matrix4::scaling((*factor).into()), object)
+            }
+            object => return Ok((self.object(object)?, Matrix4::IDENTITY)),
+        };
+        let (item, inner_matrix) = self.transformed(inner)?;
+        Ok((item, matrix * inner_matrix))
+    }
+
+    fn object(&self, object: &ObjectDescription) -> Result<Box<dyn Hittable>, SceneError> {
+        let object: Box<dyn Hittable> = match object {
+            ObjectDescription::Sphere {
+                center,
+                radius,
+                material,
+            } => Box::new(Sphere {
+                center: (*center).into(),
+                radius: *radius,
+                material: self.material(material)?,
+            }),
+            ObjectDescription::MovingSphere {
+                center,
+                time,
+                radius,
+                material,
+            } => Box::new(MovingSphere {
+                center: center.0.into()..center.1.into(),
+                time: time.0..time.1,
+                radius: *radius,
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Rectangle {
+                plane,
+                p0,
+                p1,
+                k,
+                material,
+            } => {
+                let material = self.material(material)?;
+                let (p0, p1) = (p0.0..p0.1, p1.0..p1.1);
+                match plane {
+                    PlaneDescription::Xy => Box::new(Xy::rectangle(material, p0, p1, *k)),
+                    PlaneDescription::Xz => Box::new(Xz::rectangle(material, p0, p1, *k)),
+                    PlaneDescription::Yz => Box::new(Yz::rectangle(material, p0, p1, *k)),
+                }
+            }
+            ObjectDescription::Cube { min, max, material } => Box::new(Cube::new(
+                (*min).into(),
+                (*max).into(),
+                self.material(material)?,
+            )),
+            ObjectDescription::Cylinder {
+                base,
+                radius,
+                height,
+                capped,
+                material,
+            } => Box::new(Cylinder {
+                base: (*base).into(),
+                radius: *radius,
+                height: *height,
+                capped: *capped,
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Cone {
+                base,
+                radius,
+                height,
+                capped,
+                material,
+            } => Box::new(Cone {
+                base: (*base).into(),
+                radius: *radius,
+                height: *height,
+                capped: *capped,
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Disk {
+                center,
+                radius,
+                inner_radius,
+                material,
+            } => {
+                // Also keeps the texture coordinate across the annulus finite
+                if !(0.0..*radius).contains(inner_radius) {
+                    return Err(SceneError::InvalidDisk);
+                }
+                Box::new(Disk {
+                    center: (*center).into(),
+                    radius: *radius,
+                    inner_radius: *inner_radius,
+                    material: self.material(material)?,
+                })
+            }
+            ObjectDescription::Torus {
+                center,
+                major_radius,
+                minor_radius,
+                material,
+            } => Box::new(Torus {
+                center: (*center).into(),
+                major_radius: *major_radius,
+                minor_radius: *minor_radius,
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Quad {
+                origin,
+                u,
+                v,
+                shape,
+                material,
+            } => {
+                let shape = match shape {
+                    QuadShapeDescription::Parallelogram => QuadShape::Parallelogram,
+                    QuadShapeDescription::Triangle => QuadShape::Triangle,
+                    QuadShapeDescription::Disk => QuadShape::Disk,
+                };
+                Box::new(Quad::with_shape(
+                    shape,
+                    (*origin).into(),
+                    (*u).into(),
+                    (*v).into(),
+                    self.material(material)?,
+                ))
+            }
+            ObjectDescription::Translate { .. }
+            | ObjectDescription::YRotate { .. }
+            | ObjectDescription::RotateX { .. }
+            | ObjectDescription::RotateZ { .. }
+            | ObjectDescription::Rotate { .. }
+            | ObjectDescription::Scale { .. } => {
+                let (item, matrix) = self.transformed(object)?;
+                if matrix.inverse().is_none() {
+                    return Err(SceneError::SingularTransform);
+                }
+                Box::new(Transform::new(item, matrix))
+            }
+            ObjectDescription::ConstantMedium {
+                boundary,
+                density,
+                albedo,
+                phase,
+            } => Box::new(ConstantMedium::with_phase_function(
+                self.object(boundary)?.into(),
+                constant_density(*density)?,
+                phase.build(albedo.build(self.directory)),
+            )),
+            ObjectDescription::HeterogeneousMedium {
+                boundary,
+                density,
+                albedo,
+                phase,
+            } => Box::new(HeterogeneousMedium::with_phase_function(
+                self.object(boundary)?.into(),
+                density.build()?,
+                phase.build(albedo.build(self.directory)),
+            )),
+            ObjectDescription::Triangle {
+                vertices: (v0, v1, v2),
+                normals,
+                uvs,
+                material,
+            } => Box::new(Triangle {
+                vertices: [(*v0).into(), (*v1).into(), (*v2).into()],
+                normals: normals.map(|(n0, n1, n2)| [n0.into(), n1.into(), n2.into()]),
+                uvs: uvs.map(|(uv0, uv1, uv2)| [uv0, uv1, uv2]),
+                material: self.material(material)?,
+            }),
+            ObjectDescription::Mesh {
+                positions,
+                triangles,
+                normals,
+                uvs,
+                material,
+            } => {
+                let invalid = |reason: &str| Err(SceneError::InvalidMesh(reason.to_owned()));
+                if triangles.is_empty() {
+                    return invalid("there are no triangles");
+                }
+                let mut indices = triangles.iter().flat_map(|&(a, b, c)| [a, b, c]);
+                if let Some(index) = indices.find(|&i| i >= positions.len()) {
+                    return invalid(&format!("index {} out of bounds", index));
+                }
+                if normals.as_ref().is_some_and(|x| x.len() != positions.len()) {
+                    return invalid("there must be one normal per position");
+                }
+                if uvs.as_ref().is_some_and(|x| x.len() != positions.len()) {
+                    return invalid("there must be one texture coordinate per position");
+                }
+                let mut mesh = TriangleMesh::new(
+                    positions.iter().map(|&x| x.into()).collect(),
+                    triangles.iter().map(|&(a, b, c)| [a, b, c]).collect(),
+                    self.material(material)?,
+                );
+                if let Some(normals) = normals {
+                    mesh = mesh.with_normals(normals.iter().map(|&x| x.into()).collect());
+                }
+                if let Some(uvs) = uvs {
+                    mesh = mesh.with_uvs(uvs.clone());
+                }
+                Box::new(mesh)
+            }
+            ObjectDescription::Obj { path, material } => {
+                let material = match material {
+                    Some(material) => self.material(material)?,
+                    None => Lambertian::arc(Vec3f::<Color>::repeat(0.73)),
+                };
+                let obj =
+                    Obj::load(self.directory.join(path), material).map_err(SceneError::Obj)?;
+                if obj.meshes.is_empty() {
+                    return Err(SceneError::InvalidMesh(format!(
+                        "{} has no faces",
+                        path.display()
+                    )));
+                }
+                Box::new(obj.into_bvh())
+            }
+            ObjectDescription::Animated { keyframes, object } => {
+                if keyframes.is_empty() {
+                    return Err(SceneError::EmptyAnimation);
+                }
+                let keyframes = keyframes
+                    .iter()
+                    .map(|keyframe| {
+                        let (axis, angle) = keyframe.rotation;
+                        let scale: Vec3f<_> = keyframe.scale.into();
+                        if scale.x() * scale.y() * scale.z() == 0.0 || axis == (0.0, 0.0, 0.0) {
+                            return Err(SceneError::SingularTransform);
+                        }
+                        Ok(Keyframe {
+                            time: keyframe.time,
+                            translation: keyframe.translation.into(),
+                            rotation: Quaternion::from_axis_angle(axis.into(), angle),
+                            scale,
+                        })
+                    })
+                    .collect::<Result<_, _>>()?;
+                Box::new(AnimatedTransform::new(self.object(object)?, keyframes))
+            }
+            ObjectDescription::Sdf {
+                function,
+                bound,
+                material,
+            } => {
+                let function = function.build()?;
+                let bound = match bound {
+                    Some((min, max)) => Bound {
+                        min: (*min).into(),
+                        max: (*max).into(),
+                    },
+                    None => function.bound().ok_or(SceneError::UnboundedSdf)?,
+                };
+                Box::new(SdfShape::with_bound(function, bound, self.material(material)?))
+            }
+            ObjectDescription::Union { left, right } => {
+                Box::new(Csg::union(self.object(left)?, self.object(right)?))
+            }
+            ObjectDescription::Intersection { left, right } => {
+                Box::new(Csg::intersection(self.object(left)?, self.object(right)?))
+            }
+            ObjectDescription::Difference { left, right } => {
+                Box::new(Csg::difference(self.object(left)?, self.object(right)?))
+            }
+            ObjectDescription::BvhTree(objects) => {
+                if objects.is_empty() {
+                    return Err(SceneError::EmptyBvhTree);
+                }
+                let list = self.list(objects)?;
+                for object in &list.list {
+                    if object.bounding_box(0.0, 1.0).is_none() {
+                        return Err(SceneError::UnboundedBvhObject);
+                    }
+                }
+                Box::new(BvhTree::from(list))
+            }
+            ObjectDescription::List(objects) => Box::new(self.list(objects)?),
+        };
+        Ok(object)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    /// Builds a scene of `objects`, which can use the material "white".
+    fn build(objects: &str) -> Result<Scene, SceneError> {
+        let text = format!(
+            r#"(
+                camera: (lookfrom: (0.0, 0.0, 5.0), lookat: (0.0, 0.0, 0.0), vertical_fov: 40.0),
+                background: (0.0, 0.0, 0.0),
+                materials: {{ "white": Lambertian(albedo: Solid((0.5, 0.5, 0.5))) }},
+                objects: [{}],
+            )"#,
+            objects
+        );
+        let description: SceneDescription = ron::from_str(&text).unwrap();
+        description.build(Path::new("."))
+    }
+
+    #[test]
+    fn disk_holes_are_smaller_than_the_disk() {
+        let disk = |inner_radius: f64| {
+            build(&format!(
+                r#"Disk(center: (0.0, 0.0, 0.0), radius: 1.0, inner_radius: {:?}, material: "white")"#,
+                inner_radius
+            ))
+        };
+        assert!(disk(0.0).is_ok());
+        assert!(disk(0.5).is_ok());
+        assert!(matches!(disk(1.0), Err(SceneError::InvalidDisk)));
+        assert!(matches!(disk(-0.5), Err(SceneError::InvalidDisk)));
+    }
+
+    #[test]
+    fn smooth_blends_need_a_positive_k() {
+        let union = |k: f64| {
+            build(&format!(
+                r#"Sdf(
+                    function: SmoothUnion(
+                        left: Sphere(center: (0.0, 0.0, 0.0), radius: 1.0),
+                        right: Sphere(center: (1.0, 0.0, 0.0), radius: 1.0),
+                        k: {:?},
+                    ),
+                    material: "white",
+                )"#,
+                k
+            ))
+        };
+        assert!(union(0.5).is_ok());
+        assert!(matches!(union(0.0), Err(SceneError::InvalidSmoothing)));
+        assert!(matches!(union(-0.5), Err(SceneError::InvalidSmoothing)));
+    }
+
+    #[test]
+    fn constant_media_need_a_positive_density() {
+        let medium = |density: f64| {
+            build(&format!(
+                r#"ConstantMedium(
+                    boundary: Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "white"),
+                    density: {:?},
+                    albedo: Solid((1.0, 1.0, 1.0)),
+                )"#,
+                density
+            ))
+        };
+        assert!(medium(0.5).is_ok());
+        assert!(matches!(medium(0.0), Err(SceneError::InvalidDensity)));
+        assert!(matches!(medium(-0.5), Err(SceneError::InvalidDensity)));
+        assert!(matches!(medium(f64::NAN), Err(SceneError::InvalidDensity)));
+    }
+
+    #[test]
+    fn noise_densities_are_not_negative() {
+        let noise = |density: f64| {
+            build(&format!(
+                r#"HeterogeneousMedium(
+                    boundary: Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "white"),
+                    density: Noise(density: {:?}, scale: 1.0),
+                    albedo: Solid((1.0, 1.0, 1.0)),
+                )"#,
+                density
+            ))
+        };
+        assert!(noise(0.0).is_ok());
+        assert!(noise(2.0).is_ok());
+        assert!(matches!(noise(-2.0), Err(SceneError::InvalidDensity)));
+    }
+
+    #[test]
+    fn bvh_trees_only_take_bounded_objects() {
+        let sphere = r#"Sphere(center: (0.0, 0.0, 0.0), radius: 1.0, material: "white")"#;
+        assert!(build(&format!("BvhTree([{}])", sphere)).is_ok());
+        let unbounded = build(&format!("BvhTree([{}, List([])])", sphere));
+        assert!(matches!(unbounded, Err(SceneError::UnboundedBvhObject)));
+    }
+}
@@ -0,0 +1,35 @@
+use rttnw::scene::SceneDescription;
+use rttnw::{Framebuffer, Renderer};
+
+use std::path::Path;
+
+fn render(scene: &str, threads: usize) -> Framebuffer {
+    let path = Path::new("scenes").join(scene);
+    let scene = SceneDescription::load(&path)
+        .and_then(|description| description.build(Path::new("scenes")))
+        .unwrap();
+    let mut renderer = Renderer::new(32, 32, 4);
+    renderer.seed = 7;
+    rayon::ThreadPoolBuilder::new()
+        .num_threads(threads)
+        .build()
+        .unwrap()
+        .install(|| renderer.render(&scene))
+}
+
+fn bits(framebuffer: &Framebuffer) -> Vec<[u64; 3]> {
+    framebuffer
+        .pixels
+        .iter()
+        .map(|pixel| [pixel.r(), pixel.g(), pixel.b()].map(f64::to_bits))
+        .collect()
+}
+
+#[test]
+fn renders_are_the_same_on_any_number_of_threads() {
+    for scene in ["07_cornell_box.ron", "08_smoke_cornell_box.ron"] {
+        let serial = render(scene, 1);
+        let parallel = render(scene, 4);
+        assert!(bits(&serial) == bits(&parallel), "{} differs", scene);
+    }
+}